@@ -0,0 +1,131 @@
+//! Benchmarks for reading packets and forwarding them from a reader to a
+//! writer. Run with `cargo bench`, each benchmark prints the average time
+//! spent on each packet
+
+use blaze_pk::{codec::Decodable, packet::Packet, reader::TdfReader};
+use bytes::BytesMut;
+use pocket_relay_mitm::{
+    components::{Components, Util},
+    config::FlushStrategy,
+    intercept::{InterceptorRegistry, SessionInfo, SessionInterceptors},
+    mitm::{read_packet, read_packet_buf, Direction, Reader, Writer},
+    models::InstanceDetails,
+};
+use std::{
+    hint::black_box,
+    io::Cursor,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{io, runtime::Runtime};
+use tokio_util::sync::CancellationToken;
+
+/// GetServerInstance response contents decoded by the decode benchmark
+const SERVER_INSTANCE: &[u8] = include_bytes!("../tests/synthetic/server_instance.bin");
+/// The number of packets in each iteration
+const PACKETS: usize = 10_000;
+/// The number of iterations the average is taken over
+const ITERATIONS: u32 = 10;
+
+fn main() {
+    let runtime = Runtime::new().expect("Failed to create runtime");
+    for size in [64, 16 * 1024] {
+        let input = encode_packets(size);
+        bench(&format!("read_packet ({size} B)"), PACKETS, || {
+            runtime.block_on(async {
+                let mut read = input.as_slice();
+                for _ in 0..PACKETS {
+                    black_box(read_packet(&mut read, usize::MAX).await.unwrap());
+                }
+            })
+        });
+        bench(&format!("read_packet_buf ({size} B)"), PACKETS, || {
+            runtime.block_on(async {
+                let mut read = input.as_slice();
+                let mut buffer = BytesMut::new();
+                for _ in 0..PACKETS {
+                    black_box(
+                        read_packet_buf(&mut read, &mut buffer, usize::MAX)
+                            .await
+                            .unwrap(),
+                    );
+                }
+            })
+        });
+        for (name, flush) in [
+            ("immediate", FlushStrategy::Immediate),
+            (
+                "coalesce",
+                FlushStrategy::Coalesce {
+                    max_delay_ms: 2,
+                    max_bytes: 16 * 1024,
+                },
+            ),
+        ] {
+            bench(&format!("forward {name} ({size} B)"), PACKETS, || {
+                runtime.block_on(forward(input.clone(), flush))
+            });
+        }
+    }
+
+    bench("decode InstanceDetails", 1, || {
+        let mut reader = TdfReader::new(SERVER_INSTANCE);
+        black_box(InstanceDetails::decode(&mut reader).unwrap());
+    });
+}
+
+/// Encodes the packets read by each iteration
+///
+/// `size` The length of the contents of each packet
+fn encode_packets(size: usize) -> Vec<u8> {
+    let mut output = Vec::new();
+    for id in 0..PACKETS {
+        let packet = Packet::request_raw(id as u16, Components::Util(Util::Ping), vec![0; size]);
+        output.extend_from_slice(&packet.encode_bytes());
+    }
+    output
+}
+
+/// Forwards the provided packets from a reader to a writer with no
+/// interceptors registered and waits until every packet was read
+///
+/// `input` The encoded packets
+/// `flush` When the written packets are flushed
+async fn forward(input: Vec<u8>, flush: FlushStrategy) {
+    let session = SessionInfo {
+        id: 1,
+        addr: "127.0.0.1:3659".parse().unwrap(),
+        captured: false,
+        sequences: None,
+    };
+    let interceptors = SessionInterceptors::new(Arc::new(InterceptorRegistry::default()), session);
+    let writer = Writer::start(io::sink(), 64, flush, CancellationToken::new());
+    Reader::new(
+        Cursor::new(input),
+        writer,
+        Direction::ClientToServer,
+        interceptors,
+    )
+    .spawn()
+    .await
+    .unwrap()
+    .unwrap();
+}
+
+/// Runs the provided function for each iteration after a warm up run and
+/// prints the average time taken for each packet
+///
+/// `name`    The name of the benchmark
+/// `packets` The number of packets handled by each run
+/// `run`     The function to run
+fn bench(name: &str, packets: usize, mut run: impl FnMut()) {
+    run();
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        run();
+        total += start.elapsed();
+    }
+    let per_packet = total / ITERATIONS / packets as u32;
+    println!("{name:<32} {:>10} ns/packet", per_packet.as_nanos());
+}
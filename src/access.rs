@@ -0,0 +1,20 @@
+//! Module for restricting the client addresses that are served by
+//! the redirector and MITM servers
+
+use crate::config::AccessConfig;
+use log::warn;
+use std::net::IpAddr;
+
+impl AccessConfig {
+    /// Checks whether a connection from the provided address is allowed.
+    /// Denied connections are logged
+    ///
+    /// `addr` The address of the client
+    pub fn allow_connection(&self, addr: IpAddr) -> bool {
+        if self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains_ip(addr)) {
+            return true;
+        }
+        warn!("Denied connection from client outside the allowlist (Addr: {addr})");
+        false
+    }
+}
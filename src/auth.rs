@@ -0,0 +1,181 @@
+//! Module for observing the outcome of the logins that are proxied to the
+//! official server. The responses to login requests are decoded to log
+//! and emit whether each login succeeded. Only the error code in the
+//! header of failed logins is used, their contents aren't decoded
+
+use crate::{
+    components::{Authentication, Components},
+    events::{self, Event, SessionEvent},
+    intercept::{Action, PacketInterceptor, SessionInfo},
+    mitm::Direction,
+    models::LoginResponse,
+};
+use async_trait::async_trait;
+use blaze_pk::packet::{Packet, PacketComponents, PacketType};
+use log::{debug, info, warn};
+use std::time::SystemTime;
+
+/// Interceptor which decodes the responses to login requests
+pub struct LoginObserver;
+
+impl LoginObserver {
+    /// Returns whether the provided component is a login request that
+    /// responds with the details of the logged in session
+    ///
+    /// `component` The component to check
+    pub(crate) fn is_login(component: &Components) -> bool {
+        matches!(
+            component,
+            Components::Authentication(
+                Authentication::Login
+                    | Authentication::SilentLogin
+                    | Authentication::ExpressLogin
+                    | Authentication::LoginPersona
+                    | Authentication::OriginLogin
+                    | Authentication::XboxLogin
+                    | Authentication::PS3Login
+                    | Authentication::DeviceLoginGuest
+            )
+        )
+    }
+}
+
+#[async_trait]
+impl PacketInterceptor for LoginObserver {
+    async fn on_packet(
+        &self,
+        session: &SessionInfo,
+        direction: Direction,
+        packet: &mut Packet,
+    ) -> Action {
+        if !matches!(direction, Direction::ServerToClient) {
+            return Action::Forward;
+        }
+        let component = Components::from_header(&packet.header);
+        if !Self::is_login(&component) {
+            return Action::Forward;
+        }
+
+        let time = SystemTime::now();
+        match packet.header.ty {
+            PacketType::Response => match packet.decode::<LoginResponse>() {
+                Ok(response) => {
+                    info!(
+                        "Login succeeded (Command: {component:?}, Persona: {:?}, Token: {})",
+                        response.persona_id, response.has_token
+                    );
+                    events::emit(Event::Session(SessionEvent::LoginSucceeded {
+                        time,
+                        session: session.id,
+                        client: session.addr,
+                        persona_id: response.persona_id,
+                        has_token: response.has_token,
+                    }));
+                }
+                Err(err) => debug!("Failed to decode login response: {err:?}"),
+            },
+            PacketType::Error => {
+                // The error contents differ between login commands so
+                // they are forwarded without being decoded
+                let error = packet.header.error;
+                warn!("Login failed (Command: {component:?}, Error: {error})");
+                events::emit(Event::Session(SessionEvent::LoginFailed {
+                    time,
+                    session: session.id,
+                    client: session.addr,
+                    error,
+                }));
+            }
+            _ => {}
+        }
+        Action::Forward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SilentLogin response contents containing the logged in session
+    const LOGIN_RESPONSE: &[u8] = include_bytes!("../tests/synthetic/login_response.bin");
+    /// Login error response contents containing a field validation error
+    const LOGIN_FAILED: &[u8] = include_bytes!("../tests/synthetic/login_failed.bin");
+
+    /// Creates the session info for the provided session id
+    ///
+    /// `id` The id of the session
+    fn session_info(id: u64) -> SessionInfo {
+        SessionInfo {
+            id,
+            addr: "127.0.0.1:3659".parse().unwrap(),
+            captured: false,
+            sequences: None,
+        }
+    }
+
+    /// Returns the next login event emitted for the provided session.
+    /// Events from other sessions are skipped as tests run concurrently
+    ///
+    /// `events`  The subscribed events
+    /// `session` The id of the session
+    async fn next_login_event(
+        events: &mut tokio::sync::broadcast::Receiver<Event>,
+        session: u64,
+    ) -> SessionEvent {
+        loop {
+            let Event::Session(event) = events.recv().await.unwrap() else {
+                continue;
+            };
+            match &event {
+                SessionEvent::LoginSucceeded { session: id, .. }
+                | SessionEvent::LoginFailed { session: id, .. }
+                    if *id == session =>
+                {
+                    return event
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The persona and token are decoded from within the SESS group of a
+    /// successful login response
+    #[tokio::test]
+    async fn test_login_succeeded() {
+        let mut events = events::subscribe();
+        let request =
+            Packet::request_empty(1, Components::Authentication(Authentication::SilentLogin));
+        let mut packet = Packet::response_raw(&request, LOGIN_RESPONSE.to_vec());
+        LoginObserver
+            .on_packet(&session_info(80), Direction::ServerToClient, &mut packet)
+            .await;
+
+        assert!(matches!(
+            next_login_event(&mut events, 80).await,
+            SessionEvent::LoginSucceeded {
+                persona_id: Some(978651371),
+                has_token: true,
+                ..
+            }
+        ));
+        assert_eq!(&packet.contents[..], LOGIN_RESPONSE);
+    }
+
+    /// Error responses to a login emit the error code from the header
+    /// and their contents are forwarded unchanged
+    #[tokio::test]
+    async fn test_login_failed() {
+        let mut events = events::subscribe();
+        let request = Packet::request_empty(1, Components::Authentication(Authentication::Login));
+        let mut packet = Packet::error_raw(&request, 0xB, LOGIN_FAILED.to_vec());
+        LoginObserver
+            .on_packet(&session_info(81), Direction::ServerToClient, &mut packet)
+            .await;
+
+        assert!(matches!(
+            next_login_event(&mut events, 81).await,
+            SessionEvent::LoginFailed { error: 0xB, .. }
+        ));
+        assert_eq!(&packet.contents[..], LOGIN_FAILED);
+    }
+}
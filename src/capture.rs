@@ -0,0 +1,286 @@
+//! Module for capturing the packets that are proxied by the MITM server
+//! to a pcap file for later inspection.
+//!
+//! Each pcap record uses the LINKTYPE_USER1 link type and contains a single
+//! direction byte and the big endian u64 sequence number of the packet in
+//! that direction followed by the raw Blaze frame (header and TDF contents)
+//! exactly as it was sent over the wire. Captures from before sequence
+//! numbers were added use LINKTYPE_USER0 and have no sequence number.
+
+use crate::{
+    components::Components,
+    config::CaptureConfig,
+    dump::dump_packet,
+    intercept::{Action, PacketInterceptor, SessionInfo},
+    mitm::Direction,
+};
+use async_trait::async_trait;
+use blaze_pk::packet::Packet;
+use log::{error, info};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Magic number for microsecond resolution pcap files
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+/// Link type for user defined link layers (LINKTYPE_USER1)
+const PCAP_LINK_TYPE: u32 = 148;
+/// Link type of the captures without sequence numbers (LINKTYPE_USER0)
+const PCAP_LEGACY_LINK_TYPE: u32 = 147;
+/// The maximum length of a captured record
+const PCAP_SNAP_LENGTH: u32 = 0x00FF_FFFF;
+/// The length of the pcap global header
+const PCAP_HEADER_LENGTH: u64 = 24;
+
+impl CaptureConfig {
+    /// Returns whether the sessions of the provided client address
+    /// are captured
+    ///
+    /// `addr` The client address
+    pub fn captures(&self, addr: IpAddr) -> bool {
+        self.clients.is_empty() || self.clients.iter().any(|cidr| cidr.contains_ip(addr))
+    }
+}
+
+/// A captured packet waiting to be written
+struct Record {
+    /// The time the packet was captured
+    time: SystemTime,
+    /// The direction of the packet
+    direction: Direction,
+    /// The sequence number of the packet in its direction
+    sequence: u64,
+    /// The captured packet, cloning only shares its contents
+    packet: Packet,
+}
+
+/// Sink for captured packets. Records are sent to a dedicated thread which
+/// writes them to the capture file so that proxying is never blocked on
+/// disk writes
+pub struct Capture {
+    /// Sender for records to the writer thread
+    tx: mpsc::Sender<Record>,
+}
+
+impl Capture {
+    /// Creates a new capture sink from the provided config if capturing
+    /// is enabled and the capture file could be created
+    ///
+    /// `config` The capture configuration
+    pub fn start(config: &CaptureConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let path = PathBuf::from(&config.path);
+        let writer = match CaptureWriter::new(path, config.max_file_size) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Failed to create packet capture file: {err}");
+                return None;
+            }
+        };
+        info!("Capturing packets to {}", &config.path);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || writer.process(rx));
+        Some(Self { tx })
+    }
+
+    /// Captures the provided packet
+    ///
+    /// `session`   The session the packet belongs to
+    /// `direction` The direction the packet was travelling in
+    /// `packet`    The packet to capture
+    pub fn record(&self, session: &SessionInfo, direction: Direction, packet: &Packet) {
+        let record = Record {
+            time: SystemTime::now(),
+            direction,
+            sequence: session.sequence(direction),
+            packet: packet.clone(),
+        };
+        self.tx.send(record).ok();
+    }
+}
+
+#[async_trait]
+impl PacketInterceptor for Capture {
+    async fn on_packet(
+        &self,
+        session: &SessionInfo,
+        direction: Direction,
+        packet: &mut Packet,
+    ) -> Action {
+        if session.captured {
+            self.record(session, direction, packet);
+        }
+        Action::Forward
+    }
+}
+
+/// Prints the annotated structure of each packet in the provided
+/// capture file
+///
+/// `path` The path of the capture file
+pub async fn dump(path: &Path) -> io::Result<()> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut header = [0u8; PCAP_HEADER_LENGTH as usize];
+    file.read_exact(&mut header)?;
+    let link_type = u32::from_le_bytes([header[20], header[21], header[22], header[23]]);
+    if header[0..4] != PCAP_MAGIC.to_le_bytes()
+        || !matches!(link_type, PCAP_LINK_TYPE | PCAP_LEGACY_LINK_TYPE)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a packet capture file",
+        ));
+    }
+
+    let mut record = [0u8; 16];
+    loop {
+        match file.read_exact(&mut record) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+        let seconds = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+        let micros = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        let length = u32::from_le_bytes([record[8], record[9], record[10], record[11]]);
+        let mut bytes = vec![0u8; length as usize];
+        file.read_exact(&mut bytes)?;
+
+        let Some((&direction, mut frame)) = bytes.split_first() else {
+            continue;
+        };
+        let mut sequence = None;
+        if link_type == PCAP_LINK_TYPE {
+            let Some((value, rest)) = frame.split_first_chunk::<8>() else {
+                continue;
+            };
+            sequence = Some(u64::from_be_bytes(*value));
+            frame = rest;
+        }
+        let side = Direction::from_value(direction).map_or("Unknown", |value| value.side());
+        let (component, packet) = Packet::read_async_typed::<Components, _>(&mut frame).await?;
+        match sequence {
+            Some(sequence) => println!(
+                "[{seconds}.{micros:06}] From {side} #{sequence}: {}",
+                dump_packet(&component, &packet)
+            ),
+            None => println!(
+                "[{seconds}.{micros:06}] From {side}: {}",
+                dump_packet(&component, &packet)
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Writer for the capture file which handles rotating the file once
+/// it reaches the size limit
+struct CaptureWriter {
+    /// The path of the capture file
+    path: PathBuf,
+    /// The maximum file size before rotating
+    max_file_size: Option<u64>,
+    /// The current capture file
+    file: BufWriter<File>,
+    /// The number of bytes written to the current file
+    written: u64,
+    /// The number of files that have been rotated
+    rotations: u32,
+}
+
+impl CaptureWriter {
+    /// Creates a new capture writer creating the capture file
+    ///
+    /// `path`          The path of the capture file
+    /// `max_file_size` The maximum file size before rotating
+    fn new(path: PathBuf, max_file_size: Option<u64>) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = Self::create(&path)?;
+        Ok(Self {
+            path,
+            max_file_size,
+            file,
+            written: PCAP_HEADER_LENGTH,
+            rotations: 0,
+        })
+    }
+
+    /// Creates a new capture file at the provided path and writes
+    /// the pcap global header
+    ///
+    /// `path` The path of the file
+    fn create(path: &Path) -> io::Result<BufWriter<File>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        file.write_all(&2u16.to_le_bytes())?;
+        file.write_all(&4u16.to_le_bytes())?;
+        file.write_all(&0i32.to_le_bytes())?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(&PCAP_SNAP_LENGTH.to_le_bytes())?;
+        file.write_all(&PCAP_LINK_TYPE.to_le_bytes())?;
+        Ok(file)
+    }
+
+    /// Writes records until all the senders have been dropped
+    ///
+    /// `rx` The receiver for records
+    fn process(mut self, rx: mpsc::Receiver<Record>) {
+        while let Ok(record) = rx.recv() {
+            if let Err(err) = self.write(record).and_then(|_| self.file.flush()) {
+                error!("Failed to write packet capture: {err}");
+                return;
+            }
+        }
+    }
+
+    /// Writes the provided record to the capture file rotating
+    /// the file first if it would exceed the size limit
+    ///
+    /// `record` The record to write
+    fn write(&mut self, record: Record) -> io::Result<()> {
+        let contents = &record.packet.contents;
+        let header = record.packet.header.encode_bytes(contents.len());
+        let length = (header.len() + contents.len()) as u32 + 9;
+        let size = 16 + length as u64;
+        if let Some(max_file_size) = self.max_file_size {
+            if self.written > PCAP_HEADER_LENGTH && self.written + size > max_file_size {
+                self.rotate()?;
+            }
+        }
+
+        let time = record.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let file = &mut self.file;
+        file.write_all(&(time.as_secs() as u32).to_le_bytes())?;
+        file.write_all(&time.subsec_micros().to_le_bytes())?;
+        file.write_all(&length.to_le_bytes())?;
+        file.write_all(&length.to_le_bytes())?;
+        file.write_all(&[record.direction.value()])?;
+        file.write_all(&record.sequence.to_be_bytes())?;
+        file.write_all(&header)?;
+        file.write_all(contents)?;
+        self.written += size;
+        Ok(())
+    }
+
+    /// Moves the current capture file to a numbered file and starts
+    /// a new capture file in its place
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.rotations += 1;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{}", self.rotations));
+        fs::rename(&self.path, &rotated)?;
+        self.file = Self::create(&self.path)?;
+        self.written = PCAP_HEADER_LENGTH;
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
 //! Modules contains the component definitions for the servers used throughout
 //! this application.
 
+// Component command names are taken directly from the Blaze protocol
+#![allow(clippy::enum_variant_names)]
+
 use blaze_pk::define_components;
 use std::hash::Hash;
 
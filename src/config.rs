@@ -0,0 +1,1188 @@
+//! Module for loading the server configuration from the optional
+//! config file in the working directory
+
+use crate::{
+    mitm::Direction,
+    models::{
+        Environment, FirstPartyId, IdentityProfile, InstanceAddress, InstanceDetails, InstanceNet,
+        InstanceNetError, InstanceRequest, InstanceTags, Locale, NetCidr, Port,
+    },
+    EXTERNAL_HOST, MAIN_PORT, REDIRECTOR_PORT,
+};
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs, io,
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// The path to the configuration file
+const CONFIG_PATH: &str = "config.json";
+
+/// Configuration for the server. Any values that are missing from
+/// the config file use their default values
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The client identity presented to the official servers
+    pub identity: IdentityConfig,
+    /// The listener for the redirector server
+    pub redirector: RedirectorConfig,
+    /// The listener for the MITM server
+    pub mitm: MitmConfig,
+    /// The target that the redirector sends clients to
+    pub redirect: RedirectConfig,
+    /// Connection behavior for the official server
+    pub retriever: RetrieverConfig,
+    /// Capturing of the proxied packets
+    pub capture: CaptureConfig,
+    /// Recording of the client sessions for replaying
+    pub record: RecordConfig,
+    /// Latency injection for simulating poor network conditions
+    pub latency: LatencyConfig,
+    /// Packet decoding behavior
+    pub decode: DecodeConfig,
+    /// Packet encoding behavior
+    pub encode: EncodeConfig,
+    /// Graceful shutdown behavior
+    pub shutdown: ShutdownConfig,
+    /// Filtering of the proxied packets
+    pub filter: FilterConfig,
+    /// Rate limiting of clients
+    pub rate_limit: RateLimitConfig,
+    /// Restriction of the client addresses that are served
+    pub access: AccessConfig,
+    /// Limit on the number of concurrent proxy sessions
+    pub sessions: SessionsConfig,
+    /// Limits on the packets buffered for each proxy direction
+    pub buffers: BufferConfig,
+    /// When the packets written to each connection are flushed
+    pub flush: FlushStrategy,
+    /// Options for the client and official server sockets
+    pub sockets: SocketConfig,
+    /// Rewriting of the QoS ping sites sent to clients
+    pub qos: QosConfig,
+    /// Deadline for the login flow of each connection
+    pub login: LoginConfig,
+    /// Output format of the logs
+    pub logging: LoggingConfig,
+    /// The HTTP endpoint for health and readiness checks
+    pub health: HealthConfig,
+}
+
+impl Config {
+    /// Checks the values that can't be checked while deserializing
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for target in self.redirect.targets() {
+            target.instance().map_err(ConfigError::Target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Configuration for the HTTP endpoint answering liveness checks on
+/// `/healthz` and readiness checks on `/readyz`. The endpoint is only
+/// available when built with the `health` feature
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Whether the endpoint is started
+    pub enabled: bool,
+    /// The local address the endpoint listens on
+    pub bind: SocketAddr,
+    /// The number of seconds between checks of the official server
+    pub probe_interval_secs: u64,
+}
+
+impl HealthConfig {
+    /// Returns the interval between checks of the official server
+    pub fn probe_interval(&self) -> Duration {
+        Duration::from_secs(self.probe_interval_secs)
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            probe_interval_secs: 30,
+        }
+    }
+}
+
+/// Configuration for the format of the stdout and file logs
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// The format each log line is written in
+    pub format: LogFormat,
+    /// The URL of the OTLP/HTTP traces endpoint the spans are exported
+    /// to. Only used when built with the `otel` feature
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Format that log lines are written in
+#[derive(Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable text
+    #[default]
+    Text,
+    /// A JSON object on each line for log ingestion
+    Json,
+}
+
+/// Configuration for the deadline on the steps between accepting a
+/// connection and it being redirected or proxied
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct LoginConfig {
+    /// The number of seconds the whole login flow may take
+    pub timeout_secs: u64,
+}
+
+impl LoginConfig {
+    /// Returns the timeout duration for the login flow
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        Self { timeout_secs: 30 }
+    }
+}
+
+/// Configuration for replacing the QoS ping sites that clients are
+/// told to measure their latency against
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct QosConfig {
+    /// Replacement for the bandwidth ping site
+    pub bandwidth: Option<QosTarget>,
+    /// Replacements for the latency ping sites keyed by site identifier
+    pub sites: HashMap<String, QosTarget>,
+    /// Replacements for the latencies in milliseconds that clients
+    /// report for each ping site keyed by site identifier
+    pub latencies: HashMap<String, u32>,
+}
+
+/// Replacement host and port for a QoS ping site
+#[derive(Clone, Deserialize)]
+pub struct QosTarget {
+    /// The host address or hostname of the ping site
+    pub host: String,
+    /// The port of the ping site
+    pub port: Port,
+}
+
+/// Configuration for the number of packets that can be buffered for
+/// each direction before reading from the other side is paused
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct BufferConfig {
+    /// The maximum packets waiting to be written to the official server
+    pub client_to_server: usize,
+    /// The maximum packets waiting to be written to the client
+    pub server_to_client: usize,
+}
+
+impl BufferConfig {
+    /// Returns the buffer capacity for the provided direction
+    ///
+    /// `direction` The direction the packets are travelling in
+    pub fn capacity(&self, direction: Direction) -> usize {
+        let capacity = match direction {
+            Direction::ClientToServer => self.client_to_server,
+            Direction::ServerToClient => self.server_to_client,
+        };
+        capacity.max(1)
+    }
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            client_to_server: 64,
+            server_to_client: 64,
+        }
+    }
+}
+
+/// Strategy for when the packets written to the client and official
+/// server connections are flushed
+#[derive(Default, Clone, Copy, Deserialize)]
+#[serde(tag = "strategy", rename_all = "lowercase")]
+pub enum FlushStrategy {
+    /// Flush after each packet so none of them are delayed
+    #[default]
+    Immediate,
+    /// Batch the packets written together into a single flush which
+    /// happens once the oldest unflushed packet has waited for the
+    /// delay or enough bytes are unflushed
+    Coalesce {
+        /// The maximum milliseconds a packet waits to be flushed
+        max_delay_ms: u64,
+        /// The number of unflushed bytes that causes a flush
+        max_bytes: usize,
+    },
+}
+
+impl FlushStrategy {
+    /// Returns the capacity of the write buffer placed in front of each
+    /// connection. Coalesced packets are held in the buffer until they
+    /// are flushed so that each flush is a single socket write, when
+    /// flushing immediately the packets are written straight through
+    pub fn buffer_capacity(&self) -> usize {
+        match self {
+            Self::Immediate => 0,
+            Self::Coalesce { max_bytes, .. } => *max_bytes,
+        }
+    }
+}
+
+/// Configuration for the options set on the proxied client sockets and
+/// the official server sockets once they are connected
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct SocketConfig {
+    /// Whether Nagle's algorithm is disabled (TCP_NODELAY)
+    pub nodelay: bool,
+    /// The size of the socket send buffer, the OS default when not set
+    pub send_buffer_size: Option<usize>,
+    /// The size of the socket receive buffer, the OS default when not set
+    pub recv_buffer_size: Option<usize>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+/// Configuration for limiting the number of concurrent proxy sessions
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SessionsConfig {
+    /// The maximum number of concurrent sessions, zero for no limit
+    pub max_concurrent: usize,
+    /// Behavior for new connections when the limit is reached
+    pub when_full: WhenFull,
+    /// The number of milliseconds to wait for a session to finish
+    /// when waiting for a free session
+    pub wait_ms: u64,
+    /// The number of seconds without packets in either direction
+    /// before a session is closed, sessions never time out when None
+    pub idle_timeout_secs: Option<u64>,
+}
+
+impl SessionsConfig {
+    /// Returns the duration to wait for a free session
+    pub fn wait(&self) -> Duration {
+        Duration::from_millis(self.wait_ms)
+    }
+
+    /// Returns the idle timeout for sessions if there is one
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 512,
+            when_full: WhenFull::default(),
+            wait_ms: 2000,
+            idle_timeout_secs: None,
+        }
+    }
+}
+
+/// Behavior for new connections when the session limit is reached
+#[derive(Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhenFull {
+    /// Wait for a session to finish before rejecting the connection
+    #[default]
+    Wait,
+    /// Reject the connection immediately
+    Reject,
+}
+
+/// Configuration for rate limiting the connections and packets
+/// of each client address
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Whether rate limiting is enabled
+    pub enabled: bool,
+    /// The number of new connections allowed per second
+    pub connections_per_sec: u32,
+    /// The number of new connections allowed at once
+    pub connection_burst: u32,
+    /// The number of packets allowed per second
+    pub packets_per_sec: u32,
+    /// The number of packets allowed at once
+    pub packet_burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            connections_per_sec: 5,
+            connection_burst: 10,
+            packets_per_sec: 100,
+            packet_burst: 200,
+        }
+    }
+}
+
+/// Configuration for restricting the client addresses that are served
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// The client addresses or subnets that are allowed to connect. When
+    /// empty every client is allowed
+    #[serde(deserialize_with = "deserialize_cidrs")]
+    pub allow: Vec<NetCidr>,
+}
+
+/// Configuration for filtering the proxied packets by their component
+/// and command. The default empty config forwards every packet
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    /// Rules for the packets that are forwarded. When empty all the
+    /// packets that don't match a deny rule are forwarded
+    pub allow: Vec<PacketRule>,
+    /// Rules for the packets that are not forwarded
+    pub deny: Vec<PacketRule>,
+    /// Rules for the packets that are captured. When not empty only
+    /// the matching packets are captured
+    pub log: Vec<PacketRule>,
+}
+
+/// Rule matching packets by their component and optionally command
+#[derive(Clone, Deserialize)]
+pub struct PacketRule {
+    /// The component ID to match
+    pub component: u16,
+    /// The command ID to match. All the commands of the component are
+    /// matched when not provided
+    #[serde(default)]
+    pub command: Option<u16>,
+}
+
+/// Configuration for gracefully shutting down the server
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// The number of seconds to wait for active sessions to finish
+    /// before they are forcefully closed
+    pub grace_period_secs: u64,
+}
+
+impl ShutdownConfig {
+    /// Returns the grace period duration
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs(self.grace_period_secs)
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace_period_secs: 30,
+        }
+    }
+}
+
+/// Configuration for how packets are decoded
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct DecodeConfig {
+    /// Behavior when instance details contain an unsupported network
+    /// address type
+    pub unknown_address: DecodeMode,
+    /// Whether each proxied packet is decoded and a warning is logged
+    /// for packets that fail to decode. Enabled by default in debug builds
+    pub validate: bool,
+    /// The maximum length of packet contents in bytes, connections
+    /// sending longer packets are closed
+    pub max_packet_size: usize,
+    /// Whether the telemetry and user settings packets sent by clients
+    /// are decoded and emitted as events
+    pub telemetry: bool,
+}
+
+impl Default for DecodeConfig {
+    fn default() -> Self {
+        Self {
+            unknown_address: DecodeMode::default(),
+            validate: cfg!(debug_assertions),
+            max_packet_size: 4 * 1024 * 1024,
+            telemetry: false,
+        }
+    }
+}
+
+impl DecodeConfig {
+    /// Returns whether unsupported network address types are skipped
+    /// when decoding instance details
+    pub fn lenient_address(&self) -> bool {
+        self.unknown_address == DecodeMode::Lenient
+    }
+}
+
+/// Configuration for how packets are encoded
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EncodeConfig {
+    /// Whether the SECU tag is included in redirect responses
+    pub secu: bool,
+    /// Whether the XDNS tag is included in redirect responses
+    pub xdns: bool,
+}
+
+impl Default for EncodeConfig {
+    fn default() -> Self {
+        Self {
+            secu: true,
+            xdns: true,
+        }
+    }
+}
+
+impl EncodeConfig {
+    /// Returns the optional tags included when encoding instance details
+    pub fn tags(&self) -> InstanceTags {
+        InstanceTags {
+            secu: self.secu,
+            xdns: self.xdns,
+        }
+    }
+}
+
+/// Behavior when decoding encounters unsupported values
+#[derive(Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodeMode {
+    /// Fail the decode with an error
+    #[default]
+    Strict,
+    /// Log and skip the unsupported value
+    Lenient,
+}
+
+/// Configuration for injecting latency into the proxied packets
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct LatencyConfig {
+    /// Whether latency injection is enabled on startup
+    pub enabled: bool,
+    /// The delay for packets from the client to the official server
+    pub client_to_server: DelayConfig,
+    /// The delay for packets from the official server to the client
+    pub server_to_client: DelayConfig,
+}
+
+/// Configuration for the delay applied to packets in one direction
+#[derive(Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct DelayConfig {
+    /// The fixed number of milliseconds to delay each packet by
+    pub delay_ms: u64,
+    /// The maximum number of milliseconds to randomly add to or
+    /// remove from the delay
+    pub jitter_ms: u64,
+}
+
+/// Configuration for recording client sessions so that they can be replayed
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RecordConfig {
+    /// Whether session recording is enabled
+    pub enabled: bool,
+    /// The directory to store the session recordings in
+    pub directory: String,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "recordings".to_string(),
+        }
+    }
+}
+
+/// Configuration for capturing the proxied packets to a pcap file
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// Whether packet capturing is enabled
+    pub enabled: bool,
+    /// The path of the capture file
+    pub path: String,
+    /// The maximum size in bytes of the capture file before it is rotated
+    pub max_file_size: Option<u64>,
+    /// The client addresses or subnets to capture the sessions of. When
+    /// empty the sessions of every client are captured
+    #[serde(deserialize_with = "deserialize_cidrs")]
+    pub clients: Vec<NetCidr>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "captures/capture.pcap".to_string(),
+            max_file_size: None,
+            clients: Vec::new(),
+        }
+    }
+}
+
+/// Deserializes a list of subnets from CIDR notation
+fn deserialize_cidrs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<NetCidr>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Deserializes either a single redirect target or a list of targets,
+/// null is treated as an empty list
+fn deserialize_targets<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<RedirectTarget>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Targets {
+        One(RedirectTarget),
+        Many(Vec<RedirectTarget>),
+    }
+
+    Ok(match Option::<Targets>::deserialize(deserializer)? {
+        Some(Targets::One(target)) => vec![target],
+        Some(Targets::Many(targets)) => targets,
+        None => Vec::new(),
+    })
+}
+
+/// Configuration for the retriever connections to the official server
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RetrieverConfig {
+    /// The number of seconds to wait when connecting to the official servers
+    pub connect_timeout_secs: u64,
+    /// The protocol used to connect to the redirector server
+    pub redirector_protocol: Protocol,
+    /// The protocol used to connect to the main server
+    pub server_protocol: Protocol,
+    /// Reconnection behavior when the official server connection is lost
+    pub reconnect: ReconnectConfig,
+    /// Optional SRV record name used to discover the main server, the
+    /// official redirector is used when no record is found
+    pub srv: Option<String>,
+    /// Heartbeats sent over idle official server connections
+    pub heartbeat: HeartbeatConfig,
+    /// The maximum number of chained redirectors that are followed
+    pub max_redirects: usize,
+    /// The local address that connections to the official servers are
+    /// bound to, chosen by the OS when not set
+    pub source_address: Option<SocketAddr>,
+    /// Whether the instance request sent to the redirector is logged
+    pub log_instance_request: bool,
+    /// The number of times the instance request is sent again when the
+    /// response fails to decode before the connection is given up on
+    pub instance_retries: u32,
+    /// Optional SOCKS5 proxy that connections to the official servers
+    /// are tunneled through
+    pub proxy: Option<ProxyConfig>,
+    /// Optional address of the redirector that instances are requested
+    /// from in place of the official redirector
+    pub redirector: Option<SocketAddr>,
+    /// Routes sending the packets of components to upstreams other than
+    /// the official server
+    pub component_routes: Vec<ComponentRoute>,
+}
+
+/// Route sending the client packets of some components to a different
+/// upstream than the official server
+#[derive(Clone, Deserialize)]
+pub struct ComponentRoute {
+    /// The IDs of the components that are routed
+    pub components: Vec<u16>,
+    /// The host address or hostname of the upstream
+    pub host: String,
+    /// The port of the upstream
+    pub port: Port,
+}
+
+/// Configuration for a SOCKS5 proxy and its optional authentication
+#[derive(Clone, Deserialize)]
+pub struct ProxyConfig {
+    /// The address of the proxy
+    pub address: SocketAddr,
+    /// The username when the proxy requires authentication
+    pub username: Option<String>,
+    /// The password when the proxy requires authentication
+    pub password: Option<String>,
+}
+
+impl RetrieverConfig {
+    /// Returns the timeout duration for connecting
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+}
+
+impl Default for RetrieverConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            redirector_protocol: Protocol::default(),
+            server_protocol: Protocol::default(),
+            reconnect: ReconnectConfig::default(),
+            srv: None,
+            heartbeat: HeartbeatConfig::default(),
+            max_redirects: 3,
+            source_address: None,
+            log_instance_request: false,
+            instance_retries: 2,
+            proxy: None,
+            redirector: None,
+            component_routes: Vec::new(),
+        }
+    }
+}
+
+/// Secure protocol used when connecting to an upstream server
+#[derive(Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// SSLv3 as used by the official servers and game client. Only the
+    /// TLS_RSA_WITH_RC4_128_SHA and TLS_RSA_WITH_RC4_128_MD5 cipher
+    /// suites are supported
+    #[default]
+    Ssl3,
+    /// TLS 1.2 or newer using the rustls safe default cipher suites
+    Tls,
+}
+
+/// Configuration for reconnecting to the official server using an
+/// exponential backoff between each attempt
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// The number of milliseconds to wait before the first attempt. This
+    /// is doubled for each following attempt
+    pub base_delay_ms: u64,
+    /// The maximum number of attempts before giving up
+    pub max_retries: u32,
+    /// The number of seconds a connection must stay up for before the
+    /// attempts are reset
+    pub reset_after_secs: u64,
+    /// The fraction (0 to 1) of each delay that is random so sessions
+    /// that lost their connection together don't retry together
+    pub jitter: f64,
+    /// The maximum number of sessions attempting to reconnect at the
+    /// same time, zero for no limit
+    pub max_concurrent: usize,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            base_delay_ms: 500,
+            max_retries: 5,
+            reset_after_secs: 60,
+            jitter: 0.5,
+            max_concurrent: 8,
+        }
+    }
+}
+
+/// Configuration for the heartbeat pings sent over official server
+/// connections that have been idle to keep them from being dropped
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// Whether heartbeats are sent
+    pub enabled: bool,
+    /// The number of idle seconds before a heartbeat is sent
+    pub interval_secs: u64,
+    /// The number of unanswered heartbeats before the connection
+    /// is considered lost and re-established
+    pub max_missed: u32,
+}
+
+impl HeartbeatConfig {
+    /// Returns the idle duration before a heartbeat is sent
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 30,
+            max_missed: 3,
+        }
+    }
+}
+
+/// Configuration for the redirector server listener
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RedirectorConfig {
+    /// The local addresses to accept redirector connections on
+    pub bind: Vec<SocketAddr>,
+    /// The path of the certificate presented to clients, the embedded
+    /// certificate is used when not set
+    pub certificate: Option<String>,
+    /// The path of the private key for the certificate
+    pub private_key: Option<String>,
+    /// Whether clients are only redirected without the MITM server
+    /// being started to proxy them
+    pub dry_run: bool,
+}
+
+impl Default for RedirectorConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec![SocketAddr::from((Ipv4Addr::UNSPECIFIED, REDIRECTOR_PORT))],
+            certificate: None,
+            private_key: None,
+            dry_run: false,
+        }
+    }
+}
+
+/// Configuration for the listener of the MITM server
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct MitmConfig {
+    /// The local address to accept proxy connections on
+    pub bind: SocketAddr,
+}
+
+impl Default for MitmConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from((Ipv4Addr::UNSPECIFIED, MAIN_PORT)),
+        }
+    }
+}
+
+/// Configuration for the instance that the redirector tells clients
+/// to connect to along with the health checking of the upstream server
+#[derive(Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct RedirectConfig {
+    /// The primary target that clients are redirected to when none
+    /// of the routes match
+    #[serde(flatten)]
+    pub target: RedirectTarget,
+    /// Routes sending matching clients to other targets, the first
+    /// matching route is used
+    pub routes: Vec<RedirectRoute>,
+    /// Targets that clients are redirected to when the upstream health
+    /// check fails, the first target that is reachable is used. A single
+    /// target under the previous `fallback` key is also accepted
+    #[serde(alias = "fallback", deserialize_with = "deserialize_targets")]
+    pub fallbacks: Vec<RedirectTarget>,
+    /// Health checking of the upstream server before redirecting
+    pub health_check: HealthCheckConfig,
+    /// Whether remote clients are refused instead of being redirected
+    /// to a loopback or unspecified address they cannot reach
+    pub refuse_loopback: bool,
+    /// The address used in place of an unspecified (0.0.0.0) host
+    pub unspecified: UnspecifiedConfig,
+    /// Rejection of clients that don't match any identity profile
+    pub unknown_clients: UnknownClientsConfig,
+}
+
+/// Configuration for rejecting the redirect requests of clients whose
+/// name (CLNT) doesn't match any of the identity profiles
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct UnknownClientsConfig {
+    /// Whether unknown clients are rejected instead of being redirected
+    pub reject: bool,
+    /// The message sent to rejected clients
+    pub message: String,
+}
+
+impl Default for UnknownClientsConfig {
+    fn default() -> Self {
+        Self {
+            reject: false,
+            message: "This client is not supported by this server".to_string(),
+        }
+    }
+}
+
+impl RedirectConfig {
+    /// Returns the primary target followed by the targets of the routes
+    /// and the fallback targets
+    pub fn targets(&self) -> impl Iterator<Item = &RedirectTarget> {
+        std::iter::once(&self.target)
+            .chain(self.routes.iter().map(|route| &route.target))
+            .chain(&self.fallbacks)
+    }
+}
+
+/// Configuration for the address that replaces an unspecified (0.0.0.0)
+/// redirect host before it is sent to clients
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct UnspecifiedConfig {
+    /// Where the replacement address comes from
+    pub source: AddressSource,
+    /// The URL responding with the public address as plain text
+    pub lookup_url: String,
+}
+
+impl Default for UnspecifiedConfig {
+    fn default() -> Self {
+        Self {
+            source: AddressSource::default(),
+            lookup_url: "https://api.ipify.org".to_string(),
+        }
+    }
+}
+
+/// Source of the address that replaces an unspecified redirect host
+#[derive(Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressSource {
+    /// The unspecified address is sent to clients as is
+    None,
+    /// The address of the network interface used for the default route
+    #[default]
+    Lan,
+    /// The public address reported by the lookup URL
+    Public,
+}
+
+/// Route sending the clients matching all of its conditions to a
+/// specific target. A route without conditions matches every client
+#[derive(Clone, Deserialize)]
+pub struct RedirectRoute {
+    /// The subnet of the client address (e.g. 10.0.0.0/8)
+    #[serde(default, deserialize_with = "deserialize_cidr")]
+    pub subnet: Option<NetCidr>,
+    /// The client SKU (CSKU) from the redirect request
+    #[serde(default)]
+    pub sku: Option<String>,
+    /// The client locale (LOC) from the redirect request
+    #[serde(default, deserialize_with = "deserialize_locale")]
+    pub locale: Option<Locale>,
+    /// The target for matching clients
+    pub target: RedirectTarget,
+}
+
+/// Deserializes an optional subnet from CIDR notation
+fn deserialize_cidr<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<NetCidr>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Target instance for the redirector. Defaults to this server
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct RedirectTarget {
+    /// The host address or hostname of the target
+    pub host: String,
+    /// The port of the target
+    pub port: Port,
+    /// Whether the target requires a secure connection
+    pub secure: bool,
+}
+
+impl RedirectTarget {
+    /// Creates the instance details for this redirect target returning
+    /// an error if the target port is zero
+    pub fn instance(&self) -> Result<InstanceDetails, InstanceNetError> {
+        let net = InstanceNet::try_new(self.host.as_str(), self.port)?;
+        Ok(InstanceDetails::new(
+            InstanceAddress::Server(net),
+            self.secure,
+        ))
+    }
+}
+
+impl Default for RedirectTarget {
+    fn default() -> Self {
+        Self {
+            host: EXTERNAL_HOST.to_string(),
+            port: MAIN_PORT,
+            secure: false,
+        }
+    }
+}
+
+/// Configuration for the pre-flight connection check against the
+/// official server that is done before redirecting clients
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// Whether the health check is enabled
+    pub enabled: bool,
+    /// The number of seconds to wait for the connection
+    pub timeout_secs: u64,
+    /// The number of seconds the result of a check is reused for
+    pub cache_secs: u64,
+}
+
+impl HealthCheckConfig {
+    /// Returns the timeout duration for the health check
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    /// Returns the duration the result of a check is reused for
+    pub fn cache(&self) -> Duration {
+        Duration::from_secs(self.cache_secs)
+    }
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout_secs: 5,
+            cache_secs: 10,
+        }
+    }
+}
+
+/// Configuration for the client identity that is sent to the official
+/// redirector. Values that aren't provided use the values from the
+/// selected profile
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct IdentityConfig {
+    /// The identity profile the values are based on
+    #[serde(deserialize_with = "deserialize_profile")]
+    pub profile: &'static IdentityProfile,
+    /// The name of the client (CLNT)
+    pub client: Option<String>,
+    /// The client platform (PLAT)
+    pub platform: Option<String>,
+    /// The client SKU (CSKU)
+    pub sku: Option<String>,
+    /// The client version (CVER)
+    pub version: Option<String>,
+    /// The client locale code (LOC) such as enUS
+    #[serde(deserialize_with = "deserialize_locale")]
+    pub locale: Option<Locale>,
+    /// The server environment (ENV) such as prod, test or dev
+    #[serde(deserialize_with = "deserialize_environment")]
+    pub environment: Option<Environment>,
+    /// The first party ID (FPID) such as `{ "psn": 1234 }`, the
+    /// FPID is sent unset when None
+    #[serde(deserialize_with = "deserialize_first_party_id")]
+    pub first_party_id: Option<FirstPartyId>,
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        Self {
+            profile: &IdentityProfile::ME3_PC,
+            client: None,
+            platform: None,
+            sku: None,
+            version: None,
+            locale: None,
+            environment: None,
+            first_party_id: None,
+        }
+    }
+}
+
+/// Deserializes an identity profile from its registered name
+fn deserialize_profile<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<&'static IdentityProfile, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    IdentityProfile::find(&name)
+        .ok_or_else(|| serde::de::Error::custom(format!("Unknown identity profile: {name}")))
+}
+
+/// Deserializes an optional locale from its four letter code
+fn deserialize_locale<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Locale>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Deserializes an optional server environment from its name
+fn deserialize_environment<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Environment>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.map(Environment::from))
+}
+
+/// Deserializes an optional first party ID from an object keyed by
+/// the lowercase name of its platform
+fn deserialize_first_party_id<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<FirstPartyId>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Id {
+        Xbox(u64),
+        Psn(u64),
+        Origin(String),
+    }
+
+    Ok(Option::<Id>::deserialize(deserializer)?.map(|id| match id {
+        Id::Xbox(value) => FirstPartyId::Xbox(value),
+        Id::Psn(value) => FirstPartyId::Psn(value),
+        Id::Origin(value) => FirstPartyId::Origin(value),
+    }))
+}
+
+impl IdentityConfig {
+    /// Creates the instance request for this identity
+    pub fn request(&self) -> InstanceRequest {
+        let mut request = self.profile.request();
+        if let Some(client) = &self.client {
+            request = request.with_client(client);
+        }
+        if let Some(platform) = &self.platform {
+            request = request.with_platform(platform);
+        }
+        if let Some(sku) = &self.sku {
+            request = request.with_sku(sku);
+        }
+        if let Some(version) = &self.version {
+            request = request.with_version(version);
+        }
+        if let Some(locale) = self.locale {
+            request = request.with_locale(locale);
+        }
+        if let Some(environment) = &self.environment {
+            request = request.with_environment(environment.clone());
+        }
+        if let Some(first_party_id) = &self.first_party_id {
+            request = request.with_first_party_id(first_party_id.clone());
+        }
+        request
+    }
+}
+
+/// Redirect configuration that can be replaced while the server is
+/// running. Readers take the current value so that replacing it only
+/// affects redirects that start afterwards
+pub struct SharedRedirect(RwLock<Arc<RedirectConfig>>);
+
+impl SharedRedirect {
+    /// Creates a new shared redirect configuration
+    ///
+    /// `config` The initial redirect configuration
+    pub fn new(config: RedirectConfig) -> Self {
+        Self(RwLock::new(Arc::new(config)))
+    }
+
+    /// Returns the current redirect configuration
+    pub fn current(&self) -> Arc<RedirectConfig> {
+        match self.0.read() {
+            Ok(value) => value.clone(),
+            Err(err) => err.into_inner().clone(),
+        }
+    }
+
+    /// Loads the config file again and replaces the redirect configuration
+    /// with the one it contains, returning the new configuration
+    pub fn reload(&self) -> Result<Arc<RedirectConfig>, ConfigError> {
+        let config = Arc::new(load()?.redirect);
+        match self.0.write() {
+            Ok(mut value) => *value = config.clone(),
+            Err(err) => *err.into_inner() = config.clone(),
+        }
+        Ok(config)
+    }
+}
+
+/// Error type for errors that can occur while loading the config
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Failed to read the config file
+    IO(io::Error),
+    /// The config file contents were invalid
+    Parse(serde_json::Error),
+    /// A redirect target in the config file was invalid
+    Target(InstanceNetError),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IO(err) => write!(f, "Failed to read config file: {err}"),
+            Self::Parse(err) => write!(f, "Invalid config file: {err}"),
+            Self::Target(err) => write!(f, "Invalid redirect target: {err}"),
+        }
+    }
+}
+
+/// Loads the configuration from the config file. If the config
+/// file doesn't exist then the default configuration is used
+pub fn load() -> Result<Config, ConfigError> {
+    let path = Path::new(CONFIG_PATH);
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let contents = fs::read_to_string(path).map_err(ConfigError::IO)?;
+    let config: Config = serde_json::from_str(&contents).map_err(ConfigError::Parse)?;
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First party IDs are read from an object keyed by their platform
+    #[test]
+    fn test_first_party_id() {
+        let config: Config =
+            serde_json::from_str(r#"{ "identity": { "first_party_id": { "psn": 1234 } } }"#)
+                .unwrap();
+        assert_eq!(
+            config.identity.first_party_id,
+            Some(FirstPartyId::Psn(1234))
+        );
+        let config: Config =
+            serde_json::from_str(r#"{ "identity": { "first_party_id": { "origin": "Name" } } }"#)
+                .unwrap();
+        assert_eq!(
+            config.identity.first_party_id,
+            Some(FirstPartyId::Origin("Name".to_string()))
+        );
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.identity.first_party_id, None);
+    }
+}
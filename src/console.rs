@@ -0,0 +1,99 @@
+//! Module for handling commands entered into the server console
+//! while the server is running
+
+use crate::{
+    config::{Config, SharedRedirect},
+    intercept::{InterceptorHandle, InterceptorRegistry},
+    latency::LatencyInjector,
+    metrics::METRICS,
+    redirector,
+    retriever::Retriever,
+    validate::DecodeValidator,
+};
+use log::{error, info, warn};
+use std::sync::Arc;
+use tokio::io::{stdin, AsyncBufReadExt, BufReader};
+
+/// Reads commands from the console until the input is closed
+///
+/// `config`       The server configuration
+/// `latency`      The latency injector controlled by the latency command
+/// `retriever`    The retriever used by the servers command
+/// `redirect`     The redirect configuration reloaded by the reload command
+/// `interceptors` The interceptors the validate command registers with
+/// `validator`    The handle of the decode validator when it is registered
+pub async fn listen(
+    config: Arc<Config>,
+    latency: Arc<LatencyInjector>,
+    retriever: Arc<Retriever>,
+    redirect: Arc<SharedRedirect>,
+    interceptors: Arc<InterceptorRegistry>,
+    mut validator: Option<InterceptorHandle>,
+) {
+    let mut lines = BufReader::new(stdin()).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(value)) => value,
+            Ok(None) => break,
+            Err(err) => {
+                error!("Failed to read console input: {err}");
+                break;
+            }
+        };
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (None, _) => {}
+            (Some("latency"), None) => latency.toggle(),
+            (Some("latency"), Some("on")) => latency.set_enabled(true),
+            (Some("latency"), Some("off")) => latency.set_enabled(false),
+            (Some("metrics"), None) => log_metrics(),
+            (Some("servers"), None) => log_servers(&retriever).await,
+            (Some("reload"), None) => redirector::reload(&redirect).await,
+            (Some("validate"), Some("on")) => {
+                if validator.is_none() {
+                    validator = Some(interceptors.register(DecodeValidator::new(&config.decode)));
+                }
+                info!("Decode validation enabled");
+            }
+            (Some("validate"), Some("off")) => {
+                if let Some(handle) = validator.take() {
+                    interceptors.unregister(handle);
+                }
+                info!("Decode validation disabled");
+            }
+            _ => warn!("Unknown command: {line}"),
+        }
+    }
+}
+
+/// Logs a snapshot of the current metrics as JSON
+fn log_metrics() {
+    match serde_json::to_string(&METRICS.snapshot()) {
+        Ok(value) => info!("Metrics: {value}"),
+        Err(err) => error!("Failed to serialize metrics: {err}"),
+    }
+}
+
+/// Requests and logs the server instances from the official redirector
+///
+/// `retriever` The retriever to request the instances with
+async fn log_servers(retriever: &Retriever) {
+    let list = match retriever.server_list().await {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to retrieve server instances: {err}");
+            return;
+        }
+    };
+    info!(
+        "Official redirector provided {} instance(s)",
+        list.instances.len()
+    );
+    for instance in &list.instances {
+        let net = instance.net();
+        info!(
+            "Instance (Host: {}, Port: {}, Secure: {})",
+            net.host, net.port, instance.secure
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Module for resolving instance hosts into socket addresses. Resolved
+//! hostnames are cached for a short time to avoid repeatedly querying
+//! the resolver when reconnecting
+
+use crate::{
+    config::{AddressSource, UnspecifiedConfig},
+    models::{InstanceHost, InstanceNet, Port},
+};
+use hickory_resolver::{proto::rr::rdata::SRV, TokioAsyncResolver};
+use log::{debug, error};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::net::{lookup_host, UdpSocket};
+
+/// The duration that resolved hostnames are cached for
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Errors that can occur while resolving a hostname
+#[derive(Debug)]
+pub enum ResolveError {
+    /// The lookup itself failed
+    Lookup(io::Error),
+    /// The lookup succeeded but returned no addresses
+    NoAddresses,
+}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lookup(err) => write!(f, "Lookup failed: {err}"),
+            Self::NoAddresses => f.write_str("No addresses found"),
+        }
+    }
+}
+
+/// Returns the cache of resolved hostnames creating it if it has
+/// not already been created
+fn cache() -> &'static Mutex<HashMap<String, (IpAddr, Instant)>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, (IpAddr, Instant)>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Resolves the provided host into a socket address. Addresses are used
+/// directly and hostnames are resolved using the system resolver
+///
+/// `host` The host to resolve
+/// `port` The port for the socket address
+pub async fn resolve(host: &InstanceHost, port: Port) -> Result<SocketAddr, ResolveError> {
+    let ip = match host {
+        InstanceHost::Address(value) => IpAddr::V4(value.0),
+        InstanceHost::Address6(value) => IpAddr::V6(value.0),
+        InstanceHost::Host(value) => resolve_host(value).await?,
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Resolves the provided hostname into an IP address preferring IPv4
+/// addresses. Cached addresses are used if they haven't expired
+///
+/// `host` The hostname to resolve
+async fn resolve_host(host: &str) -> Result<IpAddr, ResolveError> {
+    if let Some(ip) = cached(host) {
+        return Ok(ip);
+    }
+
+    let addrs: Vec<SocketAddr> = lookup_host((host, 0))
+        .await
+        .map_err(ResolveError::Lookup)?
+        .collect();
+    let ip = addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or_else(|| addrs.first())
+        .map(SocketAddr::ip)
+        .ok_or(ResolveError::NoAddresses)?;
+
+    let mut cache = match cache().lock() {
+        Ok(value) => value,
+        Err(err) => err.into_inner(),
+    };
+    cache.insert(host.to_string(), (ip, Instant::now() + CACHE_TTL));
+    Ok(ip)
+}
+
+/// Returns the cached address for the provided hostname if
+/// there is one and it hasn't expired
+///
+/// `host` The hostname to find
+fn cached(host: &str) -> Option<IpAddr> {
+    let mut cache = match cache().lock() {
+        Ok(value) => value,
+        Err(err) => err.into_inner(),
+    };
+    let (ip, expires) = *cache.get(host)?;
+    if expires <= Instant::now() {
+        cache.remove(host);
+        return None;
+    }
+    Some(ip)
+}
+
+/// Determines the address that replaces an unspecified redirect host
+/// using the configured source. None is returned if the source is
+/// disabled or the address couldn't be determined
+///
+/// `config` The replacement address configuration
+pub async fn local_address(config: &UnspecifiedConfig) -> Option<Ipv4Addr> {
+    match config.source {
+        AddressSource::None => None,
+        AddressSource::Lan => lan_address().await,
+        AddressSource::Public => public_address(&config.lookup_url).await,
+    }
+}
+
+/// Determines the LAN address of this machine from the interface that
+/// would be used to reach a public address. Connecting a UDP socket
+/// doesn't send any packets
+async fn lan_address() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.ok()?;
+    socket.connect((Ipv4Addr::new(8, 8, 8, 8), 80)).await.ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+        _ => None,
+    }
+}
+
+/// Looks up the public address of this machine using the provided URL
+/// which responds with the address as plain text. Successful lookups
+/// are cached just like resolved hostnames
+///
+/// `url` The lookup URL
+async fn public_address(url: &str) -> Option<Ipv4Addr> {
+    if let Some(IpAddr::V4(ip)) = cached(url) {
+        return Some(ip);
+    }
+
+    let response = match reqwest::get(url).await {
+        Ok(value) => value.text().await,
+        Err(err) => Err(err),
+    };
+    let ip: Ipv4Addr = match response {
+        Ok(value) => match value.trim().parse() {
+            Ok(value) => value,
+            Err(_) => {
+                error!("Public address lookup returned an invalid address: {value}");
+                return None;
+            }
+        },
+        Err(err) => {
+            error!("Failed to lookup public address: {err}");
+            return None;
+        }
+    };
+
+    let mut cache = match cache().lock() {
+        Ok(value) => value,
+        Err(err) => err.into_inner(),
+    };
+    cache.insert(
+        url.to_string(),
+        (IpAddr::V4(ip), Instant::now() + CACHE_TTL),
+    );
+    Some(ip)
+}
+
+/// Looks up the SRV records for the provided service name and picks a
+/// target from the records with the lowest priority, weighted by their
+/// weight. None is returned if there are no records
+///
+/// `name` The SRV record name (e.g. _blaze._tcp.example.com)
+pub async fn lookup_srv(name: &str) -> Option<InstanceNet> {
+    let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+        Ok(value) => value,
+        Err(err) => {
+            debug!("Unable to create SRV resolver: {err}");
+            return None;
+        }
+    };
+    let records: Vec<SRV> = match resolver.srv_lookup(name).await {
+        Ok(value) => value.into_iter().collect(),
+        Err(err) => {
+            debug!("No SRV records found (Name: {name}): {err}");
+            return None;
+        }
+    };
+    let record = select_srv(&records)?;
+    let host = record.target().to_utf8();
+    Some(InstanceNet {
+        host: InstanceHost::from(host.trim_end_matches('.').to_string()),
+        port: record.port(),
+    })
+}
+
+/// Selects a record from the provided records. Only the records with
+/// the lowest priority are considered and one is picked at random with
+/// a chance proportional to its weight
+///
+/// `records` The records to select from
+fn select_srv(records: &[SRV]) -> Option<&SRV> {
+    let priority = records.iter().map(SRV::priority).min()?;
+    let candidates: Vec<&SRV> = records
+        .iter()
+        .filter(|record| record.priority() == priority)
+        .collect();
+
+    let mut rng = rand::thread_rng();
+    let total: u32 = candidates.iter().map(|record| record.weight() as u32).sum();
+    if total == 0 {
+        return Some(candidates[rng.gen_range(0..candidates.len())]);
+    }
+
+    let mut pick = rng.gen_range(0..total);
+    candidates.into_iter().find(|record| {
+        let weight = record.weight() as u32;
+        if pick < weight {
+            return true;
+        }
+        pick -= weight;
+        false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Differently spelled hostnames resolve through a single cache entry
+    /// for their normalized form
+    #[tokio::test]
+    async fn test_resolve_normalized_host() {
+        let host: InstanceHost = "LocalHost.".parse().unwrap();
+        let addr = resolve(&host, 42127).await.unwrap();
+        assert!(addr.ip().is_loopback());
+        assert_eq!(addr.port(), 42127);
+
+        let cache = match cache().lock() {
+            Ok(value) => value,
+            Err(err) => err.into_inner(),
+        };
+        assert!(cache.contains_key("localhost"));
+        assert!(!cache.contains_key("LocalHost."));
+    }
+}
@@ -0,0 +1,238 @@
+//! Module for formatting packets as an annotated view of their TDF
+//! structure for reverse engineering. Each tag is shown with its offset,
+//! name, type and value and anything that can't be interpreted is shown
+//! as a hex dump instead
+
+use crate::components::Components;
+use blaze_pk::{
+    error::DecodeResult,
+    packet::Packet,
+    reader::TdfReader,
+    tag::{Tag, TdfType},
+    types::UNION_UNSET,
+};
+use std::fmt::Write;
+
+/// The number of bytes shown on each line of a hex dump
+const HEX_LINE_LENGTH: usize = 16;
+/// Blobs up to this length are shown inline rather than as a hex dump
+const INLINE_BLOB_LENGTH: usize = 16;
+
+/// Formats the provided packet as a header line followed by the
+/// annotated structure of its contents
+///
+/// `component` The component of the packet
+/// `packet`    The packet to format
+pub fn dump_packet(component: &Components, packet: &Packet) -> String {
+    let header = &packet.header;
+    let mut out = format!(
+        "{:?} {:?} (ID: {}, Error: {}, Length: {})\n",
+        component,
+        header.ty,
+        header.id,
+        header.error,
+        packet.contents.len()
+    );
+    dump_contents(&packet.contents, &mut out);
+    out
+}
+
+/// Appends the annotated structure of the provided packet contents.
+/// Once a tag fails to decode the remaining bytes from the start of
+/// that tag are appended as a hex dump
+///
+/// `contents` The packet contents
+/// `out`      The output to append to
+pub fn dump_contents(contents: &[u8], out: &mut String) {
+    let mut reader = TdfReader::new(contents);
+    while reader.cursor < reader.buffer.len() {
+        let start = reader.cursor;
+        let mut tag = String::new();
+        match dump_tag(&mut reader, &mut tag, 1) {
+            Ok(()) => out.push_str(&tag),
+            Err(err) => {
+                let _ = writeln!(
+                    out,
+                    "  Unable to decode at {start:04x} ({err:?}), remaining:"
+                );
+                hex_dump(&contents[start..], start, 2, out);
+                break;
+            }
+        }
+    }
+}
+
+/// Appends the next tag and its value
+///
+/// `reader` The reader for the contents
+/// `out`    The output to append to
+/// `indent` The current indent level
+fn dump_tag(reader: &mut TdfReader, out: &mut String, indent: usize) -> DecodeResult<()> {
+    let offset = reader.cursor;
+    let Tag(name, ty) = reader.read_tag()?;
+    let _ = write!(out, "{offset:04x} {}{name:<4} {ty:?} ", "  ".repeat(indent));
+    dump_value(reader, out, indent, &ty)?;
+    out.push('\n');
+    Ok(())
+}
+
+/// Appends the next value of the provided type
+///
+/// `reader` The reader for the contents
+/// `out`    The output to append to
+/// `indent` The current indent level
+/// `ty`     The type of the value
+fn dump_value(
+    reader: &mut TdfReader,
+    out: &mut String,
+    indent: usize,
+    ty: &TdfType,
+) -> DecodeResult<()> {
+    match ty {
+        TdfType::VarInt => {
+            let value = reader.read_u64()?;
+            let _ = write!(out, "{value} (0x{value:x})");
+        }
+        TdfType::String => {
+            let value = reader.read_string()?;
+            let _ = write!(out, "{value:?}");
+        }
+        TdfType::Blob => {
+            let length = reader.read_blob()?.len();
+            let offset = reader.cursor - length;
+            let value = &reader.buffer[offset..reader.cursor];
+            if value.len() <= INLINE_BLOB_LENGTH {
+                let _ = write!(out, "[{}]", hex_bytes(value));
+            } else {
+                let _ = writeln!(out, "({} bytes)", value.len());
+                hex_dump(value, offset, indent + 3, out);
+                // The hex dump ends with a new line which the tag adds
+                out.pop();
+            }
+        }
+        TdfType::Group => {
+            if reader.buffer.get(reader.cursor) == Some(&2) {
+                reader.cursor += 1;
+                out.push_str("(2) ");
+            }
+            out.push_str("{\n");
+            while reader.cursor < reader.buffer.len() {
+                if reader.buffer[reader.cursor] == 0 {
+                    reader.cursor += 1;
+                    break;
+                }
+                dump_tag(reader, out, indent + 1)?;
+            }
+            close(out, indent, '}');
+        }
+        TdfType::List => {
+            let value_type = reader.read_type()?;
+            let length = reader.read_usize()?;
+            let _ = writeln!(out, "<{value_type:?}> ({length} items) [");
+            for index in 0..length {
+                let _ = write!(out, "     {}[{index}] ", "  ".repeat(indent + 1));
+                dump_value(reader, out, indent + 1, &value_type)?;
+                out.push('\n');
+            }
+            close(out, indent, ']');
+        }
+        TdfType::Map => {
+            let key_type = reader.read_type()?;
+            let value_type = reader.read_type()?;
+            let length = reader.read_usize()?;
+            let _ = writeln!(out, "<{key_type:?}, {value_type:?}> ({length} entries) {{");
+            for _ in 0..length {
+                let _ = write!(out, "     {}", "  ".repeat(indent + 1));
+                dump_value(reader, out, indent + 1, &key_type)?;
+                out.push_str(" => ");
+                dump_value(reader, out, indent + 1, &value_type)?;
+                out.push('\n');
+            }
+            close(out, indent, '}');
+        }
+        TdfType::Union => {
+            let key = reader.read_byte()?;
+            if key == UNION_UNSET {
+                out.push_str("(Unset)");
+            } else {
+                let _ = writeln!(out, "({key}) {{");
+                dump_tag(reader, out, indent + 1)?;
+                close(out, indent, '}');
+            }
+        }
+        TdfType::VarIntList => {
+            let length = reader.read_usize()?;
+            let mut values = Vec::with_capacity(length);
+            for _ in 0..length {
+                values.push(reader.read_u64()?.to_string());
+            }
+            let _ = write!(out, "[{}]", values.join(", "));
+        }
+        TdfType::Pair => {
+            let a = reader.read_u64()?;
+            let b = reader.read_u64()?;
+            let _ = write!(out, "({a}, {b})");
+        }
+        TdfType::Triple => {
+            let a = reader.read_u64()?;
+            let b = reader.read_u64()?;
+            let c = reader.read_u64()?;
+            let _ = write!(out, "({a}, {b}, {c})");
+        }
+        TdfType::Float => {
+            let value = reader.read_f32()?;
+            let _ = write!(out, "{value}");
+        }
+    }
+    Ok(())
+}
+
+/// Appends the closing character for a nested value aligned with
+/// the tag that opened it
+///
+/// `out`     The output to append to
+/// `indent`  The indent level of the opening tag
+/// `closing` The closing character
+fn close(out: &mut String, indent: usize, closing: char) {
+    let _ = write!(out, "     {}{closing}", "  ".repeat(indent));
+}
+
+/// Appends a hex dump of the provided bytes with the offset of each
+/// line and the printable ASCII characters
+///
+/// `bytes`  The bytes to dump
+/// `offset` The offset of the first byte within the contents
+/// `indent` The indent level for each line
+/// `out`    The output to append to
+pub fn hex_dump(bytes: &[u8], offset: usize, indent: usize, out: &mut String) {
+    for (index, line) in bytes.chunks(HEX_LINE_LENGTH).enumerate() {
+        let ascii: String = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "{}{:04x}  {:<47}  |{ascii}|",
+            "  ".repeat(indent),
+            offset + index * HEX_LINE_LENGTH,
+            hex_bytes(line)
+        );
+    }
+}
+
+/// Formats the provided bytes as space separated hex pairs
+///
+/// `bytes` The bytes to format
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,383 @@
+//! Module for the events channel which allows observing what the server is
+//! doing without parsing the logs. Events are sent over a broadcast channel
+//! so subscribers that fall behind miss events rather than slowing the proxy
+
+use crate::models::{InstanceDetails, InstanceNet, Port, TelemetryMessage};
+use log::warn;
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::debug;
+
+/// The number of events that are buffered for each subscriber before
+/// the oldest events are dropped
+const EVENTS_CAPACITY: usize = 256;
+
+/// Events emitted by the server
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The retriever decoded the details of an official server instance
+    InstanceDecoded {
+        /// The host of the instance
+        host: String,
+        /// The port of the instance
+        port: Port,
+        /// Whether the instance requires a secure connection
+        secure: bool,
+    },
+    /// A client session moved to a new stage of its lifecycle
+    Session(SessionEvent),
+    /// A telemetry or user settings packet from a client was decoded
+    Telemetry {
+        /// The unique identifier of the proxy session
+        session: u64,
+        /// The address of the client
+        client: SocketAddr,
+        /// The decoded message
+        message: TelemetryMessage,
+    },
+}
+
+/// Lifecycle transitions of a client from being accepted by the
+/// redirector until its proxy session is closed
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// The redirector accepted a client connection
+    Accepted {
+        /// The time the transition happened
+        time: SystemTime,
+        /// The address of the client
+        client: SocketAddr,
+    },
+    /// The redirector sent the client to an instance
+    Redirected {
+        /// The time the transition happened
+        time: SystemTime,
+        /// The address of the client
+        client: SocketAddr,
+        /// The instance the client was sent to
+        instance: InstanceNet,
+    },
+    /// The proxy connection to the official server was established,
+    /// this is emitted again after each reconnect
+    ProxyStarted {
+        /// The time the transition happened
+        time: SystemTime,
+        /// The unique identifier of the proxy session
+        session: u64,
+        /// The address of the client
+        client: SocketAddr,
+        /// The official server the session is connected to
+        upstream: InstanceNet,
+    },
+    /// The official server accepted a login from the client
+    LoginSucceeded {
+        /// The time the transition happened
+        time: SystemTime,
+        /// The unique identifier of the proxy session
+        session: u64,
+        /// The address of the client
+        client: SocketAddr,
+        /// The identifier of the logged in user or persona
+        persona_id: Option<u32>,
+        /// Whether a session token was provided
+        has_token: bool,
+    },
+    /// The official server rejected a login from the client
+    LoginFailed {
+        /// The time the transition happened
+        time: SystemTime,
+        /// The unique identifier of the proxy session
+        session: u64,
+        /// The address of the client
+        client: SocketAddr,
+        /// The error code from the response
+        error: u16,
+    },
+    /// The proxy session was closed
+    Closed {
+        /// The time the transition happened
+        time: SystemTime,
+        /// The unique identifier of the proxy session
+        session: u64,
+        /// The address of the client
+        client: SocketAddr,
+        /// Why the session was closed
+        reason: CloseReason,
+        /// The number of packets read from the client and the official
+        /// server when packet sequence numbers are tracked
+        packets: Option<(u64, u64)>,
+    },
+}
+
+impl SessionEvent {
+    /// Returns the time that the transition happened
+    pub fn time(&self) -> SystemTime {
+        match self {
+            Self::Accepted { time, .. }
+            | Self::Redirected { time, .. }
+            | Self::ProxyStarted { time, .. }
+            | Self::LoginSucceeded { time, .. }
+            | Self::LoginFailed { time, .. }
+            | Self::Closed { time, .. } => *time,
+        }
+    }
+}
+
+impl Display for SessionEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = self
+            .time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        match self {
+            Self::Accepted { client, .. } => {
+                write!(f, "Redirector accepted (Addr: {client}, Time: {time})")
+            }
+            Self::Redirected {
+                client, instance, ..
+            } => write!(
+                f,
+                "Redirected (Addr: {client}, Host: {}, Port: {}, Time: {time})",
+                instance.host, instance.port
+            ),
+            Self::ProxyStarted {
+                session,
+                client,
+                upstream,
+                ..
+            } => write!(
+                f,
+                "Proxy started (Session: {session}, Addr: {client}, Host: {}, Port: {}, Time: {time})",
+                upstream.host, upstream.port
+            ),
+            Self::LoginSucceeded {
+                session,
+                client,
+                persona_id,
+                has_token,
+                ..
+            } => write!(
+                f,
+                "Login succeeded (Session: {session}, Addr: {client}, Persona: {persona_id:?}, Token: {has_token}, Time: {time})"
+            ),
+            Self::LoginFailed {
+                session,
+                client,
+                error,
+                ..
+            } => write!(
+                f,
+                "Login failed (Session: {session}, Addr: {client}, Error: {error}, Time: {time})"
+            ),
+            Self::Closed {
+                session,
+                client,
+                reason,
+                packets,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Session closed (Session: {session}, Addr: {client}, Reason: {reason:?}"
+                )?;
+                if let Some((client_packets, server_packets)) = packets {
+                    write!(
+                        f,
+                        ", Client Packets: {client_packets}, Server Packets: {server_packets}"
+                    )?;
+                }
+                write!(f, ", Time: {time})")
+            }
+        }
+    }
+}
+
+/// The reason that a proxy session was closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The client closed its connection
+    ClientClosed,
+    /// The official server closed the connection and it couldn't
+    /// be re-established
+    UpstreamClosed,
+    /// The session failed with an error
+    Error,
+    /// The server is shutting down
+    Shutdown,
+    /// No packets were received from either side for the idle timeout
+    IdleTimeout,
+}
+
+impl From<&InstanceDetails> for Event {
+    fn from(value: &InstanceDetails) -> Self {
+        Self::InstanceDecoded {
+            host: value.host_string(),
+            port: value.net().port,
+            secure: value.secure,
+        }
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InstanceDecoded { host, port, secure } => write!(
+                f,
+                "Instance decoded (Host: {host}, Port: {port}, Secure: {secure})"
+            ),
+            Self::Session(event) => event.fmt(f),
+            Self::Telemetry {
+                session,
+                client,
+                message,
+            } => write!(
+                f,
+                "Telemetry decoded (Session: {session}, Addr: {client}): {message:?}"
+            ),
+        }
+    }
+}
+
+/// Returns the sender for the events channel creating it if
+/// it has not already been created
+fn sender() -> &'static broadcast::Sender<Event> {
+    static EVENTS: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+    EVENTS.get_or_init(|| broadcast::channel(EVENTS_CAPACITY).0)
+}
+
+/// Emits the provided event to all the subscribers
+///
+/// `event` The event to emit
+pub fn emit(event: Event) {
+    // Sending only fails when there are no subscribers
+    sender().send(event).ok();
+}
+
+/// Creates a new subscriber to the events channel
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    sender().subscribe()
+}
+
+/// Logs all the events received by the provided subscriber
+/// to the debug output
+///
+/// `events` The subscriber to the events channel
+pub async fn log_events(mut events: broadcast::Receiver<Event>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => log_event(&event),
+            Err(RecvError::Lagged(count)) => warn!("Event logging missed {count} events"),
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Logs the provided event with each of its values as a separate field
+/// so that the JSON logs include them individually
+///
+/// `event` The event to log
+fn log_event(event: &Event) {
+    let session = match event {
+        Event::InstanceDecoded { host, port, secure } => {
+            debug!(
+                event = "instance_decoded",
+                host = host.as_str(),
+                port,
+                secure,
+                "Event: {event}"
+            );
+            return;
+        }
+        Event::Session(session) => session,
+        Event::Telemetry {
+            session, client, ..
+        } => {
+            debug!(event = "telemetry", session, %client, "Event: {event}");
+            return;
+        }
+    };
+    let time = session
+        .time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    match session {
+        SessionEvent::Accepted { client, .. } => {
+            debug!(event = "accepted", %client, time, "Event: {event}")
+        }
+        SessionEvent::Redirected {
+            client, instance, ..
+        } => debug!(
+            event = "redirected",
+            %client,
+            host = %instance.host,
+            port = instance.port,
+            time,
+            "Event: {event}"
+        ),
+        SessionEvent::ProxyStarted {
+            session,
+            client,
+            upstream,
+            ..
+        } => debug!(
+            event = "proxy_started",
+            session,
+            %client,
+            host = %upstream.host,
+            port = upstream.port,
+            time,
+            "Event: {event}"
+        ),
+        SessionEvent::LoginSucceeded {
+            session,
+            client,
+            persona_id,
+            has_token,
+            ..
+        } => debug!(
+            event = "login_succeeded",
+            session,
+            %client,
+            persona_id,
+            has_token,
+            time,
+            "Event: {event}"
+        ),
+        SessionEvent::LoginFailed {
+            session,
+            client,
+            error,
+            ..
+        } => debug!(
+            event = "login_failed",
+            session,
+            %client,
+            error,
+            time,
+            "Event: {event}"
+        ),
+        SessionEvent::Closed {
+            session,
+            client,
+            reason,
+            packets,
+            ..
+        } => debug!(
+            event = "closed",
+            session,
+            %client,
+            ?reason,
+            client_packets = packets.map(|(client, _)| client),
+            server_packets = packets.map(|(_, server)| server),
+            time,
+            "Event: {event}"
+        ),
+    }
+}
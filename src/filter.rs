@@ -0,0 +1,87 @@
+//! Module for filtering the proxied packets based on the component
+//! and command of their headers
+
+use crate::{
+    capture::Capture,
+    config::{FilterConfig, PacketRule},
+    intercept::{Action, PacketInterceptor, SessionInfo},
+    mitm::Direction,
+};
+use async_trait::async_trait;
+use blaze_pk::packet::Packet;
+use log::debug;
+use std::sync::Arc;
+
+impl PacketRule {
+    /// Returns whether this rule matches the provided packet
+    ///
+    /// `packet` The packet to check
+    fn matches(&self, packet: &Packet) -> bool {
+        let header = &packet.header;
+        header.component == self.component
+            && self.command.is_none_or(|command| header.command == command)
+    }
+}
+
+/// Interceptor which drops packets based on the allow and deny rules
+/// and captures packets that match the log rules
+pub struct PacketFilter {
+    /// Rules for packets that are allowed. When empty all the packets
+    /// are allowed
+    allow: Vec<PacketRule>,
+    /// Rules for packets that are dropped
+    deny: Vec<PacketRule>,
+    /// Rules for packets that are captured
+    log: Vec<PacketRule>,
+    /// The capture sink for packets matching the log rules
+    capture: Option<Arc<Capture>>,
+}
+
+impl PacketFilter {
+    /// Creates a new packet filter from the provided config
+    ///
+    /// `config`  The filter configuration
+    /// `capture` The capture sink for packets matching the log rules
+    pub fn new(config: &FilterConfig, capture: Option<Arc<Capture>>) -> Self {
+        Self {
+            allow: config.allow.clone(),
+            deny: config.deny.clone(),
+            log: config.log.clone(),
+            capture,
+        }
+    }
+
+    /// Returns whether the provided packet should be forwarded
+    ///
+    /// `packet` The packet to check
+    fn is_allowed(&self, packet: &Packet) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(packet)))
+            && !self.deny.iter().any(|rule| rule.matches(packet))
+    }
+}
+
+#[async_trait]
+impl PacketInterceptor for PacketFilter {
+    async fn on_packet(
+        &self,
+        session: &SessionInfo,
+        direction: Direction,
+        packet: &mut Packet,
+    ) -> Action {
+        if let Some(capture) = &self.capture {
+            if session.captured && self.log.iter().any(|rule| rule.matches(packet)) {
+                capture.record(session, direction, packet);
+            }
+        }
+        if self.is_allowed(packet) {
+            Action::Forward
+        } else {
+            let header = &packet.header;
+            debug!(
+                "Dropped filtered packet (Component: {:#06x}, Command: {:#06x})",
+                header.component, header.command
+            );
+            Action::Drop
+        }
+    }
+}
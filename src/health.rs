@@ -0,0 +1,169 @@
+//! Module for the HTTP endpoint answering the liveness and readiness
+//! checks of container orchestrators. `/healthz` responds while the
+//! process is running and `/readyz` responds with whether the official
+//! server was reachable on the last check and the server isn't shutting
+//! down along with the number of active sessions
+
+use crate::{config::Config, metrics::METRICS, retriever::Retriever};
+use log::{debug, error, info};
+use serde_json::json;
+use std::{io, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+use tokio_util::sync::CancellationToken;
+
+/// The maximum length of the request line and headers that is read
+const MAX_REQUEST_LENGTH: usize = 4096;
+/// The maximum time a connection is given to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts the health endpoint on the configured address along with the
+/// periodic checks of the official server. The endpoint keeps answering
+/// after shutdown so that readiness is reported as failing while the
+/// sessions are drained
+///
+/// `config`    The server configuration
+/// `retriever` The retriever for checking the official server
+/// `shutdown`  Token cancelled when the server is shutting down
+pub async fn start_server(
+    config: Arc<Config>,
+    retriever: Arc<Retriever>,
+    shutdown: CancellationToken,
+) {
+    let listener = match TcpListener::bind(config.health.bind).await {
+        Ok(value) => value,
+        Err(err) => {
+            error!(
+                "Failed to bind health endpoint on {} (Reason: {err})",
+                config.health.bind
+            );
+            return;
+        }
+    };
+    info!("Started health endpoint on {}", config.health.bind);
+
+    tokio::spawn(probe(
+        retriever.clone(),
+        config.health.probe_interval(),
+        config.redirect.health_check.timeout(),
+    ));
+
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Failed to accept health connection: {err}");
+                continue;
+            }
+        };
+        let retriever = retriever.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(err) = respond(stream, &retriever, &shutdown).await {
+                debug!("Health connection failed (Addr: {addr}, Reason: {err})");
+            }
+        });
+    }
+}
+
+/// Checks whether the official server is reachable on each interval so
+/// that readiness reflects its current state
+///
+/// `retriever` The retriever for checking the official server
+/// `interval`  The time between each check
+/// `timeout`   The maximum time to wait for each check
+async fn probe(retriever: Arc<Retriever>, interval: Duration, timeout: Duration) {
+    let mut interval = time::interval(interval);
+    loop {
+        interval.tick().await;
+        if !retriever.is_reachable(timeout).await {
+            debug!("Official server unreachable on health check");
+        }
+    }
+}
+
+/// Reads the request from the provided connection and writes the
+/// response for the requested path
+///
+/// `stream`    The health connection
+/// `retriever` The retriever with the last check of the official server
+/// `shutdown`  Token cancelled when the server is shutting down
+async fn respond(
+    mut stream: TcpStream,
+    retriever: &Retriever,
+    shutdown: &CancellationToken,
+) -> io::Result<()> {
+    let request = time::timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Request timed out"))??;
+    let line = request.lines().next().unwrap_or_default();
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, body) = if method != "GET" && method != "HEAD" {
+        (
+            "405 Method Not Allowed",
+            json!({ "error": "Method not allowed" }),
+        )
+    } else {
+        match path {
+            "/healthz" => ("200 OK", json!({ "status": "ok" })),
+            "/readyz" => {
+                let reachable = retriever.last_reachable();
+                let ready = reachable == Some(true) && !shutdown.is_cancelled();
+                let status = if ready {
+                    "200 OK"
+                } else {
+                    "503 Service Unavailable"
+                };
+                let body = json!({
+                    "ready": ready,
+                    "upstream_reachable": reachable,
+                    "shutting_down": shutdown.is_cancelled(),
+                    "active_sessions": METRICS.snapshot().active_sessions,
+                });
+                (status, body)
+            }
+            _ => ("404 Not Found", json!({ "error": "Not found" })),
+        }
+    };
+
+    let body = body.to_string();
+    let mut response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    if method != "HEAD" {
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads the request line and headers from the provided connection up
+/// to the blank line that ends them
+///
+/// `stream` The health connection
+async fn read_request(stream: &mut TcpStream) -> io::Result<String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buffer.len() >= MAX_REQUEST_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Request headers too long",
+            ));
+        }
+        let count = stream.read(&mut chunk).await?;
+        if count == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..count]);
+    }
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
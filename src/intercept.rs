@@ -0,0 +1,248 @@
+//! Module for intercepting the packets proxied by the MITM server. Registered
+//! interceptors are able to inspect, modify, drop or replace packets before
+//! they are forwarded.
+
+use crate::mitm::Direction;
+use async_trait::async_trait;
+use blaze_pk::packet::Packet;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// The action to take with a packet after it has been intercepted
+// Replace is not used by the built-in interceptors
+#[allow(dead_code)]
+pub enum Action {
+    /// Forward the packet (including any modifications)
+    Forward,
+    /// Drop the packet without forwarding it
+    Drop,
+    /// Forward the provided packet in place of the intercepted packet
+    Replace(Packet),
+}
+
+/// Details of the session that an intercepted packet belongs to. These
+/// are determined once when the session starts
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    /// The unique identifier of the session
+    pub id: u64,
+    /// The address of the client
+    pub addr: SocketAddr,
+    /// Whether the packets of the session are captured
+    pub captured: bool,
+    /// The sequence numbers of the session packets, these are only
+    /// tracked while capturing
+    pub sequences: Option<Arc<PacketSequences>>,
+}
+
+impl SessionInfo {
+    /// Returns the sequence number of the packet currently being processed
+    /// in the provided direction or zero when they aren't tracked
+    ///
+    /// `direction` The direction of the packet
+    pub fn sequence(&self, direction: Direction) -> u64 {
+        self.sequences
+            .as_ref()
+            .map_or(0, |sequences| sequences.current(direction))
+    }
+}
+
+/// Sequence numbers assigned to the packets of a session in the order
+/// they were read, counted separately for each direction starting at one
+#[derive(Debug, Default)]
+pub struct PacketSequences {
+    /// The sequence of the last packet from the client
+    client: AtomicU64,
+    /// The sequence of the last packet from the official server
+    server: AtomicU64,
+}
+
+impl PacketSequences {
+    /// Returns the counter for the provided direction
+    ///
+    /// `direction` The direction of the packets
+    fn counter(&self, direction: Direction) -> &AtomicU64 {
+        match direction {
+            Direction::ClientToServer => &self.client,
+            Direction::ServerToClient => &self.server,
+        }
+    }
+
+    /// Assigns the next sequence number in the provided direction
+    ///
+    /// `direction` The direction of the packet
+    fn next(&self, direction: Direction) -> u64 {
+        self.counter(direction).fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Returns the last sequence number assigned in the provided direction.
+    /// Each direction is read by one task at a time so this is the sequence
+    /// of the packet that task is processing
+    ///
+    /// `direction` The direction of the packet
+    pub fn current(&self, direction: Direction) -> u64 {
+        self.counter(direction).load(Ordering::Relaxed)
+    }
+}
+
+/// Trait implemented by packet interceptors which are called for each
+/// packet proxied by the MITM server
+#[async_trait]
+pub trait PacketInterceptor: Send + Sync {
+    /// Handles a packet that is being proxied returning the action
+    /// that should be taken with the packet
+    ///
+    /// `session`   The session the packet belongs to
+    /// `direction` The direction the packet is travelling in
+    /// `packet`    The packet being proxied
+    async fn on_packet(
+        &self,
+        session: &SessionInfo,
+        direction: Direction,
+        packet: &mut Packet,
+    ) -> Action;
+}
+
+#[async_trait]
+impl<I: PacketInterceptor> PacketInterceptor for Arc<I> {
+    async fn on_packet(
+        &self,
+        session: &SessionInfo,
+        direction: Direction,
+        packet: &mut Packet,
+    ) -> Action {
+        self.as_ref().on_packet(session, direction, packet).await
+    }
+}
+
+/// Handle for a registered interceptor which is used to unregister it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterceptorHandle(u64);
+
+/// The registered interceptors along with their handle identifiers
+type Registered = Vec<(u64, Arc<dyn PacketInterceptor>)>;
+
+/// Registry of interceptors which can be registered and unregistered
+/// while the server is running. Interceptors are called in the order
+/// that they were registered
+#[derive(Default)]
+pub struct InterceptorRegistry {
+    /// The registered interceptors. The list is replaced rather than
+    /// modified so packets that are being processed keep using the
+    /// interceptors that were registered when they arrived
+    interceptors: RwLock<Arc<Registered>>,
+    /// The identifier for the next registered interceptor
+    next_id: AtomicU64,
+}
+
+impl InterceptorRegistry {
+    /// Registers the provided interceptor after the currently registered
+    /// interceptors returning the handle to unregister it with
+    ///
+    /// `interceptor` The interceptor to register
+    pub fn register<I: PacketInterceptor + 'static>(&self, interceptor: I) -> InterceptorHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut interceptors = match self.interceptors.write() {
+            Ok(value) => value,
+            Err(err) => err.into_inner(),
+        };
+        let mut registered = Registered::clone(&interceptors);
+        registered.push((id, Arc::new(interceptor)));
+        *interceptors = Arc::new(registered);
+        InterceptorHandle(id)
+    }
+
+    /// Unregisters the interceptor for the provided handle returning
+    /// whether it was registered
+    ///
+    /// `handle` The handle of the interceptor
+    pub fn unregister(&self, handle: InterceptorHandle) -> bool {
+        let mut interceptors = match self.interceptors.write() {
+            Ok(value) => value,
+            Err(err) => err.into_inner(),
+        };
+        let mut registered = Registered::clone(&interceptors);
+        registered.retain(|(id, _)| *id != handle.0);
+        let removed = registered.len() != interceptors.len();
+        *interceptors = Arc::new(registered);
+        removed
+    }
+
+    /// Returns the currently registered interceptors
+    fn current(&self) -> Arc<Registered> {
+        match self.interceptors.read() {
+            Ok(value) => value.clone(),
+            Err(err) => err.into_inner().clone(),
+        }
+    }
+
+    /// Passes the provided packet through each of the interceptors
+    /// returning the packet to forward or None if it was dropped
+    ///
+    /// `session`   The session the packet belongs to
+    /// `direction` The direction the packet is travelling in
+    /// `packet`    The packet being proxied
+    pub async fn process(
+        &self,
+        session: &SessionInfo,
+        direction: Direction,
+        mut packet: Packet,
+    ) -> Option<Packet> {
+        for (_, interceptor) in self.current().iter() {
+            match interceptor.on_packet(session, direction, &mut packet).await {
+                Action::Forward => {}
+                Action::Drop => return None,
+                Action::Replace(value) => packet = value,
+            }
+        }
+        Some(packet)
+    }
+}
+
+/// The interceptors along with the details of the session that
+/// they are processing the packets of
+#[derive(Clone)]
+pub struct SessionInterceptors {
+    /// The registered interceptors
+    interceptors: Arc<InterceptorRegistry>,
+    /// The session the packets belong to
+    session: SessionInfo,
+}
+
+impl SessionInterceptors {
+    /// Creates the interceptors for a session
+    ///
+    /// `interceptors` The registered interceptors
+    /// `session`      The session the packets belong to
+    pub fn new(interceptors: Arc<InterceptorRegistry>, session: SessionInfo) -> Self {
+        Self {
+            interceptors,
+            session,
+        }
+    }
+
+    /// Returns the session the packets belong to
+    pub fn session(&self) -> &SessionInfo {
+        &self.session
+    }
+
+    /// Assigns the packet its sequence number and passes it through each
+    /// of the interceptors returning the packet to forward or None if it
+    /// was dropped
+    ///
+    /// `direction` The direction the packet is travelling in
+    /// `packet`    The packet being proxied
+    pub async fn process(&self, direction: Direction, packet: Packet) -> Option<Packet> {
+        if let Some(sequences) = &self.session.sequences {
+            sequences.next(direction);
+        }
+        self.interceptors
+            .process(&self.session, direction, packet)
+            .await
+    }
+}
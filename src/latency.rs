@@ -0,0 +1,93 @@
+//! Module for injecting latency into the proxied packets in order to
+//! simulate poor network conditions
+
+use crate::{
+    config::{DelayConfig, LatencyConfig},
+    intercept::{Action, PacketInterceptor, SessionInfo},
+    mitm::Direction,
+};
+use async_trait::async_trait;
+use blaze_pk::packet::Packet;
+use log::info;
+use rand::Rng;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::time;
+
+/// Interceptor which delays each proxied packet by the configured
+/// amount for the direction that it is travelling in
+pub struct LatencyInjector {
+    /// Whether the latency injection is currently enabled
+    enabled: AtomicBool,
+    /// The delay for packets from the client to the official server
+    client_to_server: DelayConfig,
+    /// The delay for packets from the official server to the client
+    server_to_client: DelayConfig,
+}
+
+impl LatencyInjector {
+    /// Creates a new latency injector from the provided config
+    ///
+    /// `config` The latency configuration
+    pub fn new(config: &LatencyConfig) -> Self {
+        Self {
+            enabled: AtomicBool::new(config.enabled),
+            client_to_server: config.client_to_server.clone(),
+            server_to_client: config.server_to_client.clone(),
+        }
+    }
+
+    /// Sets whether the latency injection is enabled
+    ///
+    /// `enabled` Whether to enable the latency injection
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        info!(
+            "Latency injection {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+    }
+
+    /// Toggles whether the latency injection is enabled
+    pub fn toggle(&self) {
+        self.set_enabled(!self.enabled.load(Ordering::Relaxed));
+    }
+
+    /// Returns the delay to apply to a packet travelling in the
+    /// provided direction
+    ///
+    /// `direction` The direction of the packet
+    fn delay(&self, direction: Direction) -> Duration {
+        let config = match direction {
+            Direction::ClientToServer => &self.client_to_server,
+            Direction::ServerToClient => &self.server_to_client,
+        };
+        let mut delay = config.delay_ms;
+        if config.jitter_ms > 0 {
+            let jitter = config.jitter_ms as i64;
+            let offset = rand::thread_rng().gen_range(-jitter..=jitter);
+            delay = (delay as i64 + offset).max(0) as u64;
+        }
+        Duration::from_millis(delay)
+    }
+}
+
+#[async_trait]
+impl PacketInterceptor for LatencyInjector {
+    async fn on_packet(
+        &self,
+        _session: &SessionInfo,
+        direction: Direction,
+        _packet: &mut Packet,
+    ) -> Action {
+        if self.enabled.load(Ordering::Relaxed) {
+            let delay = self.delay(direction);
+            if !delay.is_zero() {
+                time::sleep(delay).await;
+            }
+        }
+        Action::Forward
+    }
+}
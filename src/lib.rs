@@ -0,0 +1,49 @@
+//! Man-In-The-Middle server for Pocket Relay development and testing. The
+//! [`Server`] runs the redirector and MITM servers and can be embedded
+//! within another process using its builder
+
+pub mod access;
+pub mod auth;
+pub mod capture;
+pub mod components;
+pub mod config;
+pub mod console;
+pub mod dns;
+pub mod dump;
+pub mod events;
+pub mod filter;
+#[cfg(feature = "health")]
+pub mod health;
+pub mod intercept;
+pub mod latency;
+pub mod logging;
+pub mod login;
+pub mod metrics;
+pub mod mitm;
+pub mod models;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod qos;
+pub mod ratelimit;
+pub mod redirector;
+pub mod replay;
+pub mod retriever;
+pub mod server;
+pub mod socks;
+pub mod stream;
+#[cfg(feature = "testing")]
+pub mod stub;
+pub mod telemetry;
+pub mod validate;
+
+pub use server::{Server, ServerBuilder, ServerError};
+
+/// The external address of the server. This address is whats used in
+/// the system hosts file as a redirect so theres no need to use any
+/// other address.
+pub const EXTERNAL_HOST: &str = "gosredirector.ea.com";
+/// The server version extracted from the Cargo.toml
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const REDIRECTOR_PORT: u16 = 42127;
+pub const MAIN_PORT: u16 = 42128;
@@ -1,36 +1,210 @@
-use log::LevelFilter;
-use log4rs::{
-    append::{console::ConsoleAppender, file::FileAppender},
-    config::{Appender, Root},
-    encode::pattern::PatternEncoder,
-    init_config, Config,
+use crate::config::{LogFormat, LoggingConfig};
+use serde_json::{Map, Value};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
 };
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span, Event, Subscriber,
+};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields, FormattedFields},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+};
+
+/// The path to the logging file
+const LOGGING_PATH: &str = "logs/log.log";
+
+/// Setup function for setting up the tracing logging with stdout and file
+/// logging. Records from the log macros are forwarded to tracing so that
+/// they include the fields of the session span they were logged within.
+/// When built with the `otel` feature and an OTLP endpoint is configured
+/// the spans are also exported to it
+///
+/// `config` The logging configuration
+pub fn setup(config: &LoggingConfig) {
+    fs::create_dir_all("logs").expect("Unable to create logging directory");
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(LOGGING_PATH)
+        .expect("Unable to create logging file");
+
+    let registry = tracing_subscriber::registry().with(LevelFilter::DEBUG);
+    #[cfg(feature = "otel")]
+    let (otel, otel_error) = match config.otlp_endpoint.as_deref().map(crate::otel::layer) {
+        Some(Ok(layer)) => (Some(layer), None),
+        Some(Err(err)) => (None, Some(err)),
+        None => (None, None),
+    };
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel);
+    match config.format {
+        LogFormat::Text => registry
+            .with(tracing_subscriber::fmt::layer())
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(Mutex::new(file)),
+            )
+            .init(),
+        LogFormat::Json => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(JsonFields)
+                    .event_format(JsonFormat),
+            )
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(JsonFields)
+                    .event_format(JsonFormat)
+                    .with_writer(Mutex::new(file)),
+            )
+            .init(),
+    }
+
+    #[cfg(feature = "otel")]
+    if let Some(err) = otel_error {
+        log::warn!("Failed to create the OTLP span exporter, spans won't be exported: {err}");
+    }
+    #[cfg(not(feature = "otel"))]
+    if config.otlp_endpoint.is_some() {
+        log::warn!("OTLP span export requires the otel feature, spans won't be exported");
+    }
+}
+
+/// Exports the spans that are still queued when built with the `otel`
+/// feature, called before the process exits
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    crate::otel::shutdown();
+}
+
+/// Formatter for writing each event as a JSON object on its own line.
+/// The fields of the spans the event was logged within are included
+/// prefixed by the span name (e.g. `session.id`)
+struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        // Records from the log macros carry their metadata as fields
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        let mut object = Map::new();
+        object.insert("timestamp".to_string(), timestamp.into());
+        object.insert("level".to_string(), metadata.level().as_str().into());
+        object.insert("target".to_string(), metadata.target().into());
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let Some(fields) = extensions.get::<FormattedFields<JsonFields>>() else {
+                    continue;
+                };
+                let Ok(Value::Object(fields)) = serde_json::from_str(&fields.fields) else {
+                    continue;
+                };
+                for (name, value) in fields {
+                    object.insert(format!("{}.{name}", span.name()), value);
+                }
+            }
+        }
+
+        let mut visitor = JsonVisitor(object);
+        event.record(&mut visitor);
+        writeln!(writer, "{}", Value::Object(visitor.0))
+    }
+}
+
+/// Formatter for the fields of spans which stores them as a JSON
+/// object so they can be merged into the events logged within them
+struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor(Map::new());
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.0))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &span::Record<'_>,
+    ) -> fmt::Result {
+        let existing = match serde_json::from_str(&current.fields) {
+            Ok(Value::Object(existing)) => existing,
+            _ => Map::new(),
+        };
+        let mut visitor = JsonVisitor(existing);
+        fields.record(&mut visitor);
+        current.fields = Value::Object(visitor.0).to_string();
+        Ok(())
+    }
+}
+
+/// Visitor collecting the recorded fields into a JSON object
+struct JsonVisitor(Map<String, Value>);
+
+impl JsonVisitor {
+    /// Inserts the provided value skipping the metadata fields that
+    /// are added to records from the log macros
+    ///
+    /// `field` The field the value is for
+    /// `value` The value of the field
+    fn insert(&mut self, field: &Field, value: Value) {
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
 
-/// The pattern to use when logging
-const LOGGING_PATTERN: &str = "[{d} {h({l})} {M}] {m}{n}";
-
-/// Setup function for setting up the Log4rs logging configuring it
-/// for all the different modules and and setting up file and stdout logging
-pub fn setup() {
-    let pattern = Box::new(PatternEncoder::new(LOGGING_PATTERN));
-    let stdout_appender = ConsoleAppender::builder().encoder(pattern.clone()).build();
-
-    let file_appender = FileAppender::builder()
-        .encoder(pattern)
-        .build("logs/log.log")
-        .expect("Unable to create logging file appender");
-
-    const APPENDERS: [&str; 2] = ["stdout", "file"];
-
-    let config = Config::builder()
-        .appender(Appender::builder().build("stdout", Box::new(stdout_appender)))
-        .appender(Appender::builder().build("file", Box::new(file_appender)))
-        .build(
-            Root::builder()
-                .appenders(APPENDERS)
-                .build(LevelFilter::Debug),
-        )
-        .expect("Failed to create logging config");
-
-    init_config(config).expect("Unable to initialize logger");
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{value:?}").into());
+    }
 }
@@ -0,0 +1,89 @@
+//! Module for the overall deadline on the login flow of a connection.
+//! The flow is made up of several steps which each wait on the network
+//! so the current step is tracked in order to report which one stalled
+
+use log::warn;
+use std::{fmt::Display, future::Future, sync::Mutex, time::Duration};
+use tokio::{select, time};
+
+/// The steps of the login flow
+#[derive(Debug, Clone, Copy)]
+pub enum LoginStage {
+    /// Completing the SSL handshake with the client
+    Handshake,
+    /// Waiting for the client to request a redirect
+    AwaitingRequest,
+    /// Determining the instance the client is redirected to
+    Redirecting,
+    /// Sending the redirect response to the client
+    Responding,
+    /// Connecting to the official server for the proxy link
+    ConnectingUpstream,
+}
+
+impl Display for LoginStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Handshake => "handshake",
+            Self::AwaitingRequest => "awaiting request",
+            Self::Redirecting => "redirecting",
+            Self::Responding => "responding",
+            Self::ConnectingUpstream => "connecting upstream",
+        })
+    }
+}
+
+/// Tracker for the current step of a login flow
+pub struct LoginTracker(Mutex<LoginStage>);
+
+impl LoginTracker {
+    /// Creates a new tracker starting at the provided step
+    ///
+    /// `stage` The first step of the flow
+    pub fn new(stage: LoginStage) -> Self {
+        Self(Mutex::new(stage))
+    }
+
+    /// Moves the flow onto the provided step
+    ///
+    /// `stage` The new step
+    pub fn enter(&self, stage: LoginStage) {
+        match self.0.lock() {
+            Ok(mut value) => *value = stage,
+            Err(err) => *err.into_inner() = stage,
+        }
+    }
+
+    /// Returns the current step of the flow
+    pub fn current(&self) -> LoginStage {
+        match self.0.lock() {
+            Ok(value) => *value,
+            Err(err) => *err.into_inner(),
+        }
+    }
+}
+
+/// Runs the provided login flow until it completes or the timeout is
+/// reached. On timeout the flow is dropped, closing any connections it
+/// had opened, and the step that stalled is logged before None is returned
+///
+/// `timeout` The maximum duration of the whole flow
+/// `tracker` The tracker updated by the flow
+/// `flow`    The login flow future
+pub async fn with_deadline<F: Future>(
+    timeout: Duration,
+    tracker: &LoginTracker,
+    flow: F,
+) -> Option<F::Output> {
+    select! {
+        value = flow => Some(value),
+        _ = time::sleep(timeout) => {
+            warn!(
+                "Login flow timed out after {}s during the {} stage",
+                timeout.as_secs_f32(),
+                tracker.current()
+            );
+            None
+        }
+    }
+}
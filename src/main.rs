@@ -1,24 +1,9 @@
-use std::sync::Arc;
+use std::{env, path::Path};
 
-use log::info;
+use log::{error, info};
+use pocket_relay_mitm::{capture, config, logging, Server, VERSION};
 use tokio::signal;
-
-mod components;
-mod logging;
-mod mitm;
-mod models;
-mod redirector;
-mod retriever;
-
-/// The external address of the server. This address is whats used in
-/// the system hosts file as a redirect so theres no need to use any
-/// other address.
-pub const EXTERNAL_HOST: &str = "gosredirector.ea.com";
-/// The server version extracted from the Cargo.toml
-pub const VERSION: &str = env!("CARGO_PKG_VERSION");
-
-pub const REDIRECTOR_PORT: u16 = 42127;
-pub const MAIN_PORT: u16 = 42128;
+use tokio_util::sync::CancellationToken;
 
 fn main() {
     // Create the tokio runtime
@@ -27,22 +12,67 @@ fn main() {
         .build()
         .expect("Failed building the tokio Runtime");
 
-    logging::setup();
+    let config = config::load().expect("Failed to load config");
+
+    logging::setup(&config.logging);
 
     info!("Starting Pocket Relay MITM v{}", VERSION);
 
-    let retriever = runtime
-        .block_on(retriever::Retriever::new())
-        .expect("Failed to initialize connection to official server");
+    // Dump a packet capture instead of starting the servers. This is done
+    // before capturing starts so the capture file isn't replaced
+    let args: Vec<String> = env::args().collect();
+    if let (Some("dump"), Some(path)) = (args.get(1).map(String::as_str), args.get(2)) {
+        if let Err(err) = runtime.block_on(capture::dump(Path::new(path))) {
+            error!("Failed to dump packet capture: {err}");
+        }
+        return;
+    }
 
-    // Spawn redirector in its own task
-    runtime.spawn(redirector::start_server());
+    let server = Server::builder()
+        .config(config)
+        .console(true)
+        .reload_signal(true)
+        .build();
 
-    // Start the MITM server
-    runtime.spawn(mitm::start_server(Arc::new(retriever)));
+    // Replay a session recording instead of starting the servers
+    if let (Some("replay"), Some(path)) = (args.get(1).map(String::as_str), args.get(2)) {
+        let realtime = args.iter().any(|arg| arg == "--realtime");
+        if let Err(err) = runtime.block_on(server.replay(Path::new(path), realtime)) {
+            error!("Failed to replay session recording: {err}");
+        }
+        return;
+    }
 
-    // Block until shutdown is recieved
-    runtime.block_on(signal::ctrl_c()).ok();
+    let shutdown = CancellationToken::new();
+    let run = runtime.spawn(server.run(shutdown.clone()));
+
+    // Shutdown once a shutdown signal is recieved
+    runtime.spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down...");
+        shutdown.cancel();
+    });
+
+    match runtime.block_on(run) {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => error!("{err}"),
+        Err(err) => error!("Server stopped unexpectedly: {err}"),
+    }
+    logging::shutdown();
+}
 
-    info!("Shutting down...");
+/// Waits until a shutdown signal is received. This is either Ctrl+C
+/// or on unix systems SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.ok();
 }
@@ -0,0 +1,452 @@
+//! Module for the metrics counters which track the load on the server
+
+use crate::{events::CloseReason, mitm::Direction};
+use blaze_pk::packet::{Packet, PacketHeader};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+/// The global metrics counters
+pub static METRICS: Metrics = Metrics::new();
+
+/// Atomic counters for the server metrics
+pub struct Metrics {
+    /// The total number of packets forwarded
+    packets_forwarded: AtomicU64,
+    /// The total number of bytes forwarded from clients to the official server
+    bytes_client_to_server: AtomicU64,
+    /// The total number of bytes forwarded from the official server to clients
+    bytes_server_to_client: AtomicU64,
+    /// The number of currently active sessions
+    active_sessions: AtomicU64,
+    /// The total number of sessions started
+    total_sessions: AtomicU64,
+    /// The highest number of sessions that were active at once
+    peak_sessions: AtomicU64,
+    /// The time the server started
+    started: OnceLock<Instant>,
+    /// The total number of reconnection attempts to the official server
+    retriever_reconnects: AtomicU64,
+    /// The total number of packets that failed to decode
+    decode_errors: AtomicU64,
+    /// The number of packets that failed to decode for each component
+    /// and command pair
+    decode_errors_by_command: Mutex<BTreeMap<(u16, u16), u64>>,
+    /// The total number of rejected connections and delayed packets
+    rate_limited: AtomicU64,
+    /// The total number of connections rejected by the session limit
+    sessions_rejected: AtomicU64,
+    /// The total number of failed SSL handshakes with clients
+    client_handshake_failures: AtomicU64,
+    /// The total number of sessions closed for each reason
+    sessions_closed: ClosedCounters,
+    /// The total number of times reading paused for a full buffer
+    backpressure: AtomicU64,
+    /// The total number of flushes of coalesced packets
+    coalesced_flushes: AtomicU64,
+    /// The total number of packets written by coalesced flushes
+    coalesced_packets: AtomicU64,
+    /// The recent throughput from clients to the official server
+    throughput_client_to_server: Throughput,
+    /// The recent throughput from the official server to clients
+    throughput_server_to_client: Throughput,
+}
+
+/// Plain snapshot of the metrics counters at a point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    /// The total number of packets forwarded
+    pub packets_forwarded: u64,
+    /// The total number of bytes forwarded from clients to the official server
+    pub bytes_client_to_server: u64,
+    /// The total number of bytes forwarded from the official server to clients
+    pub bytes_server_to_client: u64,
+    /// The number of currently active sessions
+    pub active_sessions: u64,
+    /// The total number of sessions started
+    pub total_sessions: u64,
+    /// The highest number of sessions that were active at once
+    pub peak_sessions: u64,
+    /// The number of seconds since the server started
+    pub uptime_secs: u64,
+    /// The total number of reconnection attempts to the official server
+    pub retriever_reconnects: u64,
+    /// The total number of packets that failed to decode
+    pub decode_errors: u64,
+    /// The number of packets that failed to decode for each component
+    /// and command that has failed
+    pub decode_errors_by_command: Vec<DecodeErrorCount>,
+    /// The total number of rejected connections and delayed packets
+    pub rate_limited: u64,
+    /// The total number of connections rejected by the session limit
+    pub sessions_rejected: u64,
+    /// The total number of failed SSL handshakes with clients
+    pub client_handshake_failures: u64,
+    /// The total number of sessions closed for each reason
+    pub sessions_closed: ClosedSnapshot,
+    /// The total number of times reading paused for a full buffer
+    pub backpressure: u64,
+    /// The average number of packets written by each coalesced flush
+    pub average_flush_batch: f64,
+    /// The recent throughput from clients to the official server
+    pub throughput_client_to_server: ThroughputSnapshot,
+    /// The recent throughput from the official server to clients
+    pub throughput_server_to_client: ThroughputSnapshot,
+}
+
+impl Metrics {
+    /// Creates a new set of metrics with all the counters at zero
+    const fn new() -> Self {
+        Self {
+            packets_forwarded: AtomicU64::new(0),
+            bytes_client_to_server: AtomicU64::new(0),
+            bytes_server_to_client: AtomicU64::new(0),
+            active_sessions: AtomicU64::new(0),
+            total_sessions: AtomicU64::new(0),
+            peak_sessions: AtomicU64::new(0),
+            started: OnceLock::new(),
+            retriever_reconnects: AtomicU64::new(0),
+            decode_errors: AtomicU64::new(0),
+            decode_errors_by_command: Mutex::new(BTreeMap::new()),
+            rate_limited: AtomicU64::new(0),
+            sessions_rejected: AtomicU64::new(0),
+            client_handshake_failures: AtomicU64::new(0),
+            sessions_closed: ClosedCounters {
+                client_closed: AtomicU64::new(0),
+                upstream_closed: AtomicU64::new(0),
+                error: AtomicU64::new(0),
+                shutdown: AtomicU64::new(0),
+                idle_timeout: AtomicU64::new(0),
+            },
+            backpressure: AtomicU64::new(0),
+            coalesced_flushes: AtomicU64::new(0),
+            coalesced_packets: AtomicU64::new(0),
+            throughput_client_to_server: Throughput::new(),
+            throughput_server_to_client: Throughput::new(),
+        }
+    }
+
+    /// Records a packet that was forwarded in the provided direction
+    ///
+    /// `direction` The direction the packet was forwarded in
+    /// `packet`    The forwarded packet
+    pub fn packet_forwarded(&self, direction: Direction, packet: &Packet) {
+        self.packets_forwarded.fetch_add(1, Ordering::Relaxed);
+        let bytes = frame_length(packet) as u64;
+        let (counter, throughput) = match direction {
+            Direction::ClientToServer => (
+                &self.bytes_client_to_server,
+                &self.throughput_client_to_server,
+            ),
+            Direction::ServerToClient => (
+                &self.bytes_server_to_client,
+                &self.throughput_server_to_client,
+            ),
+        };
+        counter.fetch_add(bytes, Ordering::Relaxed);
+        throughput.record(bytes);
+    }
+
+    /// Records the time the server started which uptime is measured
+    /// from, only the first call has an effect
+    pub fn start(&self) {
+        self.started.get_or_init(Instant::now);
+    }
+
+    /// Records the start of a session returning a guard which records
+    /// the end of the session when dropped
+    pub fn session_started(&'static self) -> SessionGuard {
+        let active = self.active_sessions.fetch_add(1, Ordering::Relaxed) + 1;
+        self.total_sessions.fetch_add(1, Ordering::Relaxed);
+        self.peak_sessions.fetch_max(active, Ordering::Relaxed);
+        SessionGuard(self)
+    }
+
+    /// Records a reconnection attempt to the official server
+    pub fn retriever_reconnect(&self) {
+        self.retriever_reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a packet that failed to decode
+    ///
+    /// `header` The header of the packet
+    pub fn decode_error(&self, header: &PacketHeader) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+        let mut counts = match self.decode_errors_by_command.lock() {
+            Ok(value) => value,
+            Err(err) => err.into_inner(),
+        };
+        *counts
+            .entry((header.component, header.command))
+            .or_default() += 1;
+    }
+
+    /// Records a connection or packet that was rate limited
+    pub fn rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a connection that was rejected by the session limit
+    pub fn session_rejected(&self) {
+        self.sessions_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a failed SSL handshake with a client
+    pub fn client_handshake_failed(&self) {
+        self.client_handshake_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a session that was closed
+    ///
+    /// `reason` The reason the session was closed
+    pub fn session_closed(&self, reason: CloseReason) {
+        let closed = &self.sessions_closed;
+        let counter = match reason {
+            CloseReason::ClientClosed => &closed.client_closed,
+            CloseReason::UpstreamClosed => &closed.upstream_closed,
+            CloseReason::Error => &closed.error,
+            CloseReason::Shutdown => &closed.shutdown,
+            CloseReason::IdleTimeout => &closed.idle_timeout,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a pause in reading because the buffer for the other
+    /// side of the connection was full
+    pub fn backpressure(&self) {
+        self.backpressure.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a flush of packets that were coalesced together
+    ///
+    /// `packets` The number of packets written by the flush
+    pub fn flush_batch(&self, packets: u64) {
+        self.coalesced_flushes.fetch_add(1, Ordering::Relaxed);
+        self.coalesced_packets.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the current counter values
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let decode_errors_by_command = match self.decode_errors_by_command.lock() {
+            Ok(value) => value,
+            Err(err) => err.into_inner(),
+        };
+        MetricsSnapshot {
+            packets_forwarded: self.packets_forwarded.load(Ordering::Relaxed),
+            bytes_client_to_server: self.bytes_client_to_server.load(Ordering::Relaxed),
+            bytes_server_to_client: self.bytes_server_to_client.load(Ordering::Relaxed),
+            active_sessions: self.active_sessions.load(Ordering::Relaxed),
+            total_sessions: self.total_sessions.load(Ordering::Relaxed),
+            peak_sessions: self.peak_sessions.load(Ordering::Relaxed),
+            uptime_secs: self.started.get_or_init(Instant::now).elapsed().as_secs(),
+            retriever_reconnects: self.retriever_reconnects.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            decode_errors_by_command: decode_errors_by_command
+                .iter()
+                .map(|(&(component, command), &count)| DecodeErrorCount {
+                    component,
+                    command,
+                    count,
+                })
+                .collect(),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            sessions_rejected: self.sessions_rejected.load(Ordering::Relaxed),
+            client_handshake_failures: self.client_handshake_failures.load(Ordering::Relaxed),
+            sessions_closed: ClosedSnapshot {
+                client_closed: self.sessions_closed.client_closed.load(Ordering::Relaxed),
+                upstream_closed: self.sessions_closed.upstream_closed.load(Ordering::Relaxed),
+                error: self.sessions_closed.error.load(Ordering::Relaxed),
+                shutdown: self.sessions_closed.shutdown.load(Ordering::Relaxed),
+                idle_timeout: self.sessions_closed.idle_timeout.load(Ordering::Relaxed),
+            },
+            backpressure: self.backpressure.load(Ordering::Relaxed),
+            average_flush_batch: match self.coalesced_flushes.load(Ordering::Relaxed) {
+                0 => 0.0,
+                flushes => self.coalesced_packets.load(Ordering::Relaxed) as f64 / flushes as f64,
+            },
+            throughput_client_to_server: self.throughput_client_to_server.snapshot(),
+            throughput_server_to_client: self.throughput_server_to_client.snapshot(),
+        }
+    }
+}
+
+/// Counters for the number of sessions closed for each reason
+struct ClosedCounters {
+    /// Sessions closed by the client
+    client_closed: AtomicU64,
+    /// Sessions closed because the official server connection was lost
+    upstream_closed: AtomicU64,
+    /// Sessions closed by an error
+    error: AtomicU64,
+    /// Sessions closed by the server shutting down
+    shutdown: AtomicU64,
+    /// Sessions closed after being idle
+    idle_timeout: AtomicU64,
+}
+
+/// Snapshot of the number of sessions closed for each reason
+#[derive(Debug, Clone, Serialize)]
+pub struct ClosedSnapshot {
+    /// Sessions closed by the client
+    pub client_closed: u64,
+    /// Sessions closed because the official server connection was lost
+    pub upstream_closed: u64,
+    /// Sessions closed by an error
+    pub error: u64,
+    /// Sessions closed by the server shutting down
+    pub shutdown: u64,
+    /// Sessions closed after being idle
+    pub idle_timeout: u64,
+}
+
+/// The number of decode failures for a single component and command
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodeErrorCount {
+    /// The component of the packets
+    pub component: u16,
+    /// The command of the packets
+    pub command: u16,
+    /// The number of packets that failed to decode
+    pub count: u64,
+}
+
+/// The number of seconds of throughput history that is kept, this is
+/// one more than the longest average so that the current second
+/// doesn't replace the oldest second being averaged
+const THROUGHPUT_WINDOW: usize = 61;
+
+/// Returns the number of whole seconds since the first throughput
+/// was recorded
+fn current_second() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs()
+}
+
+/// Bytes and packets forwarded within a single second
+struct ThroughputSlot {
+    /// The second that the slot is counting, one more than the
+    /// actual second so that zero marks an unused slot
+    second: AtomicU64,
+    /// The number of bytes forwarded in the second
+    bytes: AtomicU64,
+    /// The number of packets forwarded in the second
+    packets: AtomicU64,
+}
+
+/// Sliding window of the throughput for each of the recent seconds.
+/// Slots are reused once a second has passed out of the window, the
+/// counts are approximate if a slot is reused while being recorded to
+struct Throughput {
+    /// The slots for each second in the window
+    slots: [ThroughputSlot; THROUGHPUT_WINDOW],
+}
+
+impl Throughput {
+    /// Creates a new throughput window with no recorded seconds
+    const fn new() -> Self {
+        Self {
+            slots: [const {
+                ThroughputSlot {
+                    second: AtomicU64::new(0),
+                    bytes: AtomicU64::new(0),
+                    packets: AtomicU64::new(0),
+                }
+            }; THROUGHPUT_WINDOW],
+        }
+    }
+
+    /// Records a forwarded packet in the slot for the current second
+    ///
+    /// `bytes` The length of the forwarded packet
+    fn record(&self, bytes: u64) {
+        let second = current_second() + 1;
+        let slot = &self.slots[second as usize % THROUGHPUT_WINDOW];
+        let previous = slot.second.load(Ordering::Relaxed);
+        if previous != second
+            && slot
+                .second
+                .compare_exchange(previous, second, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            slot.bytes.store(0, Ordering::Relaxed);
+            slot.packets.store(0, Ordering::Relaxed);
+        }
+        slot.bytes.fetch_add(bytes, Ordering::Relaxed);
+        slot.packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the average throughput over the last 1, 10 and 60
+    /// complete seconds
+    fn snapshot(&self) -> ThroughputSnapshot {
+        let current = current_second() + 1;
+        let average = |seconds: u64, counter: fn(&ThroughputSlot) -> &AtomicU64| {
+            let total: u64 = self
+                .slots
+                .iter()
+                .filter(|slot| {
+                    let second = slot.second.load(Ordering::Relaxed);
+                    second < current && second + seconds >= current
+                })
+                .map(|slot| counter(slot).load(Ordering::Relaxed))
+                .sum();
+            total as f64 / seconds as f64
+        };
+        let averages = |counter: fn(&ThroughputSlot) -> &AtomicU64| RateAverages {
+            last_1s: average(1, counter),
+            last_10s: average(10, counter),
+            last_60s: average(60, counter),
+        };
+        ThroughputSnapshot {
+            bytes_per_sec: averages(|slot| &slot.bytes),
+            packets_per_sec: averages(|slot| &slot.packets),
+        }
+    }
+}
+
+/// Snapshot of the recent throughput in a single direction
+#[derive(Debug, Clone, Serialize)]
+pub struct ThroughputSnapshot {
+    /// The average number of bytes forwarded per second
+    pub bytes_per_sec: RateAverages,
+    /// The average number of packets forwarded per second
+    pub packets_per_sec: RateAverages,
+}
+
+/// Averages of a rate over windows of different lengths
+#[derive(Debug, Clone, Serialize)]
+pub struct RateAverages {
+    /// The average over the last second
+    pub last_1s: f64,
+    /// The average over the last 10 seconds
+    pub last_10s: f64,
+    /// The average over the last 60 seconds
+    pub last_60s: f64,
+}
+
+/// Guard for an active session which decrements the active
+/// sessions counter when dropped
+pub struct SessionGuard(&'static Metrics);
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.active_sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the length of the packet frame as it was sent over the
+/// wire including the header
+///
+/// `packet` The packet to get the length of
+fn frame_length(packet: &Packet) -> usize {
+    let length = packet.contents.len();
+    // Extended length headers use two extra bytes
+    let header = if length > 0xFFFF { 14 } else { 12 };
+    header + length
+}
@@ -1,130 +1,1069 @@
 //! Module for the Redirector server which handles redirecting the clients
 //! to the correct address for the main server.
 
-use blaze_pk::packet::{Packet, PacketDebug};
-use log::{debug, error, info};
-use std::sync::Arc;
+use blaze_pk::packet::{Packet, PacketComponents, PacketDebug, PacketHeader, PacketType};
+use bytes::{Buf, BytesMut};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
 use tokio::{
-    io::{split, AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf},
-    net::TcpListener,
-    sync::mpsc,
+    io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, WriteHalf},
+    net::{TcpListener, TcpStream},
+    select,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+    time::{self, Instant},
 };
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info_span, Instrument};
 
-use crate::{components::Components, retriever::Retriever, MAIN_PORT};
+use crate::{
+    components::{Components, Util},
+    config::{BufferConfig, Config, FlushStrategy, HeartbeatConfig, SessionsConfig, WhenFull},
+    events::{self, CloseReason, Event, SessionEvent},
+    intercept::{InterceptorRegistry, PacketSequences, SessionInfo, SessionInterceptors},
+    login::{self, LoginStage, LoginTracker},
+    metrics::METRICS,
+    ratelimit::{ClientLimits, PacketLimit},
+    replay::SessionRecorder,
+    retriever::Retriever,
+    stream::UpstreamStream,
+};
 
 /// Starts the MITM server. This server is responsible for creating a sort of
 /// proxy between this server and the official servers. All packets send and
 /// recieved by this server are forwarded to the official servers and are logged
 /// using the debug logging.
-pub async fn start_server(retriever: Arc<Retriever>) {
-    // Initializing the underlying TCP listener
-    let listener = {
-        let port = MAIN_PORT;
-        match TcpListener::bind(("0.0.0.0", port)).await {
-            Ok(value) => {
-                info!("Started MITM server (Port: {})", port);
-                value
-            }
-            Err(_) => {
-                error!("Failed to bind MITM server (Port: {})", port);
-                panic!()
-            }
-        }
+///
+/// `listener`     The listener bound to the configured address
+/// `config`       The server configuration
+/// `retriever`    The retriever for connecting to the official server
+/// `interceptors` The interceptors for the proxied packets
+/// `limits`       The optional rate limits for clients
+/// `shutdown`     Token cancelled when the server is shutting down
+/// `sessions`     Tracker for the spawned session tasks
+/// `force_close`  Token cancelled to close the sessions still active
+///                after the shutdown grace period
+#[allow(clippy::too_many_arguments)]
+pub async fn start_server(
+    listener: TcpListener,
+    config: Arc<Config>,
+    retriever: Arc<Retriever>,
+    interceptors: Arc<InterceptorRegistry>,
+    limits: Option<Arc<ClientLimits>>,
+    shutdown: CancellationToken,
+    sessions: TaskTracker,
+    force_close: CancellationToken,
+) {
+    info!("Started MITM server (Addr: {})", config.mitm.bind);
+
+    let slots = match config.sessions.max_concurrent {
+        0 => None,
+        max => Some(Arc::new(Semaphore::new(max))),
     };
 
     // Accept incoming connections
     loop {
-        let (stream, _) = match listener.accept().await {
+        let accept = select! {
+            _ = shutdown.cancelled() => break,
+            accept = listener.accept() => accept,
+        };
+        let (stream, addr) = match accept {
             Ok(value) => value,
             Err(err) => {
                 error!("Failed to accept MITM connection: {err:?}");
                 continue;
             }
         };
-        let retriever = retriever.clone();
-        tokio::spawn(async move {
-            let server = match retriever.stream().await {
-                Some(stream) => stream,
-                None => {
-                    error!("MITM unable to connect to official server");
-                    return;
+        if !config.access.allow_connection(addr.ip()) {
+            continue;
+        }
+        config.sockets.apply(&stream);
+        if let Some(limits) = &limits {
+            if !limits.allow_connection(addr.ip()) {
+                continue;
+            }
+        }
+        let slot = match &slots {
+            Some(slots) => {
+                let slot = select! {
+                    _ = shutdown.cancelled() => break,
+                    slot = acquire_slot(slots.clone(), &config.sessions) => slot,
+                };
+                let Some(slot) = slot else {
+                    warn!(
+                        "Session limit reached, rejecting connection (Addr: {})",
+                        addr.ip()
+                    );
+                    METRICS.session_rejected();
+                    continue;
+                };
+                Some(slot)
+            }
+            None => None,
+        };
+        let id = next_session_id();
+        let session = Session {
+            id,
+            addr,
+            _slot: slot,
+            retriever: retriever.clone(),
+            interceptors: SessionInterceptors::new(
+                interceptors.clone(),
+                SessionInfo {
+                    id,
+                    addr,
+                    captured: config.capture.captures(addr.ip()),
+                    sequences: config
+                        .capture
+                        .enabled
+                        .then(|| Arc::new(PacketSequences::default())),
+                },
+            ),
+            recorder: SessionRecorder::start(&config.record).map(Arc::new),
+            limit: limits
+                .as_ref()
+                .map(|limits| PacketLimit::new(limits.clone(), addr.ip())),
+            heartbeat: config.retriever.heartbeat.clone(),
+            buffers: config.buffers,
+            flush: config.flush,
+            login_timeout: config.login.timeout(),
+            idle_timeout: config.sessions.idle_timeout(),
+            max_packet_size: config.decode.max_packet_size,
+            shutdown: shutdown.clone(),
+            force_close: force_close.child_token(),
+        };
+        let span = info_span!("session", id, addr = %addr.ip());
+        sessions.spawn(session.run(stream).instrument(span));
+    }
+
+    info!("Stopped accepting MITM connections");
+}
+
+/// Acquires a session slot from the provided slots. Depending on the
+/// configuration this either waits for a slot to become free or fails
+/// immediately when there are no free slots
+///
+/// `slots`  The semaphore for the session slots
+/// `config` The session limit configuration
+async fn acquire_slot(
+    slots: Arc<Semaphore>,
+    config: &SessionsConfig,
+) -> Option<OwnedSemaphorePermit> {
+    match config.when_full {
+        WhenFull::Reject => slots.try_acquire_owned().ok(),
+        WhenFull::Wait => time::timeout(config.wait(), slots.acquire_owned())
+            .await
+            .ok()?
+            .ok(),
+    }
+}
+
+/// Returns a new unique identifier for a session
+fn next_session_id() -> u64 {
+    static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// State shared by the tasks of a single proxy session
+struct Session {
+    /// The unique identifier of the session
+    id: u64,
+    /// The address of the client
+    addr: SocketAddr,
+    /// The session slot which is released when the session ends
+    _slot: Option<OwnedSemaphorePermit>,
+    /// The retriever for connecting to the official server
+    retriever: Arc<Retriever>,
+    /// The interceptors for the proxied packets
+    interceptors: SessionInterceptors,
+    /// The optional recorder for the session
+    recorder: Option<Arc<SessionRecorder>>,
+    /// The optional packet rate limit for the client
+    limit: Option<PacketLimit>,
+    /// Heartbeats sent over the idle official server connection
+    heartbeat: HeartbeatConfig,
+    /// The packet buffer limits for each direction
+    buffers: BufferConfig,
+    /// When the packets written to each connection are flushed
+    flush: FlushStrategy,
+    /// The deadline for establishing the official server connection
+    login_timeout: Duration,
+    /// The time without packets in either direction before the
+    /// session is closed
+    idle_timeout: Option<Duration>,
+    /// The maximum length of packet contents that will be read
+    max_packet_size: usize,
+    /// Token cancelled when the server is shutting down
+    shutdown: CancellationToken,
+    /// Token cancelled to close the session along with the tasks
+    /// reading and writing its connections
+    force_close: CancellationToken,
+}
+
+impl Session {
+    /// Proxies the provided client connection to the official server
+    /// until either side disconnects, the server shuts down or the
+    /// session is force closed
+    ///
+    /// `stream` The client connection
+    async fn run(self, stream: TcpStream) {
+        let _session = METRICS.session_started();
+        let reason = select! {
+            reason = self.proxy(stream) => reason,
+            _ = self.force_close.cancelled() => {
+                warn!("Force closing session");
+                CloseReason::Shutdown
+            }
+        };
+        METRICS.session_closed(reason);
+        let packets = self
+            .interceptors
+            .session()
+            .sequences
+            .as_ref()
+            .map(|sequences| {
+                (
+                    sequences.current(Direction::ClientToServer),
+                    sequences.current(Direction::ServerToClient),
+                )
+            });
+        events::emit(Event::Session(SessionEvent::Closed {
+            time: SystemTime::now(),
+            session: self.id,
+            client: self.addr,
+            reason,
+            packets,
+        }));
+    }
+
+    /// Connects to the official server and proxies the client connection
+    /// returning the reason that the session ended
+    ///
+    /// `stream` The client connection
+    async fn proxy(&self, stream: TcpStream) -> CloseReason {
+        let tracker = LoginTracker::new(LoginStage::ConnectingUpstream);
+        let connect = login::with_deadline(self.login_timeout, &tracker, self.retriever.stream());
+        let server = select! {
+            _ = self.shutdown.cancelled() => return CloseReason::Shutdown,
+            server = connect => server,
+        };
+        let server = match server {
+            Some(Ok(value)) => value,
+            None => return CloseReason::Error,
+            Some(Err(err)) => {
+                error!("MITM unable to connect to official server: {err}");
+                return CloseReason::Error;
+            }
+        };
+
+        let (client_reader, client_writer) = split(stream);
+        let client_writer = Writer::start(
+            client_writer,
+            self.buffers.capacity(Direction::ServerToClient),
+            self.flush,
+            self.force_close.clone(),
+        );
+
+        let (server_tx, server_rx) =
+            mpsc::channel(self.buffers.capacity(Direction::ClientToServer));
+        let mut client_reader = Reader::new(
+            client_reader,
+            WriterAddr(server_tx),
+            Direction::ClientToServer,
+            self.interceptors.clone(),
+        )
+        .recorder(self.recorder.clone())
+        .limit(self.limit.clone())
+        .max_packet_size(self.max_packet_size)
+        .cancel(self.force_close.clone())
+        .spawn();
+
+        let reason = self
+            .upstream(server, server_rx, client_writer, &mut client_reader)
+            .await;
+
+        // Stop reading from the client so the connection is closed
+        // once the remaining packets have been written
+        client_reader.abort();
+        reason
+    }
+
+    /// Handles the connection to the official server for a session. Packets
+    /// from the client are written to the official server and packets from the
+    /// official server are forwarded to the client. If the connection to the
+    /// official server is lost then it is re-established using the retriever.
+    ///
+    /// When heartbeats are enabled a ping is sent to the official server
+    /// after each idle interval and the connection is considered lost once
+    /// too many of them go unanswered
+    ///
+    /// When an idle timeout is set the session is closed once no packets
+    /// have been received from either side for the timeout, heartbeats
+    /// and their responses don't count as activity
+    ///
+    /// Packets from the client are only received once the previous packet
+    /// has been written so the client reader is paused while the official
+    /// server is slow to accept writes
+    ///
+    /// The official server connection is shut down as soon as the client
+    /// disconnects, including while reconnecting to the official server
+    ///
+    /// Packets for components with a component route are written to the
+    /// upstream of the route instead, these connections are opened by the
+    /// first routed packet and kept across official server reconnects.
+    /// The session is closed if a routed upstream can't be written to
+    ///
+    /// `server`        The initial connection to the official server
+    /// `rx`            Receiver for packets sent by the client
+    /// `client`        The writer for the client connection
+    /// `client_reader` The task reading from the client connection
+    async fn upstream(
+        &self,
+        mut server: UpstreamStream,
+        mut rx: mpsc::Receiver<Packet>,
+        client: WriterAddr,
+        client_reader: &mut JoinHandle<Result<(), PacketTooLarge>>,
+    ) -> CloseReason {
+        let mut backoff = self.retriever.backoff();
+        backoff.connected();
+        let mut header = Vec::with_capacity(MAX_HEADER_LENGTH);
+        let idle_timeout = self.idle_timeout.unwrap_or_default();
+        let mut client_active = Instant::now();
+        let mut routed = RoutedUpstreams::default();
+        loop {
+            events::emit(Event::Session(SessionEvent::ProxyStarted {
+                time: SystemTime::now(),
+                session: self.id,
+                client: self.addr,
+                upstream: self.retriever.target(),
+            }));
+            let (server_reader, server_writer) = split(server);
+            let mut server_writer =
+                BufWriter::with_capacity(self.flush.buffer_capacity(), server_writer);
+            let mut batch = FlushBatch::new(self.flush);
+            let heartbeat = Arc::new(Heartbeat::default());
+            let mut reader = Reader::new(
+                server_reader,
+                client.clone(),
+                Direction::ServerToClient,
+                self.interceptors.clone(),
+            )
+            .recorder(self.recorder.clone())
+            .heartbeat(heartbeat.clone())
+            .max_packet_size(self.max_packet_size)
+            .cancel(self.force_close.clone())
+            .spawn();
+
+            let interval = self.heartbeat.interval();
+            let mut idle_until = Instant::now() + interval;
+            let mut idle_deadline = Instant::now() + idle_timeout;
+            let reason = loop {
+                let packet = select! {
+                    _ = self.shutdown.cancelled() => break CloseReason::Shutdown,
+                    result = &mut reader => match result {
+                        // Oversized packets close the session without reconnecting
+                        Ok(Err(_)) => return CloseReason::Error,
+                        _ => break CloseReason::UpstreamClosed,
+                    },
+                    _ = time::sleep_until(idle_until), if self.heartbeat.enabled => {
+                        let missed = heartbeat.sent();
+                        if missed > self.heartbeat.max_missed {
+                            warn!("Official server missed {} heartbeats", missed - 1);
+                            break CloseReason::Error;
+                        }
+                        debug!("Sending heartbeat to official server");
+                        Packet::request_empty(HEARTBEAT_ID, Components::Util(Util::Ping))
+                    }
+                    _ = time::sleep_until(idle_deadline), if self.idle_timeout.is_some() => {
+                        let last_active = client_active.max(heartbeat.last_active());
+                        idle_deadline = last_active + idle_timeout;
+                        if idle_deadline <= Instant::now() {
+                            break CloseReason::IdleTimeout;
+                        }
+                        continue;
+                    }
+                    _ = time::sleep_until(batch.deadline()), if batch.is_pending() => {
+                        if let Err(err) = server_writer.flush().await {
+                            error!("Error while flushing to official server: {:?}", err);
+                            break CloseReason::Error;
+                        }
+                        batch.flushed();
+                        continue;
+                    }
+                    packet = rx.recv() => match packet {
+                        Some(packet) => {
+                            client_active = Instant::now();
+                            packet
+                        }
+                        None => break CloseReason::ClientClosed,
+                    },
+                };
+                idle_until = Instant::now() + interval;
+                if let Some(route) = self.retriever.component_route(packet.header.component) {
+                    let written = match routed.writer(route, self, &client).await {
+                        Some(writer) => match write_packet(writer, &packet, &mut header).await {
+                            Ok(()) => writer.flush().await,
+                            Err(err) => Err(err),
+                        },
+                        None => {
+                            reader.abort();
+                            return CloseReason::Error;
+                        }
+                    };
+                    if let Err(err) = written {
+                        error!("Error while writing to routed upstream: {:?}", err);
+                        reader.abort();
+                        return CloseReason::Error;
+                    }
+                    continue;
+                }
+                if let Err(err) = write_packet(&mut server_writer, &packet, &mut header).await {
+                    error!("Error while writing to official server: {:?}", err);
+                    break CloseReason::Error;
                 }
+                if !batch.written(header.len() + packet.contents.len()) {
+                    continue;
+                }
+                if let Err(err) = server_writer.flush().await {
+                    error!("Error while flushing to official server: {:?}", err);
+                    break CloseReason::Error;
+                }
+                batch.flushed();
             };
+            reader.abort();
 
-            let (client_reader, client_writer) = split(stream);
-            let client_writer = Writer::start(client_writer);
+            // Client disconnected or the server is shutting down so
+            // the session is complete
+            if matches!(
+                reason,
+                CloseReason::ClientClosed | CloseReason::Shutdown | CloseReason::IdleTimeout
+            ) {
+                match reason {
+                    CloseReason::ClientClosed => {
+                        info!("Client disconnected, closing official server connection")
+                    }
+                    CloseReason::IdleTimeout => info!(
+                        "Session idle for {} seconds, closing connections",
+                        idle_timeout.as_secs()
+                    ),
+                    _ => {}
+                }
+                if batch.is_pending() {
+                    server_writer.flush().await.ok();
+                    batch.flushed();
+                }
+                server_writer.shutdown().await.ok();
+                return reason;
+            }
 
-            let (server_reader, server_writer) = split(server);
-            let server_writer = Writer::start(server_writer);
+            error!("Lost connection to official server");
+            let reconnect = select! {
+                _ = self.shutdown.cancelled() => return CloseReason::Shutdown,
+                _ = &mut *client_reader => {
+                    info!("Client disconnected while reconnecting to official server");
+                    return CloseReason::ClientClosed;
+                }
+                reconnect = self.retriever.reconnect(&mut backoff) => reconnect,
+            };
+            server = match reconnect {
+                Some(value) => value,
+                None => return reason,
+            };
+        }
+    }
+}
 
-            Reader::spawn(client_reader, server_writer, "Client");
-            Reader::spawn(server_reader, client_writer, "Server");
+/// Connection to the upstream of a component route
+struct RoutedUpstream {
+    /// The index of the route
+    route: usize,
+    /// The writer for the upstream connection
+    writer: WriteHalf<UpstreamStream>,
+    /// The task reading from the upstream connection
+    reader: JoinHandle<Result<(), PacketTooLarge>>,
+}
+
+/// The connections to the upstreams of the component routes for a
+/// session. Their readers are stopped when this is dropped
+#[derive(Default)]
+struct RoutedUpstreams(Vec<RoutedUpstream>);
+
+impl RoutedUpstreams {
+    /// Returns the writer for the upstream of the provided route opening
+    /// a connection to it if there isn't one. Packets from the upstream
+    /// are forwarded to the client
+    ///
+    /// `route`   The index of the route
+    /// `session` The session the connection is for
+    /// `client`  The writer for the client connection
+    async fn writer(
+        &mut self,
+        route: usize,
+        session: &Session,
+        client: &WriterAddr,
+    ) -> Option<&mut WriteHalf<UpstreamStream>> {
+        if let Some(index) = self.0.iter().position(|value| value.route == route) {
+            return Some(&mut self.0[index].writer);
+        }
+        let stream = match session.retriever.route_stream(route).await {
+            Ok(value) => value,
+            Err(err) => {
+                error!("MITM unable to connect to routed upstream: {err}");
+                return None;
+            }
+        };
+        debug!("Connected to routed upstream (Route: {route})");
+        let (reader, writer) = split(stream);
+        let reader = Reader::new(
+            reader,
+            client.clone(),
+            Direction::ServerToClient,
+            session.interceptors.clone(),
+        )
+        .recorder(session.recorder.clone())
+        .max_packet_size(session.max_packet_size)
+        .cancel(session.force_close.clone())
+        .spawn();
+        self.0.push(RoutedUpstream {
+            route,
+            writer,
+            reader,
         });
+        self.0.last_mut().map(|value| &mut value.writer)
+    }
+}
+
+impl Drop for RoutedUpstreams {
+    fn drop(&mut self) {
+        for upstream in &self.0 {
+            upstream.reader.abort();
+        }
+    }
+}
+
+/// The direction that a proxied packet is travelling in
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Direction {
+    /// Packet sent from the client to the official server
+    ClientToServer,
+    /// Packet sent from the official server to the client
+    ServerToClient,
+}
+
+impl Direction {
+    /// Returns the direction byte used for packet captures
+    pub fn value(&self) -> u8 {
+        match self {
+            Self::ClientToServer => 0,
+            Self::ServerToClient => 1,
+        }
+    }
+
+    /// Returns the direction for the provided capture direction byte
+    ///
+    /// `value` The direction byte
+    pub fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::ClientToServer),
+            1 => Some(Self::ServerToClient),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the side that sent the packet
+    pub fn side(&self) -> &'static str {
+        match self {
+            Self::ClientToServer => "Client",
+            Self::ServerToClient => "Server",
+        }
     }
 }
 
 /// Writer for writing packets to a connection
-struct Writer<W> {
-    rx: mpsc::UnboundedReceiver<Packet>,
-    write: W,
+pub struct Writer<W> {
+    rx: mpsc::Receiver<Packet>,
+    /// The connection buffered by the flush strategy
+    write: BufWriter<W>,
+    /// Buffer reused for encoding the packet headers
+    header: Vec<u8>,
+    /// The packets written since the connection was last flushed
+    batch: FlushBatch,
 }
 
 impl<W> Writer<W>
 where
     W: AsyncWrite + Unpin + Send + 'static,
 {
-    pub fn start(write: W) -> WriterAddr {
-        let (tx, rx) = mpsc::unbounded_channel();
-        let writer = Writer { rx, write };
-        tokio::spawn(writer.process());
+    /// Starts a writer for the provided connection which buffers at
+    /// most `capacity` packets before senders have to wait
+    ///
+    /// `write`    The connection to write to
+    /// `capacity` The maximum number of buffered packets
+    /// `flush`    When the written packets are flushed
+    /// `cancel`   Token that stops the writer without writing the
+    ///            remaining packets when cancelled
+    pub fn start(
+        write: W,
+        capacity: usize,
+        flush: FlushStrategy,
+        cancel: CancellationToken,
+    ) -> WriterAddr {
+        let (tx, rx) = mpsc::channel(capacity);
+        let writer = Writer {
+            rx,
+            write: BufWriter::with_capacity(flush.buffer_capacity(), write),
+            header: Vec::with_capacity(MAX_HEADER_LENGTH),
+            batch: FlushBatch::new(flush),
+        };
+        tokio::spawn(
+            async move { cancel.run_until_cancelled(writer.process()).await }.in_current_span(),
+        );
         WriterAddr(tx)
     }
 
+    /// Writes packets until all the senders have been dropped and then
+    /// shuts down the connection so the other side sees it close
+    /// straight away
     pub async fn process(mut self) {
-        while let Some(packet) = self.rx.recv().await {
-            if let Err(err) = packet.write_async(&mut self.write).await {
+        loop {
+            let packet = select! {
+                packet = self.rx.recv() => packet,
+                _ = time::sleep_until(self.batch.deadline()), if self.batch.is_pending() => {
+                    self.flush().await;
+                    continue;
+                }
+            };
+            let Some(packet) = packet else { break };
+            if let Err(err) = write_packet(&mut self.write, &packet, &mut self.header).await {
                 error!("Error while write: {:?}", err)
             }
-            if let Err(err) = self.write.flush().await {
-                error!("Error while flushing: {:?}", err);
+            if self
+                .batch
+                .written(self.header.len() + packet.contents.len())
+            {
+                self.flush().await;
             }
         }
+        if self.batch.is_pending() {
+            self.flush().await;
+        }
+        self.write.shutdown().await.ok();
+    }
+
+    /// Flushes the packets written to the connection
+    async fn flush(&mut self) {
+        if let Err(err) = self.write.flush().await {
+            error!("Error while flushing: {:?}", err);
+        }
+        self.batch.flushed();
     }
 }
 
+/// The packets written to a connection since it was last flushed which
+/// decides when the connection is flushed based on the flush strategy
+struct FlushBatch {
+    /// The strategy deciding when to flush
+    strategy: FlushStrategy,
+    /// The number of packets written since the last flush
+    packets: u64,
+    /// The number of bytes written since the last flush
+    bytes: usize,
+    /// The time the written packets must be flushed by
+    deadline: Instant,
+}
+
+impl FlushBatch {
+    /// Creates an empty batch for the provided strategy
+    ///
+    /// `strategy` The strategy deciding when to flush
+    fn new(strategy: FlushStrategy) -> Self {
+        Self {
+            strategy,
+            packets: 0,
+            bytes: 0,
+            deadline: Instant::now(),
+        }
+    }
+
+    /// Records a written packet returning whether the connection should
+    /// be flushed straight away
+    ///
+    /// `bytes` The number of bytes written for the packet
+    fn written(&mut self, bytes: usize) -> bool {
+        let FlushStrategy::Coalesce {
+            max_delay_ms,
+            max_bytes,
+        } = self.strategy
+        else {
+            return true;
+        };
+        if self.packets == 0 {
+            self.deadline = Instant::now() + Duration::from_millis(max_delay_ms);
+        }
+        self.packets += 1;
+        self.bytes += bytes;
+        self.bytes >= max_bytes
+    }
+
+    /// Returns whether there are written packets waiting to be flushed
+    fn is_pending(&self) -> bool {
+        self.packets > 0
+    }
+
+    /// Returns the time the written packets must be flushed by
+    fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Records a flush of the written packets
+    fn flushed(&mut self) {
+        if self.packets > 0 {
+            METRICS.flush_batch(self.packets);
+        }
+        self.packets = 0;
+        self.bytes = 0;
+    }
+}
+
+/// The packet ID used for heartbeat pings. This is the highest ID so that
+/// it doesn't collide with the IDs used by the client
+const HEARTBEAT_ID: u16 = u16::MAX;
+
+/// Tracks the heartbeat pings sent over an official server connection
+/// that haven't been answered yet and when the official server last
+/// sent a packet that wasn't a heartbeat response
+pub struct Heartbeat {
+    /// The number of unanswered heartbeats
+    missed: AtomicU32,
+    /// The time the connection started
+    started: Instant,
+    /// Milliseconds after the start that the last packet was received
+    last_active_ms: AtomicU64,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            missed: AtomicU32::new(0),
+            started: Instant::now(),
+            last_active_ms: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Returns when the official server last sent a packet that wasn't
+    /// a heartbeat response, or when the connection started if it hasn't
+    fn last_active(&self) -> Instant {
+        self.started + Duration::from_millis(self.last_active_ms.load(Ordering::Relaxed))
+    }
+
+    /// Records a sent heartbeat returning the number of heartbeats
+    /// that are now unanswered
+    fn sent(&self) -> u32 {
+        self.missed.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Checks whether the provided packet is a response to a heartbeat
+    /// resetting the unanswered heartbeats if it is
+    ///
+    /// `component` The component of the packet
+    /// `packet`    The packet to check
+    fn acknowledge(&self, component: &Components, packet: &Packet) -> bool {
+        let header = &packet.header;
+        let is_response = *component == Components::Util(Util::Ping)
+            && header.id == HEARTBEAT_ID
+            && matches!(header.ty, PacketType::Response | PacketType::Error);
+        if is_response {
+            self.missed.store(0, Ordering::Relaxed);
+        } else {
+            let elapsed = self.started.elapsed().as_millis() as u64;
+            self.last_active_ms.store(elapsed, Ordering::Relaxed);
+        }
+        is_response
+    }
+}
+
+/// Error for a packet whose header claims a length larger than the
+/// maximum packet size
+#[derive(Debug, Clone, Copy)]
+pub struct PacketTooLarge {
+    /// The length claimed by the packet header
+    length: usize,
+    /// The maximum packet size
+    max: usize,
+}
+
+impl Display for PacketTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Packet length {} exceeds the maximum of {}",
+            self.length, self.max
+        )
+    }
+}
+
+impl Error for PacketTooLarge {}
+
+/// The length of the largest packet header (headers of packets with
+/// extended lengths)
+const MAX_HEADER_LENGTH: usize = 14;
+
+/// The initial capacity of the buffers packet contents are read into.
+/// Replacement allocations are at least this size so that the contents
+/// of small packets share allocations
+const READ_BUFFER_CAPACITY: usize = 8 * 1024;
+
+/// Reads a packet from the provided input rejecting packets which are
+/// longer than the maximum packet size before allocating their contents
+///
+/// `input` The input to read from
+/// `max`   The maximum length of packet contents in bytes
+pub async fn read_packet<R: AsyncRead + Unpin>(input: &mut R, max: usize) -> io::Result<Packet> {
+    read_packet_buf(input, &mut BytesMut::new(), max).await
+}
+
+/// Reads a packet from the provided input into the provided buffer. The
+/// contents are split off from the buffer without copying so that the
+/// allocation is reused once the previously read packets are dropped
+///
+/// `input`  The input to read from
+/// `buffer` The buffer to read the contents into
+/// `max`    The maximum length of packet contents in bytes
+pub async fn read_packet_buf<R: AsyncRead + Unpin>(
+    input: &mut R,
+    buffer: &mut BytesMut,
+    max: usize,
+) -> io::Result<Packet> {
+    let (header, length) = PacketHeader::read_async(input).await?;
+    if length > max {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            PacketTooLarge { length, max },
+        ));
+    }
+    buffer.clear();
+    buffer.resize(length, 0);
+    input.read_exact(buffer).await?;
+    Ok(Packet {
+        header,
+        contents: buffer.split().freeze(),
+    })
+}
+
+/// Writes the provided packet to the output. The encoded header is
+/// chained with the contents so they are written without being copied
+///
+/// `output` The output to write to
+/// `packet` The packet to write
+/// `header` Buffer reused for encoding the header
+async fn write_packet<W: AsyncWrite + Unpin>(
+    output: &mut W,
+    packet: &Packet,
+    header: &mut Vec<u8>,
+) -> io::Result<()> {
+    header.clear();
+    packet.header.write_bytes(header, packet.contents.len());
+    let mut buf = Buf::chain(header.as_slice(), packet.contents.as_ref());
+    output.write_all_buf(&mut buf).await
+}
+
 #[derive(Clone)]
-struct WriterAddr(mpsc::UnboundedSender<Packet>);
+pub struct WriterAddr(mpsc::Sender<Packet>);
+
+impl WriterAddr {
+    /// Sends the provided packet to the writer waiting for space in the
+    /// buffer when it is full. Returns false if the writer has stopped
+    ///
+    /// `packet` The packet to send
+    async fn send(&self, packet: Packet) -> bool {
+        let packet = match self.0.try_send(packet) {
+            Ok(()) => return true,
+            Err(TrySendError::Closed(_)) => return false,
+            Err(TrySendError::Full(packet)) => packet,
+        };
+        METRICS.backpressure();
+        self.0.send(packet).await.is_ok()
+    }
+}
 
-struct Reader<R> {
+pub struct Reader<R> {
     /// Reader to read the packets from
     read: R,
     /// Writer to send the packets to
     writer: WriterAddr,
-
-    side: &'static str,
+    /// The direction of the packets being read
+    direction: Direction,
+    /// The interceptors for the packets
+    interceptors: SessionInterceptors,
+    /// The optional recorder for the session
+    recorder: Option<Arc<SessionRecorder>>,
+    /// The optional rate limit for the packets
+    limit: Option<PacketLimit>,
+    /// The optional heartbeat tracker whose responses are consumed
+    heartbeat: Option<Arc<Heartbeat>>,
+    /// Buffer the packet contents are read into
+    buffer: BytesMut,
+    /// The maximum length of packet contents that will be read
+    max_packet_size: usize,
+    /// Token that stops the reader when cancelled
+    cancel: CancellationToken,
 }
 
 impl<R> Reader<R>
 where
     R: AsyncRead + Unpin + Send + 'static,
 {
-    pub fn spawn(read: R, writer: WriterAddr, side: &'static str) {
-        let reader = Reader { read, writer, side };
-        tokio::spawn(reader.process());
+    /// Creates a reader forwarding the packets read from `read` to the
+    /// provided writer without a recorder, rate limit or heartbeat and
+    /// without a maximum packet size. The reader runs until the connection
+    /// is closed unless a cancel token is set
+    ///
+    /// `read`         Reader to read the packets from
+    /// `writer`       Writer to send the packets to
+    /// `direction`    The direction of the packets being read
+    /// `interceptors` The interceptors for the packets
+    pub fn new(
+        read: R,
+        writer: WriterAddr,
+        direction: Direction,
+        interceptors: SessionInterceptors,
+    ) -> Self {
+        Reader {
+            read,
+            writer,
+            direction,
+            interceptors,
+            recorder: None,
+            limit: None,
+            heartbeat: None,
+            buffer: BytesMut::with_capacity(READ_BUFFER_CAPACITY),
+            max_packet_size: usize::MAX,
+            cancel: CancellationToken::new(),
+        }
     }
 
-    pub async fn process(mut self) {
+    /// Sets the optional recorder the packets are recorded to
+    ///
+    /// `recorder` The optional recorder for the session
+    pub fn recorder(mut self, recorder: Option<Arc<SessionRecorder>>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Sets the optional rate limit for the packets
+    ///
+    /// `limit` The optional rate limit
+    pub fn limit(mut self, limit: Option<PacketLimit>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Sets the heartbeat tracker whose responses are consumed
+    ///
+    /// `heartbeat` The heartbeat tracker
+    pub fn heartbeat(mut self, heartbeat: Arc<Heartbeat>) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// Sets the maximum length of packet contents that will be read,
+    /// longer packets close the connection
+    ///
+    /// `max` The maximum length in bytes
+    pub fn max_packet_size(mut self, max: usize) -> Self {
+        self.max_packet_size = max;
+        self
+    }
+
+    /// Sets the token that stops the reader when cancelled
+    ///
+    /// `cancel` The token stopping the reader
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Spawns a task reading and forwarding packets until the connection
+    /// is closed or the cancel token is cancelled
+    pub fn spawn(self) -> JoinHandle<Result<(), PacketTooLarge>> {
+        let cancel = self.cancel.clone();
+        tokio::spawn(
+            async move {
+                cancel
+                    .run_until_cancelled(self.process())
+                    .await
+                    .unwrap_or(Ok(()))
+            }
+            .in_current_span(),
+        )
+    }
+
+    /// Reads and forwards packets until the connection is closed. An
+    /// error is returned if the connection sent an oversized packet
+    pub async fn process(mut self) -> Result<(), PacketTooLarge> {
         loop {
-            let (component, packet) =
-                match Packet::read_async_typed::<Components, R>(&mut self.read).await {
+            let packet =
+                match read_packet_buf(&mut self.read, &mut self.buffer, self.max_packet_size).await
+                {
                     Ok(value) => value,
+                    Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                        debug!("{} connection closed", self.direction.side());
+                        return Ok(());
+                    }
                     Err(err) => {
-                        error!("Error while reading: {:?}", err);
-                        break;
+                        let too_large = err
+                            .get_ref()
+                            .and_then(|err| err.downcast_ref::<PacketTooLarge>());
+                        if let Some(too_large) = too_large {
+                            warn!(
+                                "Dropping oversized packet and closing {} connection: {too_large}",
+                                self.direction.side()
+                            );
+                            return Err(*too_large);
+                        }
+                        error!(
+                            "Error while reading, {} connection lost: {err}",
+                            self.direction.side()
+                        );
+                        return Ok(());
                     }
                 };
-            debug_log_packet(&component, &packet, self.side);
-            self.writer.0.send(packet).ok();
+            let component = Components::from_header(&packet.header);
+            if let Some(limit) = &self.limit {
+                limit.wait().await;
+            }
+            if let Some(heartbeat) = &self.heartbeat {
+                if heartbeat.acknowledge(&component, &packet) {
+                    continue;
+                }
+            }
+            debug_log_packet(&component, &packet, self.direction.side());
+            if let Some(recorder) = &self.recorder {
+                recorder.record(self.direction, &packet);
+            }
+            let Some(packet) = self.interceptors.process(self.direction, packet).await else {
+                continue;
+            };
+            METRICS.packet_forwarded(self.direction, &packet);
+            if !self.writer.send(packet).await {
+                return Ok(());
+            }
         }
     }
 }
@@ -143,3 +1082,119 @@ fn debug_log_packet(component: &Components, packet: &Packet, side: &str) {
     };
     debug!("\nRecieved Packet From {}\n{:?}", side, debug);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        pin::Pin,
+        sync::Mutex,
+        task::{Context, Poll},
+    };
+
+    /// Connection counting the socket writes made to it
+    #[derive(Clone, Default)]
+    struct CountingWrite {
+        /// The number of writes made
+        writes: Arc<AtomicU64>,
+        /// The bytes that were written
+        bytes: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl AsyncWrite for CountingWrite {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            self.bytes.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Writes the provided number of ping packets through a writer using
+    /// the provided strategy returning the counted connection along with
+    /// the encoded packets
+    ///
+    /// `flush`   The flush strategy of the writer
+    /// `packets` The number of packets to write
+    async fn write_pings(flush: FlushStrategy, packets: u16) -> (CountingWrite, Vec<u8>) {
+        let write = CountingWrite::default();
+        let writer = Writer::start(write.clone(), 16, flush, CancellationToken::new());
+        let mut expected = Vec::new();
+        for id in 0..packets {
+            let packet = Packet::request_raw(id, Components::Util(Util::Ping), vec![1; 16]);
+            expected.extend_from_slice(&packet.encode_bytes());
+            assert!(writer.send(packet).await);
+        }
+        drop(writer);
+        // Wait for the writer to stop once all the senders are dropped
+        while Arc::strong_count(&write.writes) > 1 {
+            time::sleep(Duration::from_millis(5)).await;
+        }
+        (write, expected)
+    }
+
+    /// Packets longer than the maximum packet size are rejected while
+    /// packets within it are read
+    #[tokio::test]
+    async fn test_read_packet_max_size() {
+        let packet = Packet::request_raw(1, Components::Util(Util::Ping), vec![0; 64]);
+        let bytes = packet.encode_bytes();
+
+        let read = read_packet(&mut bytes.as_slice(), 64).await.unwrap();
+        assert_eq!(read.contents.len(), 64);
+
+        let err = read_packet(&mut bytes.as_slice(), 63).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<PacketTooLarge>())
+            .is_some());
+    }
+
+    /// Cancelling a writer stuck writing to a connection that isn't
+    /// being read closes the connection
+    #[tokio::test]
+    async fn test_writer_cancel() {
+        let (write, mut read) = tokio::io::duplex(64);
+        let cancel = CancellationToken::new();
+        let writer = Writer::start(write, 8, FlushStrategy::Immediate, cancel.clone());
+        let packet = Packet::request_raw(1, Components::Util(Util::Ping), vec![0; 1024]);
+        assert!(writer.send(packet).await);
+
+        cancel.cancel();
+        let mut contents = Vec::new();
+        time::timeout(Duration::from_secs(5), read.read_to_end(&mut contents))
+            .await
+            .expect("Writer wasn't stopped")
+            .unwrap();
+        assert!(contents.len() < 1024);
+    }
+
+    /// Coalesced packets are written to the socket in a single write
+    /// while immediately flushed packets are each written separately
+    #[tokio::test]
+    async fn test_flush_socket_writes() {
+        let coalesce = FlushStrategy::Coalesce {
+            max_delay_ms: 1000,
+            max_bytes: 4096,
+        };
+        let (write, expected) = write_pings(coalesce, 8).await;
+        assert_eq!(write.writes.load(Ordering::Relaxed), 1);
+        assert_eq!(*write.bytes.lock().unwrap(), expected);
+
+        let (write, expected) = write_pings(FlushStrategy::Immediate, 8).await;
+        assert!(write.writes.load(Ordering::Relaxed) >= 8);
+        assert_eq!(*write.bytes.lock().unwrap(), expected);
+    }
+}
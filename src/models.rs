@@ -1,47 +1,503 @@
 use blaze_pk::{
     codec::{Decodable, Encodable},
-    error::DecodeResult,
+    error::{DecodeError, DecodeResult},
+    packet::Packet,
     reader::TdfReader,
-    tag::TdfType,
+    tag::{Tag, TdfType},
     writer::TdfWriter,
 };
-use blaze_pk::{types::Union, value_type};
+use blaze_pk::{types::UNION_UNSET, value_type};
+use log::{debug, warn};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
+    cmp,
+    error::Error,
     fmt::{Debug, Display},
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4},
+    ops::Range,
+    str::FromStr,
 };
 
 /// Packet encoding for Redirector GetServerInstance packets
 /// this contains basic information about the client session.
 ///
-/// These details are extracted from an official game copy
-pub struct InstanceRequest;
+/// The default values are extracted from an official game copy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceRequest {
+    /// The Blaze SDK version (BSDK)
+    pub sdk_version: String,
+    /// The Blaze SDK build time (BTIM)
+    pub build_time: String,
+    /// The name of the client (CLNT)
+    pub client: String,
+    /// The client type (CLTP)
+    pub client_type: u8,
+    /// The client platform (PLAT)
+    pub platform: String,
+    /// The client SKU (CSKU)
+    pub sku: String,
+    /// The client version (CVER)
+    pub version: String,
+    /// The DirtySDK version (DSDK)
+    pub dirty_sdk_version: String,
+    /// The server environment (ENV)
+    pub environment: Environment,
+    /// The client locale (LOC)
+    pub locale: Locale,
+    /// The service name (NAME)
+    pub service_name: String,
+    /// The connection profile (PROF)
+    pub profile: String,
+    /// The optional first party ID of the client (FPID)
+    pub first_party_id: Option<FirstPartyId>,
+}
+
+impl InstanceRequest {
+    /// Replaces the client name of the request
+    ///
+    /// `client` The new client name
+    pub fn with_client(mut self, client: impl Into<String>) -> Self {
+        self.client = client.into();
+        self
+    }
+
+    /// Replaces the platform of the request
+    ///
+    /// `platform` The new platform
+    pub fn with_platform(mut self, platform: impl Into<String>) -> Self {
+        self.platform = platform.into();
+        self
+    }
+
+    /// Replaces the SKU of the request
+    ///
+    /// `sku` The new SKU
+    pub fn with_sku(mut self, sku: impl Into<String>) -> Self {
+        self.sku = sku.into();
+        self
+    }
+
+    /// Replaces the client version of the request
+    ///
+    /// `version` The new client version
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// Replaces the locale of the request
+    ///
+    /// `locale` The new locale
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Replaces the server environment of the request
+    ///
+    /// `environment` The new server environment
+    pub fn with_environment(mut self, environment: Environment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Replaces the first party ID of the request
+    ///
+    /// `first_party_id` The new first party ID
+    pub fn with_first_party_id(mut self, first_party_id: FirstPartyId) -> Self {
+        self.first_party_id = Some(first_party_id);
+        self
+    }
+}
+
+/// Default request using the values from the Mass Effect 3 PC client
+impl Default for InstanceRequest {
+    fn default() -> Self {
+        IdentityProfile::ME3_PC.request()
+    }
+}
 
 impl Encodable for InstanceRequest {
     fn encode(&self, writer: &mut TdfWriter) {
-        writer.tag_str(b"BSDK", "3.15.6.0");
-        writer.tag_str(b"BTIM", "Dec 21 2012 12:47:10");
-        writer.tag_str(b"CLNT", "MassEffect3-pc");
-        writer.tag_u8(b"CLTP", 0);
-        writer.tag_str(b"CSKU", "134845");
-        writer.tag_str(b"CVER", "05427.124");
-        writer.tag_str(b"DSDK", "8.14.7.1");
-        writer.tag_str(b"ENV", "prod");
-        writer.tag_union_unset(b"FPID");
-        writer.tag_u32(b"LOC", 0x656e4e5a);
-        writer.tag_str(b"NAME", "masseffect-3-pc");
-        writer.tag_str(b"PLAT", "Windows");
-        writer.tag_str(b"PROF", "standardSecure_v3");
+        writer.tag_str(b"BSDK", &self.sdk_version);
+        writer.tag_str(b"BTIM", &self.build_time);
+        writer.tag_str(b"CLNT", &self.client);
+        writer.tag_u8(b"CLTP", self.client_type);
+        writer.tag_str(b"CSKU", &self.sku);
+        writer.tag_str(b"CVER", &self.version);
+        writer.tag_str(b"DSDK", &self.dirty_sdk_version);
+        writer.tag_str(b"ENV", self.environment.as_str());
+        match &self.first_party_id {
+            Some(value) => value.encode(writer),
+            None => writer.tag_union_unset(b"FPID"),
+        }
+        writer.tag_u32(b"LOC", self.locale.0);
+        writer.tag_str(b"NAME", &self.service_name);
+        writer.tag_str(b"PLAT", &self.platform);
+        writer.tag_str(b"PROF", &self.profile);
+    }
+}
+
+impl InstanceRequest {
+    /// Finds the name of the client (CLNT) within the provided packet
+    /// contents without decoding the rest of the request so that it is
+    /// known even for requests from unrecognized clients
+    ///
+    /// `contents` The packet contents
+    pub fn find_client(contents: &[u8]) -> DecodeResult<String> {
+        let mut reader = TdfReader::new(contents);
+        reader.until_tag("CLNT", TdfType::String)?;
+        String::decode(&mut reader)
+    }
+}
+
+impl Decodable for InstanceRequest {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let sdk_version: String = reader.tag("BSDK")?;
+        let build_time: String = reader.tag("BTIM")?;
+        let client: String = reader.tag("CLNT")?;
+        let client_type: u8 = reader.tag("CLTP")?;
+        let sku: String = reader.tag("CSKU")?;
+        let version: String = reader.tag("CVER")?;
+        let dirty_sdk_version: String = reader.tag("DSDK")?;
+        let environment: String = reader.tag("ENV")?;
+        let first_party_id = FirstPartyId::decode(reader)?;
+        let locale: u32 = reader.tag("LOC")?;
+        let service_name: String = reader.tag("NAME")?;
+        let platform: String = reader.tag("PLAT")?;
+        let profile: String = reader.tag("PROF")?;
+        Ok(Self {
+            sdk_version,
+            build_time,
+            client,
+            client_type,
+            platform,
+            sku,
+            version,
+            dirty_sdk_version,
+            environment: Environment::from(environment),
+            locale: Locale(locale),
+            service_name,
+            profile,
+            first_party_id,
+        })
+    }
+}
+
+/// The first party platform ID presented by console and Origin clients.
+/// The variant used determines the key of the FPID union
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FirstPartyId {
+    /// Xbox Live user ID (XUID)
+    Xbox(u64),
+    /// PlayStation Network account ID
+    Psn(u64),
+    /// Origin persona name
+    Origin(String),
+}
+
+impl FirstPartyId {
+    /// Union key for Xbox Live user IDs
+    const XBOX_KEY: u8 = 0x0;
+    /// Union key for PlayStation Network account IDs
+    const PSN_KEY: u8 = 0x1;
+    /// Union key for Origin persona names
+    const ORIGIN_KEY: u8 = 0x2;
+
+    /// Returns the union key for this ID
+    fn key(&self) -> u8 {
+        match self {
+            Self::Xbox(_) => Self::XBOX_KEY,
+            Self::Psn(_) => Self::PSN_KEY,
+            Self::Origin(_) => Self::ORIGIN_KEY,
+        }
+    }
+
+    /// Encodes this ID as the set FPID union
+    ///
+    /// `writer` The writer to encode to
+    fn encode(&self, writer: &mut TdfWriter) {
+        writer.tag_union_start(b"FPID", self.key());
+        match self {
+            Self::Xbox(value) | Self::Psn(value) => writer.tag_u64(b"VALU", *value),
+            Self::Origin(value) => writer.tag_str(b"VALU", value),
+        }
+    }
+
+    /// Decodes the FPID union returning None when it is unset or uses
+    /// a key that isn't known
+    ///
+    /// `reader` The reader to decode from
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Option<Self>> {
+        reader.until_tag("FPID", TdfType::Union)?;
+        let value = match reader.read_byte()? {
+            UNION_UNSET => None,
+            Self::XBOX_KEY => Some(Self::Xbox(reader.tag("VALU")?)),
+            Self::PSN_KEY => Some(Self::Psn(reader.tag("VALU")?)),
+            Self::ORIGIN_KEY => Some(Self::Origin(reader.tag("VALU")?)),
+            key => {
+                debug!("Skipping unknown first party ID type: {key}");
+                let Tag(_, value_type) = reader.read_tag()?;
+                reader.skip_type(&value_type)?;
+                None
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Named set of instance request values presented by a specific
+/// game build
+pub struct IdentityProfile {
+    /// The name used to select the profile
+    pub name: &'static str,
+    /// The Blaze SDK version (BSDK)
+    pub sdk_version: &'static str,
+    /// The Blaze SDK build time (BTIM)
+    pub build_time: &'static str,
+    /// The name of the client (CLNT)
+    pub client: &'static str,
+    /// The client type (CLTP)
+    pub client_type: u8,
+    /// The client SKU (CSKU)
+    pub sku: &'static str,
+    /// The client version (CVER)
+    pub version: &'static str,
+    /// The DirtySDK version (DSDK)
+    pub dirty_sdk_version: &'static str,
+    /// The server environment (ENV)
+    pub environment: Environment,
+    /// The client locale (LOC)
+    pub locale: Locale,
+    /// The service name (NAME)
+    pub service_name: &'static str,
+    /// The client platform (PLAT)
+    pub platform: &'static str,
+    /// The connection profile (PROF)
+    pub profile: &'static str,
+}
+
+impl IdentityProfile {
+    /// Profile using the values from the Mass Effect 3 PC client
+    pub const ME3_PC: IdentityProfile = IdentityProfile {
+        name: "me3-pc",
+        sdk_version: "3.15.6.0",
+        build_time: "Dec 21 2012 12:47:10",
+        client: "MassEffect3-pc",
+        client_type: 0,
+        sku: "134845",
+        version: "05427.124",
+        dirty_sdk_version: "8.14.7.1",
+        environment: Environment::Prod,
+        locale: Locale::EN_NZ,
+        service_name: "masseffect-3-pc",
+        platform: "Windows",
+        profile: "standardSecure_v3",
+    };
+
+    /// The registered profiles that can be selected by name
+    pub const PROFILES: &'static [IdentityProfile] = &[Self::ME3_PC];
+
+    /// Finds the registered profile with the provided name
+    ///
+    /// `name` The name of the profile
+    pub fn find(name: &str) -> Option<&'static IdentityProfile> {
+        Self::PROFILES.iter().find(|profile| profile.name == name)
+    }
+
+    /// Finds the registered profile presented by the client with the
+    /// provided name (CLNT)
+    ///
+    /// `client` The name of the client
+    pub fn find_client(client: &str) -> Option<&'static IdentityProfile> {
+        Self::PROFILES
+            .iter()
+            .find(|profile| profile.client == client)
+    }
+
+    /// Creates an instance request using the values of this profile
+    pub fn request(&self) -> InstanceRequest {
+        InstanceRequest {
+            sdk_version: self.sdk_version.to_string(),
+            build_time: self.build_time.to_string(),
+            client: self.client.to_string(),
+            client_type: self.client_type,
+            sku: self.sku.to_string(),
+            version: self.version.to_string(),
+            dirty_sdk_version: self.dirty_sdk_version.to_string(),
+            environment: self.environment.clone(),
+            locale: self.locale,
+            service_name: self.service_name.to_string(),
+            platform: self.platform.to_string(),
+            profile: self.profile.to_string(),
+            first_party_id: None,
+        }
+    }
+}
+
+/// Client locale in its packed u32 form where each byte is a
+/// character of the locale code (e.g. 0x656e4e5a is enNZ)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale(pub u32);
+
+impl Locale {
+    /// The locale sent by the official game copy (enNZ)
+    pub const EN_NZ: Locale = Locale(0x656e4e5a);
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::EN_NZ
+    }
+}
+
+/// Parses a locale from its four letter code (e.g. enUS)
+impl FromStr for Locale {
+    type Err = LocaleError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let bytes: [u8; 4] = value.as_bytes().try_into().map_err(|_| LocaleError)?;
+        if !bytes.iter().all(u8::is_ascii_alphabetic) {
+            return Err(LocaleError);
+        }
+        Ok(Self(u32::from_be_bytes(bytes)))
+    }
+}
+
+/// Formats the locale as its four letter code
+impl Display for Locale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0.to_be_bytes() {
+            write!(f, "{}", byte as char)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error for locale codes that are not four ASCII letters
+#[derive(Debug)]
+pub struct LocaleError;
+
+impl Display for LocaleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Locale must be a four letter code such as enUS")
+    }
+}
+
+impl Error for LocaleError {}
+
+/// The server environment (ENV) the client is connecting to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Environment {
+    /// The production environment used by the official game copy
+    #[default]
+    Prod,
+    /// The test environment
+    Test,
+    /// The development environment
+    Dev,
+    /// Any other environment name
+    Other(String),
+}
+
+impl Environment {
+    /// Returns the value sent for this environment
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Prod => "prod",
+            Self::Test => "test",
+            Self::Dev => "dev",
+            Self::Other(value) => value,
+        }
+    }
+}
+
+/// Converts from the environment name, names that aren't known are
+/// kept as is
+impl From<String> for Environment {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "prod" => Self::Prod,
+            "test" => Self::Test,
+            "dev" => Self::Dev,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+impl Display for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
 /// Networking information for an instance. Contains the
 /// host address and the port
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct InstanceNet {
     pub host: InstanceHost,
     pub port: Port,
 }
 
+impl InstanceNet {
+    /// Creates instance networking details for the provided host and
+    /// port returning an error if the port is zero as clients are
+    /// unable to connect to it
+    ///
+    /// `host` The host address or hostname of the instance
+    /// `port` The port of the instance
+    pub fn try_new(host: impl Into<String>, port: Port) -> Result<Self, InstanceNetError> {
+        if port == 0 {
+            return Err(InstanceNetError::ZeroPort);
+        }
+        Ok(Self::from((host.into(), port)))
+    }
+
+    /// Creates a placeholder instance net using the unspecified address
+    /// (0.0.0.0) and port zero
+    pub fn unspecified() -> Self {
+        Self {
+            host: InstanceHost::Address(NetAddress(Ipv4Addr::UNSPECIFIED)),
+            port: 0,
+        }
+    }
+
+    /// Returns the socket address for this instance if the host is an
+    /// address. None is returned for hostnames as they must be resolved
+    pub fn to_socket_addr(&self) -> Option<SocketAddr> {
+        self.host.ip_addr().map(|ip| SocketAddr::new(ip, self.port))
+    }
+
+    /// Returns whether the host is a hostname that must be resolved
+    pub fn is_hostname(&self) -> bool {
+        matches!(self.host, InstanceHost::Host(_))
+    }
+
+    /// Returns whether the host is an IPv4 or IPv6 address
+    pub fn is_ip(&self) -> bool {
+        !self.is_hostname()
+    }
+
+    /// Returns the host as a string which is either the hostname or
+    /// the formatted address
+    pub fn host_string(&self) -> String {
+        String::from(self.host.clone())
+    }
+}
+
+impl From<SocketAddrV4> for InstanceNet {
+    fn from(value: SocketAddrV4) -> Self {
+        let (addr, port) = NetAddress::from_socket_addr(value);
+        Self {
+            host: InstanceHost::Address(addr),
+            port,
+        }
+    }
+}
+
 impl From<(String, Port)> for InstanceNet {
     fn from((host, port): (String, Port)) -> Self {
         let host = InstanceHost::from(host);
@@ -49,6 +505,23 @@ impl From<(String, Port)> for InstanceNet {
     }
 }
 
+/// Errors that can occur when creating an InstanceNet
+#[derive(Debug)]
+pub enum InstanceNetError {
+    /// The port was zero
+    ZeroPort,
+}
+
+impl Display for InstanceNetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroPort => f.write_str("Instance port cannot be zero"),
+        }
+    }
+}
+
+impl Error for InstanceNetError {}
+
 impl Encodable for InstanceNet {
     fn encode(&self, writer: &mut TdfWriter) {
         self.host.encode(writer);
@@ -61,39 +534,150 @@ impl Decodable for InstanceNet {
     fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
         let host: InstanceHost = InstanceHost::decode(reader)?;
         let port: u16 = reader.tag("PORT")?;
-        reader.read_byte()?;
+        read_group_end(reader);
         Ok(Self { host, port })
     }
 }
 
+/// Consumes the terminator at the end of a group along with any unknown
+/// tags that some encoders write before it. Other encoders omit the
+/// terminator so when the contents end without one the reader is left
+/// where the group ended rather than treating it as an error
+///
+/// `reader` The reader to consume the terminator from
+fn read_group_end(reader: &mut TdfReader) {
+    let start = reader.cursor;
+    while let Some(&next) = reader.buffer.get(reader.cursor) {
+        if next == 0 {
+            reader.cursor += 1;
+            return;
+        }
+        let skipped = match reader.read_tag() {
+            Ok(Tag(tag, ty)) => {
+                debug!(
+                    "Skipping unknown group tag {tag} at offset {}",
+                    reader.cursor
+                );
+                reader.skip_type(&ty)
+            }
+            Err(err) => Err(err),
+        };
+        if skipped.is_err() {
+            break;
+        }
+    }
+    debug!("Group terminator missing at offset {start}");
+    reader.cursor = start;
+}
+
 value_type!(InstanceNet, TdfType::Group);
 
 /// Type of instance details provided either hostname
 /// encoded as string or IP address encoded as NetAddress
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum InstanceHost {
     Host(String),
     Address(NetAddress),
+    Address6(NetAddress6),
+}
+
+impl InstanceHost {
+    /// Returns the IP address of this host if it is an address rather
+    /// than a hostname
+    pub fn ip_addr(&self) -> Option<IpAddr> {
+        match self {
+            Self::Address(value) => Some(IpAddr::V4(value.0)),
+            Self::Address6(value) => Some(IpAddr::V6(value.0)),
+            Self::Host(_) => None,
+        }
+    }
+}
+
+/// Attempts to parse the provided value into a instance type. If
+/// the provided value is an IPv4 value then Address is used, if its
+/// an IPv6 value then Address6 is used otherwise Host is used.
+/// Hostnames are lowercased and a single trailing dot is removed so
+/// that the same host is always compared and resolved the same way
+///
+/// Empty values and values containing control characters are
+/// rejected as they can't be valid hostnames
+impl FromStr for InstanceHost {
+    type Err = InstanceHostError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty() {
+            return Err(InstanceHostError::Empty);
+        }
+        if let Some(index) = value.find(char::is_control) {
+            return Err(InstanceHostError::ControlCharacter(index));
+        }
+        Ok(if let Ok(value) = NetAddress::try_from(value) {
+            Self::Address(value)
+        } else if let Ok(value) = value.parse::<Ipv6Addr>() {
+            Self::Address6(NetAddress6(value))
+        } else {
+            let host = value
+                .strip_suffix('.')
+                .filter(|host| !host.is_empty())
+                .unwrap_or(value);
+            // Only ASCII is lowercased so internationalized names are
+            // passed through unchanged for the resolver
+            Self::Host(host.to_ascii_lowercase())
+        })
+    }
 }
 
-/// Attempts to convert the provided value into a instance type. If
-/// the provided value is an IPv4 value then Address is used otherwise
-/// Host is used.
+/// Converts the provided value into a instance type using the
+/// FromStr implementation. Values that fail to parse are used
+/// as the Host directly
 impl From<String> for InstanceHost {
     fn from(value: String) -> Self {
-        if let Ok(value) = value.parse::<Ipv4Addr>() {
-            Self::Address(NetAddress(value))
-        } else {
-            Self::Host(value)
+        value.parse().unwrap_or(Self::Host(value))
+    }
+}
+
+/// Errors that can occur when parsing an InstanceHost
+#[derive(Debug)]
+pub enum InstanceHostError {
+    /// The host value was empty
+    Empty,
+    /// The host value contained a control character at the
+    /// provided byte index
+    ControlCharacter(usize),
+}
+
+impl Display for InstanceHostError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Instance host cannot be empty"),
+            Self::ControlCharacter(index) => write!(
+                f,
+                "Instance host contains a control character at index {index}"
+            ),
         }
     }
 }
 
+impl Error for InstanceHostError {}
+
 /// Function for converting an instance type into its address
 /// string value for use in connections
+impl Display for InstanceHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Host(value) => f.write_str(value),
+            Self::Address(value) => Display::fmt(value, f),
+            Self::Address6(value) => Display::fmt(value, f),
+        }
+    }
+}
+
 impl From<InstanceHost> for String {
     fn from(value: InstanceHost) -> Self {
         match value {
             InstanceHost::Address(value) => value.to_string(),
+            InstanceHost::Address6(value) => value.to_string(),
             InstanceHost::Host(value) => value,
         }
     }
@@ -104,141 +688,1795 @@ impl Encodable for InstanceHost {
         match self {
             InstanceHost::Host(value) => writer.tag_str(b"HOST", value),
             InstanceHost::Address(value) => writer.tag_value(b"IP", value),
+            InstanceHost::Address6(value) => writer.tag_value(b"HOST", value),
         }
     }
 }
 
 impl Decodable for InstanceHost {
     fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
-        let host: Option<String> = reader.try_tag("HOST")?;
-        if let Some(host) = host {
-            return Ok(Self::Host(host));
+        // Tags are read manually rather than using try_tag because searching
+        // for a missing HOST tag would read past the end of the group
+        loop {
+            let next = reader.buffer.get(reader.cursor).copied().unwrap_or(0);
+            // Reached the group terminator without finding a host
+            if next == 0 {
+                return Err(DecodeError::MissingTag {
+                    tag: "HOST".to_string(),
+                    ty: TdfType::String,
+                });
+            }
+
+            let Tag(tag, ty) = reader.read_tag()?;
+            match (tag.as_str(), &ty) {
+                ("HOST", TdfType::String) => {
+                    let host = reader.read_string()?;
+                    // IPv6 addresses are carried in the host field
+                    if let Ok(value) = host.parse::<Ipv6Addr>() {
+                        return Ok(Self::Address6(NetAddress6(value)));
+                    }
+                    return Ok(Self::Host(host));
+                }
+                ("IP", TdfType::VarInt) => {
+                    let ip = NetAddress::decode(reader)?;
+                    return Ok(Self::Address(ip));
+                }
+                _ => reader.skip_type(&ty)?,
+            }
         }
-        let ip: NetAddress = reader.tag("IP")?;
-        Ok(Self::Address(ip))
     }
 }
 
 /// Details about an instance. This is used for the redirector system
 /// to both encode for redirections and decode for the retriever system
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct InstanceDetails {
-    /// The networking information for the instance
-    pub net: InstanceNet,
+    /// The networking address for the instance
+    pub address: InstanceAddress,
     /// Whether the host requires a secure connection (SSLv3)
     pub secure: bool,
+    /// Whether the client should resolve the host using XDNS
+    pub xdns: bool,
 }
 
-impl Encodable for InstanceDetails {
-    fn encode(&self, writer: &mut TdfWriter) {
-        writer.tag_union_start(b"ADDR", NetworkAddressType::Server.into());
-        writer.tag_value(b"VALU", &self.net);
+impl InstanceDetails {
+    /// Creates new instance details for the provided address with
+    /// XDNS disabled
+    ///
+    /// `address` The address of the instance
+    /// `secure`  Whether the instance requires a secure connection
+    pub fn new(address: InstanceAddress, secure: bool) -> Self {
+        Self {
+            address,
+            secure,
+            xdns: false,
+        }
+    }
 
-        writer.tag_bool(b"SECU", self.secure);
-        writer.tag_bool(b"XDNS", false);
+    /// Returns the networking information that should be used when
+    /// connecting to the instance
+    pub fn net(&self) -> &InstanceNet {
+        self.address.net()
     }
-}
 
-impl Decodable for InstanceDetails {
-    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
-        let net: InstanceNet = match reader.tag::<Union<InstanceNet>>("ADDR")? {
-            Union::Set { value, .. } => value,
-            Union::Unset => {
-                return Err(blaze_pk::error::DecodeError::MissingTag {
-                    tag: "ADDR".to_string(),
-                    ty: TdfType::Union,
-                })
-            }
-        };
-        let secure: bool = reader.tag("SECU")?;
-        Ok(InstanceDetails { net, secure })
+    /// Returns whether the instance host is a hostname that must
+    /// be resolved
+    pub fn is_hostname(&self) -> bool {
+        self.net().is_hostname()
     }
-}
 
-#[derive(Debug, Copy, Clone)]
-pub enum NetworkAddressType {
-    Server,
-    Client,
-    Pair,
-    IpAddress,
-    HostnameAddress,
-    Unknown(u8),
-}
+    /// Returns whether the instance host is an IPv4 or IPv6 address
+    pub fn is_ip(&self) -> bool {
+        self.net().is_ip()
+    }
 
-impl NetworkAddressType {
-    pub fn value(&self) -> u8 {
-        match self {
-            Self::Server => 0x0,
-            Self::Client => 0x1,
-            Self::Pair => 0x2,
-            Self::IpAddress => 0x3,
-            Self::HostnameAddress => 0x4,
-            Self::Unknown(value) => *value,
+    /// Returns the instance host as a string which is either the
+    /// hostname or the formatted address
+    pub fn host_string(&self) -> String {
+        self.net().host_string()
+    }
+
+    /// Creates a builder for server instance details with the provided
+    /// host and port. The host is converted using InstanceHost::from so
+    /// both addresses and hostnames are supported
+    ///
+    /// `host` The host address or hostname of the instance
+    /// `port` The port of the instance
+    pub fn builder(host: impl Into<String>, port: Port) -> InstanceDetailsBuilder {
+        InstanceDetailsBuilder {
+            net: InstanceNet::from((host.into(), port)),
+            secure: false,
         }
     }
 
-    pub fn from_value(value: u8) -> Self {
-        match value {
-            0x0 => Self::Server,
-            0x1 => Self::Client,
-            0x2 => Self::Pair,
-            0x3 => Self::IpAddress,
-            0x4 => Self::HostnameAddress,
-            value => Self::Unknown(value),
+    /// Returns these details for encoding with only the provided optional
+    /// tags included
+    ///
+    /// `tags` The optional tags to include
+    pub fn with_tags(&self, tags: InstanceTags) -> TaggedInstanceDetails<'_> {
+        TaggedInstanceDetails {
+            details: self,
+            tags,
         }
     }
+
+    /// Decodes instance details from the provided reader returning the
+    /// details along with the range of the reader buffer that they were
+    /// decoded from
+    ///
+    /// `reader`  The reader to decode from
+    /// `lenient` Whether unsupported address types are skipped
+    pub fn decode_with_raw(
+        reader: &mut TdfReader,
+        lenient: bool,
+    ) -> DecodeResult<(Self, Range<usize>)> {
+        let start = reader.cursor;
+        let value = Self::decode_with(reader, lenient)?;
+        Ok((value, start..reader.cursor))
+    }
+
+    /// Decodes instance details using decode_with_raw and logs the
+    /// bytes they were decoded from
+    ///
+    /// `reader`  The reader to decode from
+    /// `lenient` Whether unsupported address types are skipped
+    fn decode_logged(reader: &mut TdfReader, lenient: bool) -> DecodeResult<Self> {
+        let (value, range) = Self::decode_with_raw(reader, lenient)?;
+        debug!(
+            "Decoded instance details {:?} from {:02x?}",
+            value, &reader.buffer[range]
+        );
+        Ok(value)
+    }
 }
 
-impl From<NetworkAddressType> for u8 {
-    fn from(value: NetworkAddressType) -> Self {
-        value.value()
+/// Builder for server instance details. The instance is insecure
+/// unless specified otherwise and XDNS is disabled
+pub struct InstanceDetailsBuilder {
+    /// The networking address for the instance
+    net: InstanceNet,
+    /// Whether the host requires a secure connection (SSLv3)
+    secure: bool,
+}
+
+impl InstanceDetailsBuilder {
+    /// Sets whether the instance requires a secure connection
+    ///
+    /// `secure` Whether a secure connection is required
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Creates the instance details
+    pub fn build(self) -> InstanceDetails {
+        InstanceDetails::new(InstanceAddress::Server(self.net), self.secure)
     }
 }
 
-/// Type alias for ports which are always u16
-pub type Port = u16;
+/// Creates server instance details from a host, port and and secure
+/// triple. The host is converted using InstanceHost::from so both
+/// addresses and hostnames are supported
+impl From<(String, Port, bool)> for InstanceDetails {
+    fn from((host, port, secure): (String, Port, bool)) -> Self {
+        Self::builder(host, port).secure(secure).build()
+    }
+}
 
-/// Structure for wrapping a Blaze networking address
-#[derive(Copy, Clone, Eq, PartialEq)]
-pub struct NetAddress(pub Ipv4Addr);
+impl Encodable for InstanceDetails {
+    fn encode(&self, writer: &mut TdfWriter) {
+        self.with_tags(InstanceTags::default()).encode(writer);
+    }
+}
 
-impl Default for NetAddress {
+/// The optional tags that are included when encoding instance details.
+/// Some Blaze server implementations reject responses containing tags
+/// they don't expect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceTags {
+    /// Whether the SECU tag is included
+    pub secu: bool,
+    /// Whether the XDNS tag is included
+    pub xdns: bool,
+}
+
+impl Default for InstanceTags {
     fn default() -> Self {
-        Self(Ipv4Addr::LOCALHOST)
+        Self {
+            secu: true,
+            xdns: true,
+        }
     }
 }
 
-impl Encodable for NetAddress {
+/// Instance details that are encoded with only the selected optional
+/// tags, created using [`InstanceDetails::with_tags`]
+pub struct TaggedInstanceDetails<'a> {
+    /// The instance details to encode
+    details: &'a InstanceDetails,
+    /// The optional tags to include
+    tags: InstanceTags,
+}
+
+impl Encodable for TaggedInstanceDetails<'_> {
     fn encode(&self, writer: &mut TdfWriter) {
-        let bytes = self.0.octets();
-        let value = u32::from_be_bytes(bytes);
-        writer.write_u32(value);
+        let details = self.details;
+        writer.tag_union_start(b"ADDR", details.address.ty().into());
+        match &details.address {
+            InstanceAddress::Server(value)
+            | InstanceAddress::Client(value)
+            | InstanceAddress::IpAddress(value)
+            | InstanceAddress::HostnameAddress(value) => writer.tag_value(b"VALU", value),
+            InstanceAddress::Pair(value) => writer.tag_value(b"VALU", value),
+        }
+
+        if self.tags.secu {
+            writer.tag_bool(b"SECU", details.secure);
+        }
+        if self.tags.xdns {
+            writer.tag_bool(b"XDNS", details.xdns);
+        }
     }
 }
 
-impl Decodable for NetAddress {
+impl Decodable for InstanceDetails {
     fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
-        let value = reader.read_u32()?;
-        let bytes = value.to_be_bytes();
-        let addr = Ipv4Addr::from(bytes);
-        Ok(Self(addr))
+        Self::decode_with(reader, false)
     }
 }
 
-value_type!(NetAddress, TdfType::VarInt);
+impl InstanceDetails {
+    /// Decodes instance details from the provided reader. When lenient
+    /// an unsupported network address type is logged and replaced with
+    /// an unspecified server address rather than failing the decode
+    ///
+    /// `reader`  The reader to decode from
+    /// `lenient` Whether unsupported address types are skipped
+    pub fn decode_with(reader: &mut TdfReader, lenient: bool) -> DecodeResult<Self> {
+        let mut address = None;
+        let mut secure = None;
+        let mut xdns = false;
 
-/// Debug trait implementation sample implementation as the Display
-/// implementation so that is just called instead
-impl Debug for NetAddress {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Display::fmt(self, f)
+        // Tags are read manually rather than using tag and try_tag because
+        // searching for a missing tag would read into the following group
+        // when the details are an element of a list
+        loop {
+            let next = reader.buffer.get(reader.cursor).copied().unwrap_or(0);
+            // Reached the group terminator or the end of the contents
+            if next == 0 {
+                break;
+            }
+
+            let Tag(tag, ty) = reader.read_tag()?;
+            match (tag.as_str(), &ty) {
+                ("ADDR", TdfType::Union) => {
+                    address = Some(InstanceAddress::decode_union(reader, lenient)?)
+                }
+                ("SECU", TdfType::VarInt) => secure = Some(bool::decode(reader)?),
+                ("XDNS", TdfType::VarInt) => xdns = bool::decode(reader)?,
+                _ => reader.skip_type(&ty)?,
+            }
+        }
+
+        let address = address.ok_or_else(|| DecodeError::MissingTag {
+            tag: "ADDR".to_string(),
+            ty: TdfType::Union,
+        })?;
+        let secure = secure.ok_or_else(|| DecodeError::MissingTag {
+            tag: "SECU".to_string(),
+            ty: TdfType::VarInt,
+        })?;
+        Ok(InstanceDetails {
+            address,
+            secure,
+            xdns,
+        })
     }
 }
 
-/// Display trait implementation for NetAddress. If the value is valid
-/// the value is translated into the IPv4 representation
-impl Display for NetAddress {
-    /// Converts the value stored in this NetAddress to an IPv4 string
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// List of instances returned by the redirector. Responses containing
+/// a LIST of instances are decoded as is, otherwise the response is
+/// decoded as a single instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ServerList {
+    /// The instances in the order they were provided
+    pub instances: Vec<InstanceDetails>,
+}
+
+impl ServerList {
+    /// Returns the preferred instance which is the first instance
+    /// provided by the redirector
+    pub fn primary(&self) -> Option<&InstanceDetails> {
+        self.instances.first()
+    }
+}
+
+impl Decodable for ServerList {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        Self::decode_with(reader, false)
+    }
+}
+
+impl ServerList {
+    /// Decodes the server list from the provided reader, see
+    /// [`InstanceDetails::decode_with`] for the lenient behavior
+    ///
+    /// `reader`  The reader to decode from
+    /// `lenient` Whether unsupported address types are skipped
+    pub fn decode_with(reader: &mut TdfReader, lenient: bool) -> DecodeResult<Self> {
+        let start = reader.cursor;
+        let count = match reader.until_list("LIST", TdfType::Group) {
+            Ok(value) => value,
+            Err(DecodeError::MissingTag { .. }) => {
+                reader.cursor = start;
+                let instance = InstanceDetails::decode_logged(reader, lenient)?;
+                return Ok(Self {
+                    instances: vec![instance],
+                });
+            }
+            Err(err) => return Err(err),
+        };
+        let mut instances = Vec::with_capacity(count);
+        for _ in 0..count {
+            instances.push(InstanceDetails::decode_logged(reader, lenient)?);
+            // Skip any remaining fields and the group terminator
+            reader.skip_group()?;
+        }
+        Ok(Self { instances })
+    }
+}
+
+/// Error response from the redirector for a server instance request
+/// which contains messages explaining why the request was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ServerInstanceError {
+    /// The messages provided by the redirector (MSGS)
+    pub messages: Vec<String>,
+}
+
+impl ServerInstanceError {
+    /// Redirector error code for clients that the server no longer
+    /// accepts (REDIRECTOR_CLIENT_NOT_COMPATIBLE)
+    pub const CLIENT_NOT_COMPATIBLE: u16 = 3;
+
+    /// Creates an error with the provided message
+    ///
+    /// `message` The message explaining why the request was rejected
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            messages: vec![message.into()],
+        }
+    }
+
+    /// Returns whether the provided error code and these messages
+    /// indicate that the request was rejected because of its versions
+    ///
+    /// `error` The error code from the response header
+    pub fn is_version_mismatch(&self, error: u16) -> bool {
+        error == Self::CLIENT_NOT_COMPATIBLE
+            || self.messages.iter().any(|message| {
+                let message = message.to_ascii_lowercase();
+                message.contains("version") || message.contains("compatib")
+            })
+    }
+}
+
+impl Encodable for ServerInstanceError {
+    fn encode(&self, writer: &mut TdfWriter) {
+        writer.tag_value(b"MSGS", &self.messages);
+    }
+}
+
+impl Decodable for ServerInstanceError {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let messages = reader.try_tag("MSGS")?.unwrap_or_default();
+        Ok(Self { messages })
+    }
+}
+
+/// Response to a successful login request. Depending on the login
+/// command the session details are either at the top level or nested
+/// within a SESS group so both are searched. Failed logins are error
+/// packets which only carry their error code in the packet header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LoginResponse {
+    /// The identifier of the logged in user or persona (UID)
+    pub persona_id: Option<u32>,
+    /// Whether a session token was provided (PCTK, SKEY or KEY)
+    pub has_token: bool,
+}
+
+impl LoginResponse {
+    /// Reads the tags of the current level of the provided reader until
+    /// the end of the contents or a group terminator is reached
+    ///
+    /// `reader` The reader for the contents
+    fn decode_level(&mut self, reader: &mut TdfReader) -> DecodeResult<()> {
+        while let Some(&next) = reader.buffer.get(reader.cursor) {
+            if next == 0 {
+                reader.cursor += 1;
+                break;
+            }
+            let Tag(tag, ty) = reader.read_tag()?;
+            match (tag.as_str(), &ty) {
+                ("UID", TdfType::VarInt) => self.persona_id = Some(reader.read_u32()?),
+                ("PCTK" | "SKEY" | "KEY", TdfType::String) => {
+                    self.has_token |= !reader.read_string()?.is_empty();
+                }
+                ("SESS", TdfType::Group) => {
+                    if reader.buffer.get(reader.cursor) == Some(&2) {
+                        reader.cursor += 1;
+                    }
+                    self.decode_level(reader)?;
+                }
+                _ => reader.skip_type(&ty)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Decodable for LoginResponse {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let mut response = Self::default();
+        response.decode_level(reader)?;
+        Ok(response)
+    }
+}
+
+/// User setting saved by the client in a Util UserSettingsSave request
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UserSettingsSave {
+    /// The value of the setting (DATA)
+    pub value: String,
+    /// The key of the setting (KEY)
+    pub key: String,
+    /// The user the setting is saved for when provided (UID)
+    pub user_id: Option<u32>,
+}
+
+impl Decodable for UserSettingsSave {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let value: String = reader.tag("DATA")?;
+        let key: String = reader.tag("KEY")?;
+        let user_id: Option<u32> = reader.try_tag("UID")?;
+        Ok(Self {
+            value,
+            key,
+            user_id,
+        })
+    }
+}
+
+/// Connection metrics reported by the client in a Util SetClientMetrics
+/// request. Only the UPnP fields which are stable across builds are
+/// decoded and either may be missing
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ClientMetrics {
+    /// The description of the UPnP device (UDEV)
+    pub upnp_device: Option<String>,
+    /// The status of the UPnP port mapping (USTA)
+    pub upnp_status: Option<u8>,
+}
+
+impl Decodable for ClientMetrics {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let upnp_device: Option<String> = reader.try_tag("UDEV")?;
+        let upnp_status: Option<u8> = reader.try_tag("USTA")?;
+        Ok(Self {
+            upnp_device,
+            upnp_status,
+        })
+    }
+}
+
+/// Telemetry and user settings message sent by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TelemetryMessage {
+    /// A user setting being saved
+    UserSettingsSave(UserSettingsSave),
+    /// Connection metrics of the client
+    ClientMetrics(ClientMetrics),
+}
+
+/// QoS ping site that clients measure their latency to during login
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QosPingSite {
+    /// The host of the ping site (PSA)
+    pub host: InstanceHost,
+    /// The port of the ping site (PSP)
+    pub port: Port,
+    /// The name of the ping site (SNA)
+    pub name: String,
+}
+
+impl Encodable for QosPingSite {
+    fn encode(&self, writer: &mut TdfWriter) {
+        writer.tag_str(b"PSA", &self.host.to_string());
+        writer.tag_u16(b"PSP", self.port);
+        writer.tag_str(b"SNA", &self.name);
+        writer.tag_group_end();
+    }
+}
+
+impl Decodable for QosPingSite {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let host: String = reader.tag("PSA")?;
+        let port: u16 = reader.tag("PSP")?;
+        let name: String = reader.tag("SNA")?;
+        read_group_end(reader);
+        Ok(Self {
+            host: InstanceHost::from(host),
+            port,
+            name,
+        })
+    }
+}
+
+value_type!(QosPingSite, TdfType::Group);
+
+/// QoS settings (QOSS) from the Util PreAuth response which tell the
+/// client which ping sites to measure its latency against
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QosSettings {
+    /// The ping site used for measuring bandwidth (BWPS)
+    pub bandwidth_site: QosPingSite,
+    /// The number of latency probes to send (LNP)
+    pub latency_probes: u8,
+    /// The ping sites used for measuring latency keyed by their
+    /// identifier (LTPS)
+    pub latency_sites: Vec<(String, QosPingSite)>,
+    /// The QoS service identifier (SVID)
+    pub service_id: u32,
+}
+
+impl QosSettings {
+    /// Finds and decodes the QoS settings within the provided packet
+    /// contents returning them along with the range of the contents
+    /// that they were decoded from
+    ///
+    /// `contents` The packet contents
+    pub fn find(contents: &[u8]) -> DecodeResult<(Self, Range<usize>)> {
+        let mut reader = TdfReader::new(contents);
+        reader.until_tag("QOSS", TdfType::Group)?;
+        let start = reader.cursor;
+        let value = Self::decode(&mut reader)?;
+        Ok((value, start..reader.cursor))
+    }
+
+    /// Rewrites the QoS settings within the provided packet using the
+    /// provided function. The rest of the packet contents are left as is
+    ///
+    /// `packet`  The packet containing the QoS settings
+    /// `rewrite` The function that modifies the settings
+    pub fn rewrite(packet: &mut Packet, rewrite: impl FnOnce(&mut Self)) -> DecodeResult<()> {
+        let (mut value, range) = Self::find(&packet.contents)?;
+        rewrite(&mut value);
+        splice_rewrite(packet, range, &value);
+        Ok(())
+    }
+}
+
+/// Replaces the provided range of the packet contents with the encoded
+/// value leaving the bytes on either side of it as is
+///
+/// `packet` The packet to modify
+/// `range`  The range of the contents the value was decoded from
+/// `value`  The value to encode in place of the range
+fn splice_rewrite<T: Encodable>(packet: &mut Packet, range: Range<usize>, value: &T) {
+    let mut writer = TdfWriter::default();
+    value.encode(&mut writer);
+
+    let mut contents = Vec::with_capacity(packet.contents.len());
+    contents.extend_from_slice(&packet.contents[..range.start]);
+    contents.extend_from_slice(&writer.buffer);
+    contents.extend_from_slice(&packet.contents[range.end..]);
+    packet.contents = contents.into();
+}
+
+impl Encodable for QosSettings {
+    fn encode(&self, writer: &mut TdfWriter) {
+        writer.tag_value(b"BWPS", &self.bandwidth_site);
+        writer.tag_u8(b"LNP", self.latency_probes);
+        writer.tag_map_start(
+            b"LTPS",
+            TdfType::String,
+            TdfType::Group,
+            self.latency_sites.len(),
+        );
+        for (key, site) in &self.latency_sites {
+            writer.write_str(key);
+            site.encode(writer);
+        }
+        writer.tag_u32(b"SVID", self.service_id);
+        writer.tag_group_end();
+    }
+}
+
+impl Decodable for QosSettings {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let bandwidth_site: QosPingSite = reader.tag("BWPS")?;
+        let latency_probes: u8 = reader.tag("LNP")?;
+        reader.until_tag("LTPS", TdfType::Map)?;
+        let count = reader.read_map_header(TdfType::String, TdfType::Group)?;
+        let mut latency_sites = Vec::with_capacity(count);
+        for _ in 0..count {
+            let key = String::decode(reader)?;
+            let site = QosPingSite::decode(reader)?;
+            latency_sites.push((key, site));
+        }
+        let service_id: u32 = reader.tag("SVID")?;
+        reader.skip_group()?;
+        Ok(Self {
+            bandwidth_site,
+            latency_probes,
+            latency_sites,
+            service_id,
+        })
+    }
+}
+
+value_type!(QosSettings, TdfType::Group);
+
+/// Latencies the client measured to each QoS ping site (NLMP) which are
+/// reported to the official server in the UserSessions UpdateNetworkInfo
+/// request and used for matchmaking
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QosLatencyReport {
+    /// The latency in milliseconds keyed by ping site identifier
+    pub latencies: Vec<(String, u32)>,
+}
+
+impl QosLatencyReport {
+    /// Finds and decodes the latency report within the provided packet
+    /// contents returning it along with the range of the contents that
+    /// it was decoded from. The report is looked for within the network
+    /// info group (INFO) when present
+    ///
+    /// `contents` The packet contents
+    pub fn find(contents: &[u8]) -> DecodeResult<(Self, Range<usize>)> {
+        let mut reader = TdfReader::new(contents);
+        reader.try_until_tag("INFO", TdfType::Group);
+        reader.until_tag("NLMP", TdfType::Map)?;
+        let start = reader.cursor;
+        let value = Self::decode(&mut reader)?;
+        Ok((value, start..reader.cursor))
+    }
+
+    /// Rewrites the latency report within the provided packet using the
+    /// provided function. The rest of the packet contents are left as is
+    ///
+    /// `packet`  The packet containing the latency report
+    /// `rewrite` The function that modifies the report
+    pub fn rewrite(packet: &mut Packet, rewrite: impl FnOnce(&mut Self)) -> DecodeResult<()> {
+        let (mut value, range) = Self::find(&packet.contents)?;
+        rewrite(&mut value);
+        splice_rewrite(packet, range, &value);
+        Ok(())
+    }
+}
+
+impl Encodable for QosLatencyReport {
+    fn encode(&self, writer: &mut TdfWriter) {
+        writer.write_map_header(TdfType::String, TdfType::VarInt, self.latencies.len());
+        for (key, latency) in &self.latencies {
+            writer.write_str(key);
+            latency.encode(writer);
+        }
+    }
+}
+
+impl Decodable for QosLatencyReport {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let count = reader.read_map_header(TdfType::String, TdfType::VarInt)?;
+        let mut latencies = Vec::with_capacity(count);
+        for _ in 0..count {
+            let key = String::decode(reader)?;
+            let latency = u32::decode(reader)?;
+            latencies.push((key, latency));
+        }
+        Ok(Self { latencies })
+    }
+}
+
+value_type!(QosLatencyReport, TdfType::Map);
+
+/// The address portion of instance details. The variant used
+/// determines the network address type of the union
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum InstanceAddress {
+    /// Single server address
+    Server(InstanceNet),
+    /// Address of a client such as when mirroring a client endpoint
+    Client(InstanceNet),
+    /// Internal and external address pair
+    Pair(NetworkPair),
+    /// Address provided as an IP address
+    IpAddress(InstanceNet),
+    /// Address provided as a hostname
+    HostnameAddress(InstanceNet),
+}
+
+impl InstanceAddress {
+    /// Returns the network address type for this address
+    pub fn ty(&self) -> NetworkAddressType {
+        match self {
+            Self::Server(_) => NetworkAddressType::Server,
+            Self::Client(_) => NetworkAddressType::Client,
+            Self::Pair(_) => NetworkAddressType::Pair,
+            Self::IpAddress(_) => NetworkAddressType::IpAddress,
+            Self::HostnameAddress(_) => NetworkAddressType::HostnameAddress,
+        }
+    }
+
+    /// Returns the networking information that should be used when
+    /// connecting to the instance
+    pub fn net(&self) -> &InstanceNet {
+        match self {
+            Self::Server(value)
+            | Self::Client(value)
+            | Self::IpAddress(value)
+            | Self::HostnameAddress(value) => value,
+            Self::Pair(value) => &value.external,
+        }
+    }
+
+    /// Converts this address into the networking information that
+    /// should be used when connecting to the instance
+    pub fn into_net(self) -> InstanceNet {
+        match self {
+            Self::Server(value)
+            | Self::Client(value)
+            | Self::IpAddress(value)
+            | Self::HostnameAddress(value) => value,
+            Self::Pair(value) => value.external,
+        }
+    }
+
+    /// Decodes the address from the contents of its union. The
+    /// union type byte is read first followed by the VALU tag
+    ///
+    /// `reader`  The reader positioned after the ADDR union tag
+    /// `lenient` Whether unsupported address types are skipped
+    fn decode_union(reader: &mut TdfReader, lenient: bool) -> DecodeResult<Self> {
+        let ty = reader.read_byte()?;
+        if ty == UNION_UNSET {
+            return Err(DecodeError::MissingTag {
+                tag: "ADDR".to_string(),
+                ty: TdfType::Union,
+            });
+        }
+        Ok(match NetworkAddressType::from_value(ty) {
+            NetworkAddressType::Server => Self::Server(reader.tag("VALU")?),
+            NetworkAddressType::Client => Self::Client(reader.tag("VALU")?),
+            NetworkAddressType::Pair => Self::Pair(reader.tag("VALU")?),
+            NetworkAddressType::IpAddress => Self::IpAddress(reader.tag("VALU")?),
+            NetworkAddressType::HostnameAddress => Self::HostnameAddress(reader.tag("VALU")?),
+            ty if lenient => {
+                warn!("Skipping unsupported network address type: {ty}");
+                let Tag(_, value_type) = reader.read_tag()?;
+                reader.skip_type(&value_type)?;
+                Self::Server(InstanceNet::unspecified())
+            }
+            _ => return Err(DecodeError::Other("Unsupported network address type")),
+        })
+    }
+}
+
+/// Pair of internal and external networking information encoded
+/// under the Pair network address type
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NetworkPair {
+    /// The internal (LAN) networking information
+    pub internal: InstanceNet,
+    /// The external (WAN) networking information
+    pub external: InstanceNet,
+}
+
+impl Encodable for NetworkPair {
+    fn encode(&self, writer: &mut TdfWriter) {
+        writer.tag_value(b"EXIP", &self.external);
+        writer.tag_value(b"INIP", &self.internal);
+        writer.tag_group_end();
+    }
+}
+
+impl Decodable for NetworkPair {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let external: InstanceNet = reader.tag("EXIP")?;
+        let internal: InstanceNet = reader.tag("INIP")?;
+        read_group_end(reader);
+        Ok(Self { internal, external })
+    }
+}
+
+value_type!(NetworkPair, TdfType::Group);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NetworkAddressType {
+    Server,
+    Client,
+    Pair,
+    IpAddress,
+    HostnameAddress,
+    Unknown(u8),
+}
+
+impl NetworkAddressType {
+    pub fn value(&self) -> u8 {
+        match self {
+            Self::Server => 0x0,
+            Self::Client => 0x1,
+            Self::Pair => 0x2,
+            Self::IpAddress => 0x3,
+            Self::HostnameAddress => 0x4,
+            Self::Unknown(value) => *value,
+        }
+    }
+
+    pub fn from_value(value: u8) -> Self {
+        match value {
+            0x0 => Self::Server,
+            0x1 => Self::Client,
+            0x2 => Self::Pair,
+            0x3 => Self::IpAddress,
+            0x4 => Self::HostnameAddress,
+            value => Self::Unknown(value),
+        }
+    }
+}
+
+/// Display implementation for NetworkAddressType using the human
+/// readable names for the known types and the hex value for unknown
+/// types (e.g. `unknown(0x07)`)
+impl Display for NetworkAddressType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Server => f.write_str("server"),
+            Self::Client => f.write_str("client"),
+            Self::Pair => f.write_str("pair"),
+            Self::IpAddress => f.write_str("ip address"),
+            Self::HostnameAddress => f.write_str("hostname address"),
+            Self::Unknown(value) => write!(f, "unknown({value:#04x})"),
+        }
+    }
+}
+
+impl From<NetworkAddressType> for u8 {
+    fn from(value: NetworkAddressType) -> Self {
+        value.value()
+    }
+}
+
+/// Type alias for ports which are always u16
+pub type Port = u16;
+
+/// Structure for wrapping a Blaze networking address. The hash
+/// implementation only hashes the address octets
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct NetAddress(pub Ipv4Addr);
+
+impl NetAddress {
+    /// The loopback address (127.0.0.1)
+    pub const LOCALHOST: NetAddress = NetAddress::localhost();
+
+    /// Returns the loopback address (127.0.0.1)
+    pub const fn localhost() -> Self {
+        Self(Ipv4Addr::LOCALHOST)
+    }
+
+    /// Returns the four octets of this address
+    pub const fn octets(&self) -> [u8; 4] {
+        self.0.octets()
+    }
+
+    /// Creates an address from the provided IP address. IPv4-mapped
+    /// IPv6 addresses are converted, None is returned for other IPv6
+    /// addresses
+    ///
+    /// `value` The IP address to convert
+    pub fn from_ip_addr(value: IpAddr) -> Option<Self> {
+        match value {
+            IpAddr::V4(value) => Some(Self(value)),
+            IpAddr::V6(value) => value.to_ipv4_mapped().map(Self),
+        }
+    }
+
+    /// Creates an address from its big-endian packed u32 form as
+    /// used when encoding the address (e.g. 0x7F000001 is 127.0.0.1)
+    ///
+    /// `value` The packed address value
+    pub const fn from_u32(value: u32) -> Self {
+        let [a, b, c, d] = value.to_be_bytes();
+        Self(Ipv4Addr::new(a, b, c, d))
+    }
+
+    /// Returns the big-endian packed u32 form of this address as
+    /// used when encoding the address
+    pub const fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.octets())
+    }
+
+    /// Splits the provided socket address into its address and port
+    ///
+    /// `value` The socket address to split
+    pub fn from_socket_addr(value: SocketAddrV4) -> (Self, Port) {
+        (Self(*value.ip()), value.port())
+    }
+
+    /// Creates a socket address from this address and the provided port
+    ///
+    /// `port` The port for the socket address
+    pub fn to_socket_addr(self, port: Port) -> SocketAddrV4 {
+        SocketAddrV4::new(self.0, port)
+    }
+
+    /// Returns whether this is a loopback address (127.0.0.0/8)
+    pub fn is_loopback(&self) -> bool {
+        self.0.is_loopback()
+    }
+
+    /// Returns whether this is a private address (10.0.0.0/8,
+    /// 172.16.0.0/12 or 192.168.0.0/16)
+    pub fn is_private(&self) -> bool {
+        self.0.is_private()
+    }
+
+    /// Returns whether this is the unspecified address (0.0.0.0)
+    pub fn is_unspecified(&self) -> bool {
+        self.0.is_unspecified()
+    }
+}
+
+// Pins the packed form that addresses are encoded with so that a change
+// to the byte order fails to compile. High octets are included to catch
+// sign and byte order mistakes that low addresses wouldn't show
+const _: () = {
+    assert!(NetAddress::LOCALHOST.to_u32() == 0x7F00_0001);
+    assert!(NetAddress(Ipv4Addr::new(255, 254, 253, 252)).to_u32() == 0xFFFE_FDFC);
+
+    let [a, b, c, d] = NetAddress::from_u32(0x7F00_0001).octets();
+    assert!(a == 127 && b == 0 && c == 0 && d == 1);
+    let [a, b, c, d] = NetAddress::from_u32(0xFFFE_FDFC).octets();
+    assert!(a == 255 && b == 254 && c == 253 && d == 252);
+};
+
+impl Default for NetAddress {
+    fn default() -> Self {
+        Self::LOCALHOST
+    }
+}
+
+/// Addresses are ordered by their packed u32 form which matches the
+/// order of the address octets
+impl Ord for NetAddress {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.to_u32().cmp(&other.to_u32())
+    }
+}
+
+impl PartialOrd for NetAddress {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Encodable for NetAddress {
+    fn encode(&self, writer: &mut TdfWriter) {
+        writer.write_u32(self.to_u32());
+    }
+}
+
+impl Decodable for NetAddress {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let value = reader.read_u32()?;
+        Ok(Self::from_u32(value))
+    }
+}
+
+value_type!(NetAddress, TdfType::VarInt);
+
+/// Serializes the address using its dotted-quad string form
+#[cfg(feature = "serde")]
+impl Serialize for NetAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserializes the address from its dotted-quad string form
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for NetAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let addr = value
+            .parse::<Ipv4Addr>()
+            .map_err(serde::de::Error::custom)?;
+        Ok(Self(addr))
+    }
+}
+
+/// Wrapper for serializing an address as its big-endian packed u32
+/// form rather than the dotted-quad string form, this matches storage
+/// formats which keep addresses as integers
+#[cfg(feature = "serde")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct NetAddressInt(pub NetAddress);
+
+#[cfg(feature = "serde")]
+impl From<NetAddress> for NetAddressInt {
+    fn from(value: NetAddress) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<NetAddressInt> for NetAddress {
+    fn from(value: NetAddressInt) -> Self {
+        value.0
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for NetAddressInt {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.0.to_u32())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for NetAddressInt {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = u32::deserialize(deserializer)?;
+        Ok(Self(NetAddress::from_u32(value)))
+    }
+}
+
+/// Debug trait implementation sample implementation as the Display
+/// implementation so that is just called instead
+impl Debug for NetAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Display trait implementation for NetAddress. If the value is valid
+/// the value is translated into the IPv4 representation
+impl Display for NetAddress {
+    /// Converts the value stored in this NetAddress to an IPv4 string
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parses an address from its IPv4 dotted quad form (e.g. 127.0.0.1)
+impl TryFrom<&str> for NetAddress {
+    type Error = AddressParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut octets = [0u8; 4];
+        let mut parts = value.split('.');
+        for (index, octet) in octets.iter_mut().enumerate() {
+            let part = parts.next().ok_or(AddressParseError::NotAnAddress)?;
+            if part.is_empty() || !part.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(AddressParseError::NotAnAddress);
+            }
+            // Leading zeros are rejected to match the strict parsing
+            // of Ipv4Addr as they are sometimes read as octal
+            if part.len() > 1 && part.starts_with('0') {
+                return Err(AddressParseError::LeadingZero(index));
+            }
+            *octet = part
+                .parse()
+                .map_err(|_| AddressParseError::OctetOutOfRange(index))?;
+        }
+        if parts.next().is_some() {
+            return Err(AddressParseError::NotAnAddress);
+        }
+        Ok(Self(Ipv4Addr::from(octets)))
+    }
+}
+
+impl FromStr for NetAddress {
+    type Err = AddressParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::try_from(value)
+    }
+}
+
+/// Errors that can occur when parsing a NetAddress
+#[derive(Debug)]
+pub enum AddressParseError {
+    /// The value is not four dot separated numbers
+    NotAnAddress,
+    /// The octet at the provided index is greater than 255
+    OctetOutOfRange(usize),
+    /// The octet at the provided index has a leading zero
+    LeadingZero(usize),
+}
+
+impl Display for AddressParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAnAddress => f.write_str("Value is not an IPv4 address"),
+            Self::OctetOutOfRange(index) => write!(
+                f,
+                "IPv4 address octet {} is out of range (0-255)",
+                index + 1
+            ),
+            Self::LeadingZero(index) => {
+                write!(f, "IPv4 address octet {} has a leading zero", index + 1)
+            }
+        }
+    }
+}
+
+impl Error for AddressParseError {}
+
+/// IPv4 subnet in CIDR notation (e.g. 10.0.0.0/8) used for matching
+/// addresses against a range
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct NetCidr {
+    /// The network address with the host bits cleared
+    network: NetAddress,
+    /// The number of leading network bits (0-32)
+    prefix: u8,
+}
+
+impl NetCidr {
+    /// Creates a subnet from the provided address and prefix length
+    /// clearing the host bits of the address. None is returned if the
+    /// prefix is longer than 32 bits
+    ///
+    /// `address` Any address within the subnet
+    /// `prefix`  The number of leading network bits
+    pub const fn new(address: NetAddress, prefix: u8) -> Option<Self> {
+        if prefix > 32 {
+            return None;
+        }
+        let network = NetAddress::from_u32(address.to_u32() & Self::mask(prefix));
+        Some(Self { network, prefix })
+    }
+
+    /// Returns the network mask for the provided prefix length
+    ///
+    /// `prefix` The number of leading network bits
+    const fn mask(prefix: u8) -> u32 {
+        match prefix {
+            0 => 0,
+            prefix => u32::MAX << (32 - prefix as u32),
+        }
+    }
+
+    /// Returns the network address of this subnet
+    pub const fn network(&self) -> NetAddress {
+        self.network
+    }
+
+    /// Returns the number of leading network bits of this subnet
+    pub const fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Returns whether the provided address is within this subnet
+    ///
+    /// `address` The address to check
+    pub const fn contains(&self, address: NetAddress) -> bool {
+        address.to_u32() & Self::mask(self.prefix) == self.network.to_u32()
+    }
+
+    /// Returns whether the provided IP address is within this subnet,
+    /// IPv6 addresses are only within the subnet when they are IPv4
+    /// mapped addresses
+    ///
+    /// `addr` The address to check
+    pub fn contains_ip(&self, addr: IpAddr) -> bool {
+        NetAddress::from_ip_addr(addr).is_some_and(|address| self.contains(address))
+    }
+}
+
+impl Display for NetCidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Parses a subnet from CIDR notation (e.g. 192.168.0.0/16). A plain
+/// address without a prefix matches only that address
+impl FromStr for NetCidr {
+    type Err = CidrParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => {
+                let prefix = prefix.parse().map_err(|_| CidrParseError::InvalidPrefix)?;
+                (address, prefix)
+            }
+            None => (value, 32),
+        };
+        let address = NetAddress::try_from(address).map_err(CidrParseError::Address)?;
+        Self::new(address, prefix).ok_or(CidrParseError::InvalidPrefix)
+    }
+}
+
+/// Errors that can occur when parsing a NetCidr
+#[derive(Debug)]
+pub enum CidrParseError {
+    /// The address portion is not a valid address
+    Address(AddressParseError),
+    /// The prefix length is not a number between 0 and 32
+    InvalidPrefix,
+}
+
+impl Display for CidrParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Address(err) => Display::fmt(err, f),
+            Self::InvalidPrefix => f.write_str("Subnet prefix length must be between 0 and 32"),
+        }
+    }
+}
+
+impl Error for CidrParseError {}
+
+/// Structure for wrapping an IPv6 networking address. Blaze has no
+/// integer representation for IPv6 addresses so these are encoded
+/// as strings in the same place that a hostname would be
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct NetAddress6(pub Ipv6Addr);
+
+impl Encodable for NetAddress6 {
+    fn encode(&self, writer: &mut TdfWriter) {
+        writer.write_str(&self.0.to_string());
+    }
+}
+
+impl Decodable for NetAddress6 {
+    fn decode(reader: &mut TdfReader) -> DecodeResult<Self> {
+        let value = reader.read_string()?;
+        let addr = value
+            .parse::<Ipv6Addr>()
+            .map_err(|_| DecodeError::Other("Invalid IPv6 address"))?;
+        Ok(Self(addr))
+    }
+}
+
+value_type!(NetAddress6, TdfType::String);
+
+/// Serializes the address using its IPv6 string form
+#[cfg(feature = "serde")]
+impl Serialize for NetAddress6 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserializes the address from its IPv6 string form
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for NetAddress6 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let addr = value
+            .parse::<Ipv6Addr>()
+            .map_err(serde::de::Error::custom)?;
+        Ok(Self(addr))
+    }
+}
+
+/// Debug trait implementation sample implementation as the Display
+/// implementation so that is just called instead
+impl Debug for NetAddress6 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Display trait implementation for NetAddress6 translates the
+/// value into the IPv6 representation
+impl Display for NetAddress6 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Addresses with leading zero octets are rejected and parsed as
+    /// hostnames by InstanceHost like Ipv4Addr does
+    #[test]
+    fn test_leading_zero_address() {
+        assert!(matches!(
+            NetAddress::try_from("010.0.0.1"),
+            Err(AddressParseError::LeadingZero(0))
+        ));
+        assert!("010.0.0.1".parse::<Ipv4Addr>().is_err());
+        assert_eq!(
+            "010.0.0.1".parse::<InstanceHost>().unwrap(),
+            InstanceHost::Host("010.0.0.1".to_string())
+        );
+        assert_eq!(
+            "10.0.0.1".parse::<InstanceHost>().unwrap(),
+            InstanceHost::Address(NetAddress(Ipv4Addr::new(10, 0, 0, 1)))
+        );
+        assert_eq!(
+            NetAddress::try_from("0.0.0.0").unwrap(),
+            NetAddress(Ipv4Addr::UNSPECIFIED)
+        );
+    }
+
+    /// Encodes the contents of a server instance response element with
+    /// the optional XDNS tag only included when provided
+    ///
+    /// `writer` The writer to write the element to
+    /// `host`   The host of the instance
+    /// `xdns`   The XDNS value to include
+    fn write_instance(writer: &mut TdfWriter, host: &str, xdns: Option<bool>) {
+        writer.tag_union_start(b"ADDR", NetworkAddressType::Server.into());
+        writer.tag_value(b"VALU", &InstanceNet::try_new(host, 443).unwrap());
+        writer.tag_bool(b"SECU", true);
+        if let Some(xdns) = xdns {
+            writer.tag_bool(b"XDNS", xdns);
+        }
+    }
+
+    /// Instance details missing the XDNS tag within a server list don't
+    /// take the XDNS tag of the following instance
+    #[test]
+    fn test_server_list_missing_xdns() {
+        let mut writer = TdfWriter::default();
+        writer.tag_list_start(b"LIST", TdfType::Group, 2);
+        write_instance(&mut writer, "first.example.com", None);
+        writer.tag_group_end();
+        write_instance(&mut writer, "second.example.com", Some(true));
+        writer.tag_group_end();
+
+        let mut reader = TdfReader::new(&writer.buffer);
+        let list = ServerList::decode(&mut reader).unwrap();
+        assert_eq!(list.instances.len(), 2);
+        assert_eq!(
+            list.instances[0].address,
+            InstanceAddress::Server(InstanceNet::try_new("first.example.com", 443).unwrap())
+        );
+        assert!(list.instances[0].secure);
+        assert!(!list.instances[0].xdns);
+        assert_eq!(
+            list.instances[1].address,
+            InstanceAddress::Server(InstanceNet::try_new("second.example.com", 443).unwrap())
+        );
+        assert!(list.instances[1].xdns);
+        assert_eq!(reader.cursor, writer.buffer.len());
+    }
+
+    /// Instance details missing the SECU tag within a server list fail
+    /// rather than reading the SECU tag of the following instance
+    #[test]
+    fn test_server_list_missing_secu() {
+        let mut writer = TdfWriter::default();
+        writer.tag_list_start(b"LIST", TdfType::Group, 2);
+        writer.tag_union_start(b"ADDR", NetworkAddressType::Server.into());
+        writer.tag_value(
+            b"VALU",
+            &InstanceNet::try_new("first.example.com", 443).unwrap(),
+        );
+        writer.tag_group_end();
+        write_instance(&mut writer, "second.example.com", Some(false));
+        writer.tag_group_end();
+
+        let mut reader = TdfReader::new(&writer.buffer);
+        assert!(matches!(
+            ServerList::decode(&mut reader),
+            Err(DecodeError::MissingTag { .. })
+        ));
+    }
+
+    /// Instance details are only encoded with the selected optional tags
+    #[test]
+    fn test_instance_tags() {
+        let mut details = InstanceDetails::builder("127.0.0.1", 42128)
+            .secure(true)
+            .build();
+        details.xdns = true;
+
+        let bytes = details.encode_bytes();
+        let mut reader = TdfReader::new(&bytes);
+        assert_eq!(InstanceDetails::decode(&mut reader).unwrap(), details);
+
+        let tags = InstanceTags {
+            secu: true,
+            xdns: false,
+        };
+        let bytes = details.with_tags(tags).encode_bytes();
+        let mut reader = TdfReader::new(&bytes);
+        let decoded = InstanceDetails::decode(&mut reader).unwrap();
+        assert!(decoded.secure);
+        assert!(!decoded.xdns);
+
+        let tags = InstanceTags {
+            secu: false,
+            xdns: false,
+        };
+        let bytes = details.with_tags(tags).encode_bytes();
+        let mut reader = TdfReader::new(&bytes);
+        assert!(matches!(
+            InstanceDetails::decode(&mut reader),
+            Err(DecodeError::MissingTag { .. })
+        ));
+    }
+
+    /// Unsupported address types fail the decode unless it is lenient
+    /// in which case the address is replaced with an unspecified one
+    #[test]
+    fn test_lenient_address_decode() {
+        let mut writer = TdfWriter::default();
+        writer.tag_union_start(b"ADDR", NetworkAddressType::Unknown(0x7).into());
+        writer.tag_str(b"VALU", "unsupported");
+        writer.tag_bool(b"SECU", false);
+        writer.tag_bool(b"XDNS", false);
+
+        let mut reader = TdfReader::new(&writer.buffer);
+        assert!(matches!(
+            InstanceDetails::decode(&mut reader),
+            Err(DecodeError::Other(_))
+        ));
+
+        let mut reader = TdfReader::new(&writer.buffer);
+        let details = InstanceDetails::decode_with(&mut reader, true).unwrap();
+        assert_eq!(
+            details.address,
+            InstanceAddress::Server(InstanceNet::unspecified())
+        );
+        assert_eq!(reader.cursor, writer.buffer.len());
+    }
+
+    /// Networking information round trips for both host forms including
+    /// ports at the VarInt length boundaries
+    #[test]
+    fn test_instance_net_round_trip() {
+        for host in ["gosredirector.ea.com", "10.0.0.1"] {
+            for port in [1, 63, 64, 8191, 8192, Port::MAX] {
+                let net = InstanceNet::try_new(host, port).unwrap();
+                let mut writer = TdfWriter::default();
+                writer.tag_value(b"VALU", &net);
+                let mut reader = TdfReader::new(&writer.buffer);
+                assert_eq!(reader.tag::<InstanceNet>("VALU").unwrap(), net);
+                assert_eq!(reader.cursor, writer.buffer.len());
+            }
+        }
+    }
+
+    /// Strategy for hosts of either variant. Hostnames are generated
+    /// already normalized as they are decoded as is
+    fn instance_host() -> impl Strategy<Value = InstanceHost> {
+        prop_oneof![
+            "[a-z0-9]([a-z0-9-]{0,14}[a-z0-9])?(\\.[a-z0-9]([a-z0-9-]{0,14}[a-z0-9])?){0,3}"
+                .prop_map(InstanceHost::Host),
+            any::<u32>().prop_map(|value| InstanceHost::Address(NetAddress(Ipv4Addr::from(value)))),
+        ]
+    }
+
+    /// Strategy for instance networking details. Ports are drawn evenly
+    /// from each VarInt length so the length boundaries are covered and
+    /// port zero is left out as it is rejected
+    fn instance_net() -> impl Strategy<Value = InstanceNet> {
+        let port = prop_oneof![1..64 as Port, 64..8192 as Port, 8192..=Port::MAX];
+        (instance_host(), port).prop_map(|(host, port)| InstanceNet { host, port })
+    }
+
+    /// Strategy for instance details with each network address type
+    fn instance_details() -> impl Strategy<Value = InstanceDetails> {
+        let address = prop_oneof![
+            instance_net().prop_map(InstanceAddress::Server),
+            instance_net().prop_map(InstanceAddress::Client),
+            (instance_net(), instance_net()).prop_map(|(internal, external)| {
+                InstanceAddress::Pair(NetworkPair { internal, external })
+            }),
+            instance_net().prop_map(InstanceAddress::IpAddress),
+            instance_net().prop_map(InstanceAddress::HostnameAddress),
+        ];
+        (address, any::<bool>(), any::<bool>()).prop_map(|(address, secure, xdns)| {
+            InstanceDetails {
+                address,
+                secure,
+                xdns,
+            }
+        })
+    }
+
+    proptest! {
+        /// Hosts decode from within a group to the encoded host
+        #[test]
+        fn prop_instance_host_round_trip(host in instance_host()) {
+            let mut writer = TdfWriter::default();
+            host.encode(&mut writer);
+            writer.tag_group_end();
+            let mut reader = TdfReader::new(&writer.buffer);
+            prop_assert_eq!(InstanceHost::decode(&mut reader).unwrap(), host);
+            prop_assert_eq!(reader.cursor, writer.buffer.len() - 1);
+        }
+
+        /// Networking details decode to the encoded details and consume
+        /// the whole group
+        #[test]
+        fn prop_instance_net_round_trip(net in instance_net()) {
+            let mut writer = TdfWriter::default();
+            writer.tag_value(b"VALU", &net);
+            let mut reader = TdfReader::new(&writer.buffer);
+            prop_assert_eq!(reader.tag::<InstanceNet>("VALU").unwrap(), net);
+            prop_assert_eq!(reader.cursor, writer.buffer.len());
+        }
+
+        /// Instance details decode to the encoded details and consume all
+        /// of their tags
+        #[test]
+        fn prop_instance_details_round_trip(details in instance_details()) {
+            let bytes = details.encode_bytes();
+            let mut reader = TdfReader::new(&bytes);
+            prop_assert_eq!(InstanceDetails::decode(&mut reader).unwrap(), details);
+            prop_assert_eq!(reader.cursor, bytes.len());
+        }
+    }
+
+    /// The group terminator is consumed when present and a missing
+    /// terminator leaves the following bytes to be read
+    #[test]
+    fn test_group_terminator() {
+        let net = InstanceNet::try_new("gosredirector.ea.com", 42127).unwrap();
+
+        let mut writer = TdfWriter::default();
+        net.encode(&mut writer);
+        writer.tag_bool(b"SECU", true);
+        let mut reader = TdfReader::new(&writer.buffer);
+        assert_eq!(InstanceNet::decode(&mut reader).unwrap(), net);
+        assert!(reader.tag::<bool>("SECU").unwrap());
+
+        let mut writer = TdfWriter::default();
+        net.host.encode(&mut writer);
+        writer.tag_u16(b"PORT", net.port);
+        let end = writer.buffer.len();
+        writer.tag_bool(b"SECU", true);
+        let mut reader = TdfReader::new(&writer.buffer);
+        assert_eq!(InstanceNet::decode(&mut reader).unwrap(), net);
+        assert_eq!(reader.cursor, end);
+        assert!(reader.tag::<bool>("SECU").unwrap());
+
+        // A missing terminator at the end of the contents
+        let mut reader = TdfReader::new(&writer.buffer[..end]);
+        assert_eq!(InstanceNet::decode(&mut reader).unwrap(), net);
+        assert_eq!(reader.cursor, end);
+    }
+
+    /// Unknown tags written after the port are skipped up to the group
+    /// terminator so the tags following the group are still decoded
+    #[test]
+    fn test_group_trailing_tags() {
+        let net = InstanceNet::try_new("gosredirector.ea.com", 42127).unwrap();
+
+        let mut writer = TdfWriter::default();
+        writer.tag_union_start(b"ADDR", NetworkAddressType::Server.into());
+        writer.tag_group(b"VALU");
+        net.host.encode(&mut writer);
+        writer.tag_u16(b"PORT", net.port);
+        writer.tag_str(b"NAME", "redirector");
+        writer.tag_u32(b"FLAG", 3);
+        writer.tag_group_end();
+        writer.tag_bool(b"SECU", true);
+        writer.tag_bool(b"XDNS", false);
+
+        let mut reader = TdfReader::new(&writer.buffer);
+        let details = InstanceDetails::decode(&mut reader).unwrap();
+        assert_eq!(details.address, InstanceAddress::Server(net));
+        assert!(details.secure);
+        assert_eq!(reader.cursor, writer.buffer.len());
+    }
+
+    /// A group without a host fails rather than reading the HOST tag of
+    /// the group that follows it
+    #[test]
+    fn test_host_less_group() {
+        let mut writer = TdfWriter::default();
+        writer.tag_group(b"VALU");
+        writer.tag_u16(b"PORT", 42127);
+        writer.tag_group_end();
+        writer.tag_value(
+            b"NEXT",
+            &InstanceNet::try_new("second.example.com", 443).unwrap(),
+        );
+
+        let mut reader = TdfReader::new(&writer.buffer);
+        reader.until_tag("VALU", TdfType::Group).unwrap();
+        assert!(matches!(
+            InstanceNet::decode(&mut reader),
+            Err(DecodeError::MissingTag { tag, .. }) if tag == "HOST"
+        ));
+    }
+
+    /// Addresses are encoded as the VarInt of their big-endian packed
+    /// form, the expected bytes are the wire encoding of that value
+    #[test]
+    fn test_net_address_wire_bytes() {
+        let cases = [
+            (
+                NetAddress(Ipv4Addr::new(127, 0, 0, 1)),
+                [0x81, 0x80, 0x80, 0xF0, 0x0F],
+            ),
+            (
+                NetAddress(Ipv4Addr::new(255, 254, 253, 252)),
+                [0xBC, 0xF7, 0xF7, 0xFF, 0x1F],
+            ),
+        ];
+        for (address, bytes) in cases {
+            assert_eq!(address.encode_bytes(), bytes);
+            let mut reader = TdfReader::new(&bytes);
+            assert_eq!(NetAddress::decode(&mut reader).unwrap(), address);
+            assert_eq!(reader.cursor, bytes.len());
+        }
+    }
+
+    /// Subnets at both ends of the prefix range, IPv4 mapped addresses
+    /// and prefixes that are out of range or aren't numbers
+    #[test]
+    fn test_net_cidr() {
+        let all: NetCidr = "10.1.2.3/0".parse().unwrap();
+        assert_eq!(all.network(), NetAddress(Ipv4Addr::UNSPECIFIED));
+        assert!(all.contains(NetAddress(Ipv4Addr::new(255, 255, 255, 255))));
+        assert!(all.contains(NetAddress(Ipv4Addr::UNSPECIFIED)));
+
+        let single: NetCidr = "10.1.2.3/32".parse().unwrap();
+        assert!(single.contains(NetAddress(Ipv4Addr::new(10, 1, 2, 3))));
+        assert!(!single.contains(NetAddress(Ipv4Addr::new(10, 1, 2, 4))));
+        assert_eq!(single.to_string(), "10.1.2.3/32");
+
+        // A plain address matches only itself
+        let plain: NetCidr = "10.1.2.3".parse().unwrap();
+        assert_eq!(plain.prefix(), 32);
+
+        let subnet: NetCidr = "192.168.1.77/24".parse().unwrap();
+        assert_eq!(subnet.to_string(), "192.168.1.0/24");
+        assert!(subnet.contains_ip("::ffff:192.168.1.20".parse().unwrap()));
+        assert!(!subnet.contains_ip("::ffff:192.168.2.20".parse().unwrap()));
+        assert!(!subnet.contains_ip("fe80::1".parse().unwrap()));
+
+        for value in ["10.0.0.0/33", "10.0.0.0/-1", "10.0.0.0/", "10.0.0.0/a"] {
+            assert!(matches!(
+                value.parse::<NetCidr>(),
+                Err(CidrParseError::InvalidPrefix)
+            ));
+        }
+        assert!(matches!(
+            "::ffff:10.0.0.0/8".parse::<NetCidr>(),
+            Err(CidrParseError::Address(_))
+        ));
+    }
+
+    /// Hostnames are lowercased and have a single trailing dot removed
+    /// while internationalized names only have their ASCII lowercased
+    #[test]
+    fn test_hostname_normalization() {
+        let host = |value: &str| value.parse::<InstanceHost>().unwrap();
+        assert_eq!(
+            host("Server.Example.Com."),
+            InstanceHost::Host("server.example.com".to_string())
+        );
+        assert_eq!(
+            host("GOSREDIRECTOR.EA.COM"),
+            InstanceHost::Host("gosredirector.ea.com".to_string())
+        );
+        // Only a single trailing dot is removed and a lone dot is kept
+        assert_eq!(
+            host("example.com.."),
+            InstanceHost::Host("example.com.".to_string())
+        );
+        assert_eq!(host("."), InstanceHost::Host(".".to_string()));
+        assert_eq!(
+            host("XN--Bcher-KVA.Example."),
+            InstanceHost::Host("xn--bcher-kva.example".to_string())
+        );
+        assert_eq!(
+            host("BÜcher.example"),
+            InstanceHost::Host("bÜcher.example".to_string())
+        );
+        // Addresses aren't affected
+        assert_eq!(
+            host("10.0.0.1"),
+            InstanceHost::Address(NetAddress(Ipv4Addr::new(10, 0, 0, 1)))
+        );
+    }
+
+    /// Serializes the provided value to JSON and asserts that the JSON
+    /// matches and deserializes back to an equal value
+    ///
+    /// `value` The value to round trip
+    /// `json`  The expected JSON form of the value
+    #[cfg(feature = "serde")]
+    fn assert_serde_round_trip<V>(value: V, json: &str)
+    where
+        V: Serialize + for<'de> Deserialize<'de> + PartialEq + Debug,
+    {
+        let serialized = serde_json::to_string(&value).unwrap();
+        assert_eq!(serialized, json);
+        assert_eq!(serde_json::from_str::<V>(&serialized).unwrap(), value);
+    }
+
+    /// Tests that addresses serialize as their dotted-quad string
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_net_address() {
+        assert_serde_round_trip(NetAddress(Ipv4Addr::new(127, 0, 0, 1)), "\"127.0.0.1\"");
+        assert!(serde_json::from_str::<NetAddress>("2130706433").is_err());
+    }
+
+    /// Tests that the integer wrapper uses the packed u32 form and that
+    /// both forms convert to the same address
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_net_address_int() {
+        let address = NetAddress(Ipv4Addr::new(127, 0, 0, 1));
+        assert_serde_round_trip(NetAddressInt(address), "2130706433");
+        assert_serde_round_trip(
+            NetAddressInt(NetAddress(Ipv4Addr::new(255, 254, 253, 252))),
+            "4294901244",
+        );
+        assert!(serde_json::from_str::<NetAddressInt>("\"127.0.0.1\"").is_err());
+
+        let string: NetAddress = serde_json::from_str("\"127.0.0.1\"").unwrap();
+        let int: NetAddressInt = serde_json::from_str("2130706433").unwrap();
+        assert_eq!(NetAddress::from(int), string);
+        assert_eq!(NetAddressInt::from(string), int);
+    }
+
+    /// Tests that the unknown network address type keeps its value
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_network_address_type() {
+        assert_serde_round_trip(NetworkAddressType::HostnameAddress, "\"HostnameAddress\"");
+        assert_serde_round_trip(NetworkAddressType::Unknown(0x7), "{\"Unknown\":7}");
+    }
+
+    /// Tests both the hostname and address forms of the host
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_instance_host() {
+        assert_serde_round_trip(
+            InstanceHost::Host("gosredirector.ea.com".to_string()),
+            "{\"Host\":\"gosredirector.ea.com\"}",
+        );
+        assert_serde_round_trip(
+            InstanceHost::Address(NetAddress(Ipv4Addr::new(10, 0, 0, 1))),
+            "{\"Address\":\"10.0.0.1\"}",
+        );
+    }
+
+    /// Tests the networking information and the instance details
+    /// containing it
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_instance_details() {
+        let net = InstanceNet::try_new("10.0.0.1", 42127).unwrap();
+        assert_serde_round_trip(
+            net.clone(),
+            "{\"host\":{\"Address\":\"10.0.0.1\"},\"port\":42127}",
+        );
+        assert_serde_round_trip(
+            InstanceDetails::new(InstanceAddress::Server(net), true),
+            "{\"address\":{\"Server\":{\"host\":{\"Address\":\"10.0.0.1\"},\"port\":42127}},\
+             \"secure\":true,\"xdns\":false}",
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Module for exporting the tracing spans to an OpenTelemetry collector
+//! using `tracing-opentelemetry`. Each session span starts a new trace and
+//! its id is set as the `session.id` attribute on every span within it so
+//! a login flow can be followed end to end
+
+use crate::VERSION;
+use log::warn;
+use opentelemetry::{
+    trace::{TraceContextExt, TracerProvider as _},
+    InstrumentationScope, KeyValue,
+};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    trace::{BatchConfigBuilder, BatchSpanProcessor, SdkTracerProvider, Tracer},
+    Resource,
+};
+use std::{sync::OnceLock, time::Duration};
+use tracing::{
+    dispatcher::WeakDispatch,
+    field::{Field, Visit},
+    span, Dispatch, Subscriber,
+};
+use tracing_subscriber::{filter::filter_fn, layer::Context, registry::LookupSpan, Layer};
+
+/// The name of the service the spans are exported as
+const SERVICE_NAME: &str = "pocket-relay-mitm";
+/// The maximum number of closed spans waiting to be exported, spans
+/// closed while the queue is full are dropped
+const MAX_QUEUE_SIZE: usize = 2048;
+/// The maximum number of spans sent in one export request
+const MAX_BATCH_SIZE: usize = 512;
+/// The maximum time a span waits before its batch is exported
+const BATCH_DELAY: Duration = Duration::from_secs(5);
+
+/// The provider of the tracer the spans are exported with, kept so that
+/// the queued spans can be exported before the process exits
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Creates the layer exporting the spans of this crate in batches to the
+/// provided OTLP/HTTP traces endpoint. The batches are exported from
+/// their own thread so closing a span never waits on the collector
+///
+/// `endpoint` The URL of the collector traces endpoint
+pub fn layer<S>(endpoint: &str) -> Result<impl Layer<S>, ExporterBuildError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let config = BatchConfigBuilder::default()
+        .with_max_queue_size(MAX_QUEUE_SIZE)
+        .with_max_export_batch_size(MAX_BATCH_SIZE)
+        .with_scheduled_delay(BATCH_DELAY)
+        .build();
+    let processor = BatchSpanProcessor::builder(exporter)
+        .with_batch_config(config)
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_span_processor(processor)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+    let tracer = session_tracer(&provider);
+    let _ = PROVIDER.set(provider);
+    Ok(tracer_layer(tracer))
+}
+
+/// Exports the spans that are still queued, the export requests are
+/// waited on so this is called once the servers have stopped
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(err) = provider.shutdown() {
+            warn!("Failed to export the remaining spans: {err}");
+        }
+    }
+}
+
+/// Creates the tracer the spans are exported with from the provided
+/// provider
+///
+/// `provider` The provider to create the tracer from
+fn session_tracer(provider: &SdkTracerProvider) -> Tracer {
+    provider.tracer_with_scope(
+        InstrumentationScope::builder(SERVICE_NAME)
+            .with_version(VERSION)
+            .build(),
+    )
+}
+
+/// Creates the layer exporting the spans of this crate with the provided
+/// tracer and setting their session attribute
+///
+/// `tracer` The tracer to export the spans with
+fn tracer_layer<S>(tracer: Tracer) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        // Spans from dependencies such as the HTTP client making the
+        // export requests aren't exported
+        .with_filter(filter_fn(|metadata| {
+            metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+        }))
+        .and_then(SessionLayer::default())
+}
+
+/// The id of the session a span is within, stored in its extensions so
+/// that the spans created within it can inherit it
+struct SessionId(u64);
+
+/// Layer setting the `session.id` attribute on each session span and the
+/// spans within them. It runs after the `tracing-opentelemetry` layer so
+/// the exported span already exists when the attribute is set
+#[derive(Default)]
+struct SessionLayer {
+    /// The dispatcher the layer is registered with which the exported
+    /// span of each span is looked up through
+    dispatch: OnceLock<WeakDispatch>,
+}
+
+impl<S> Layer<S> for SessionLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_register_dispatch(&self, dispatch: &Dispatch) {
+        let _ = self.dispatch.set(dispatch.downgrade());
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let session = match span.name() {
+            "session" => {
+                let mut visitor = SessionVisitor(None);
+                attrs.record(&mut visitor);
+                visitor.0
+            }
+            _ => span.parent().and_then(|parent| {
+                parent
+                    .extensions()
+                    .get::<SessionId>()
+                    .map(|session| session.0)
+            }),
+        };
+        let Some(session) = session else {
+            return;
+        };
+        span.extensions_mut().insert(SessionId(session));
+
+        let Some(dispatch) = self.dispatch.get().and_then(WeakDispatch::upgrade) else {
+            return;
+        };
+        // Spans that are filtered out of the export have no context
+        if let Some(context) = tracing_opentelemetry::get_otel_context(id, &dispatch) {
+            context
+                .span()
+                .set_attribute(KeyValue::new("session.id", session as i64));
+        }
+    }
+}
+
+/// Visitor reading the `id` field of a session span
+struct SessionVisitor(Option<u64>);
+
+impl Visit for SessionVisitor {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "id" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if field.name() == "id" {
+            self.0 = u64::try_from(value).ok();
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::{trace::SpanId, Value};
+    use opentelemetry_sdk::{
+        error::OTelSdkResult,
+        trace::{SpanData, SpanExporter},
+    };
+    use std::sync::{Arc, Mutex};
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Exporter storing the exported spans
+    #[derive(Clone, Debug, Default)]
+    struct CollectExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for CollectExporter {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    /// Returns the value of the provided attribute of the span
+    ///
+    /// `span` The exported span
+    /// `key`  The key of the attribute
+    fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| &attribute.value)
+    }
+
+    /// Tests that the spans within a session share its trace and carry
+    /// its id while spans outside of it start their own trace and spans
+    /// of other crates aren't exported
+    #[test]
+    fn test_session_trace() {
+        let exporter = CollectExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber =
+            tracing_subscriber::registry().with(tracer_layer(session_tracer(&provider)));
+        tracing::subscriber::with_default(subscriber, || {
+            let session = info_span!("session", id = 7u64);
+            let _session = session.enter();
+            let connect = info_span!("connect", host = "example.com");
+            connect.in_scope(|| {
+                let _decode = info_span!("decode").entered();
+                let _request = info_span!(target: "hyper", "request").entered();
+            });
+            drop(connect);
+            drop(_session);
+            drop(session);
+            let _other = info_span!("other").entered();
+        });
+
+        let spans = exporter.0.lock().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, ["decode", "connect", "session", "other"]);
+
+        let (decode, connect, session, other) = (&spans[0], &spans[1], &spans[2], &spans[3]);
+        let trace_id = session.span_context.trace_id();
+        assert_eq!(session.parent_span_id, SpanId::INVALID);
+        assert_eq!(connect.parent_span_id, session.span_context.span_id());
+        assert_eq!(decode.parent_span_id, connect.span_context.span_id());
+        for span in [decode, connect, session] {
+            assert_eq!(span.span_context.trace_id(), trace_id);
+            assert_eq!(attribute(span, "session.id"), Some(&Value::I64(7)));
+        }
+        assert_eq!(attribute(other, "session.id"), None);
+        assert_ne!(other.span_context.trace_id(), trace_id);
+        assert_eq!(
+            attribute(connect, "host"),
+            Some(&Value::from("example.com"))
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Module for rewriting the QoS ping sites that clients are told to
+//! measure their latency against during login and the latencies that
+//! clients report back so that players can be kept routed through a
+//! specific region
+
+use crate::{
+    components::{Components, UserSessions, Util},
+    config::{QosConfig, QosTarget},
+    intercept::{Action, PacketInterceptor, SessionInfo},
+    mitm::Direction,
+    models::{InstanceHost, QosLatencyReport, QosPingSite, QosSettings},
+};
+use async_trait::async_trait;
+use blaze_pk::packet::{Packet, PacketComponents, PacketType};
+use log::{debug, warn};
+use std::collections::HashMap;
+
+/// Interceptor which replaces the QoS ping sites in the PreAuth
+/// response with the configured targets and the latencies reported
+/// by clients with the configured latencies
+pub struct QosRewriter {
+    /// Replacement for the bandwidth ping site
+    bandwidth: Option<QosTarget>,
+    /// Replacements for the latency ping sites keyed by site identifier
+    sites: HashMap<String, QosTarget>,
+    /// Replacements for the reported latencies keyed by site identifier
+    latencies: HashMap<String, u32>,
+}
+
+impl QosRewriter {
+    /// Creates a new rewriter from the provided config if there
+    /// are any ping sites to replace
+    ///
+    /// `config` The QoS configuration
+    pub fn new(config: &QosConfig) -> Option<Self> {
+        if config.bandwidth.is_none() && config.sites.is_empty() && config.latencies.is_empty() {
+            return None;
+        }
+        Some(Self {
+            bandwidth: config.bandwidth.clone(),
+            sites: config.sites.clone(),
+            latencies: config.latencies.clone(),
+        })
+    }
+
+    /// Replaces the configured ping sites within the provided settings
+    ///
+    /// `settings` The QoS settings to modify
+    fn apply(&self, settings: &mut QosSettings) {
+        if let Some(target) = &self.bandwidth {
+            replace_site(&mut settings.bandwidth_site, target);
+        }
+        for (key, site) in &mut settings.latency_sites {
+            if let Some(target) = self.sites.get(key) {
+                replace_site(site, target);
+            }
+        }
+    }
+
+    /// Replaces the configured latencies within the provided report
+    ///
+    /// `report` The latency report to modify
+    fn apply_latencies(&self, report: &mut QosLatencyReport) {
+        for (key, latency) in &mut report.latencies {
+            if let Some(replacement) = self.latencies.get(key) {
+                debug!(
+                    "Replacing reported QoS latency for {key} ({latency}ms) with {replacement}ms"
+                );
+                *latency = *replacement;
+            }
+        }
+    }
+}
+
+/// Replaces the host and port of the provided ping site
+///
+/// `site`   The ping site to replace
+/// `target` The replacement target
+fn replace_site(site: &mut QosPingSite, target: &QosTarget) {
+    debug!(
+        "Replacing QoS ping site {} (Host: {}, Port: {}) with (Host: {}, Port: {})",
+        site.name, site.host, site.port, target.host, target.port
+    );
+    site.host = InstanceHost::from(target.host.clone());
+    site.port = target.port;
+}
+
+#[async_trait]
+impl PacketInterceptor for QosRewriter {
+    async fn on_packet(
+        &self,
+        _session: &SessionInfo,
+        direction: Direction,
+        packet: &mut Packet,
+    ) -> Action {
+        let is_pre_auth = matches!(direction, Direction::ServerToClient)
+            && packet.header.ty == PacketType::Response
+            && Components::from_header(&packet.header) == Components::Util(Util::PreAuth);
+        if is_pre_auth {
+            if let Err(err) = QosSettings::rewrite(packet, |settings| self.apply(settings)) {
+                warn!("Failed to rewrite QoS ping sites: {err:?}");
+            }
+        }
+
+        let is_network_info = !self.latencies.is_empty()
+            && matches!(direction, Direction::ClientToServer)
+            && packet.header.ty == PacketType::Request
+            && Components::from_header(&packet.header)
+                == Components::UserSessions(UserSessions::UpdateNetworkInfo);
+        if is_network_info {
+            if let Err(err) =
+                QosLatencyReport::rewrite(packet, |report| self.apply_latencies(report))
+            {
+                warn!("Failed to rewrite QoS latencies: {err:?}");
+            }
+        }
+        Action::Forward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blaze_pk::codec::Encodable;
+
+    /// PreAuth response contents containing the QoS settings
+    const PRE_AUTH: &[u8] = include_bytes!("../tests/synthetic/pre_auth.bin");
+    /// The PreAuth response contents with the ea-sjc ping site replaced
+    const PRE_AUTH_REWRITTEN: &[u8] = include_bytes!("../tests/synthetic/pre_auth_rewritten.bin");
+    /// UpdateNetworkInfo request contents containing the latency report
+    const UPDATE_NETWORK_INFO: &[u8] = include_bytes!("../tests/synthetic/update_network_info.bin");
+    /// The UpdateNetworkInfo request contents with the rs-iad latency
+    /// replaced
+    const UPDATE_NETWORK_INFO_REWRITTEN: &[u8] =
+        include_bytes!("../tests/synthetic/update_network_info_rewritten.bin");
+
+    /// Session info for the client sending the packets
+    fn session_info() -> SessionInfo {
+        SessionInfo {
+            id: 1,
+            addr: "127.0.0.1:3659".parse().unwrap(),
+            captured: false,
+            sequences: None,
+        }
+    }
+
+    /// Replacing a latency ping site only changes the bytes of that
+    /// site and leaves the rest of the response as is
+    #[tokio::test]
+    async fn test_rewrite_pre_auth() {
+        let config: QosConfig = serde_json::from_str(
+            r#"{ "sites": { "ea-sjc": { "host": "10.0.0.2", "port": 42129 } } }"#,
+        )
+        .unwrap();
+        let rewriter = QosRewriter::new(&config).unwrap();
+        let session = session_info();
+
+        let request = Packet::request_empty(1, Components::Util(Util::PreAuth));
+        let mut packet = Packet::response_raw(&request, PRE_AUTH.to_vec());
+        let action = rewriter
+            .on_packet(&session, Direction::ServerToClient, &mut packet)
+            .await;
+        assert!(matches!(action, Action::Forward));
+        assert_eq!(&packet.contents[..], PRE_AUTH_REWRITTEN);
+
+        // The same packet sent by the client is left unchanged
+        let mut packet = Packet::request_raw(1, Components::Util(Util::PreAuth), PRE_AUTH.to_vec());
+        rewriter
+            .on_packet(&session, Direction::ClientToServer, &mut packet)
+            .await;
+        assert_eq!(&packet.contents[..], PRE_AUTH);
+    }
+
+    /// The latency report decodes from within the network info group and
+    /// encodes back to the same bytes
+    #[test]
+    fn test_latency_report_round_trip() {
+        let (report, range) = QosLatencyReport::find(UPDATE_NETWORK_INFO).unwrap();
+        assert_eq!(
+            report.latencies,
+            [
+                ("ea-sjc".to_string(), 45),
+                ("rs-iad".to_string(), 120),
+                ("rs-lhr".to_string(), 210)
+            ]
+        );
+        assert_eq!(report.encode_bytes(), &UPDATE_NETWORK_INFO[range]);
+    }
+
+    /// Replacing a reported latency only changes the bytes of that
+    /// latency and leaves the rest of the request as is
+    #[tokio::test]
+    async fn test_rewrite_latencies() {
+        let config: QosConfig =
+            serde_json::from_str(r#"{ "latencies": { "rs-iad": 20 } }"#).unwrap();
+        let rewriter = QosRewriter::new(&config).unwrap();
+
+        let mut packet = Packet::request_raw(
+            1,
+            Components::UserSessions(UserSessions::UpdateNetworkInfo),
+            UPDATE_NETWORK_INFO.to_vec(),
+        );
+        rewriter
+            .on_packet(&session_info(), Direction::ClientToServer, &mut packet)
+            .await;
+        assert_eq!(&packet.contents[..], UPDATE_NETWORK_INFO_REWRITTEN);
+    }
+}
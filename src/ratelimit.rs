@@ -0,0 +1,164 @@
+//! Module for rate limiting clients using a token bucket for each
+//! client address
+
+use crate::{config::RateLimitConfig, metrics::METRICS};
+use log::warn;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::time;
+
+/// The number of buckets after which full buckets are removed
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Token bucket for a single client
+struct TokenBucket {
+    /// The number of tokens currently available
+    tokens: f64,
+    /// The last time the tokens were refilled
+    refilled: Instant,
+}
+
+/// Rate limiter which allows each client address to perform a number of
+/// actions per second with an additional burst allowance
+pub struct RateLimiter {
+    /// The number of tokens restored each second
+    rate: f64,
+    /// The maximum number of tokens a bucket can hold
+    burst: f64,
+    /// The buckets for each client address
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter
+    ///
+    /// `rate`  The number of actions allowed per second
+    /// `burst` The maximum number of actions allowed at once
+    pub fn new(rate: u32, burst: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Attempts to take a token for the provided client address. If
+    /// the client has no tokens remaining then the time until the
+    /// next token is available is returned as the error
+    ///
+    /// `addr` The address of the client
+    pub fn check(&self, addr: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = match self.buckets.lock() {
+            Ok(value) => value,
+            Err(err) => err.into_inner(),
+        };
+
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(addr).or_insert(TokenBucket {
+            tokens: self.burst,
+            refilled: now,
+        });
+        let tokens = self.refill(bucket, now);
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            return Ok(());
+        }
+        if self.rate <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64((1.0 - tokens) / self.rate))
+    }
+
+    /// Refills the provided bucket based on the time elapsed since
+    /// it was last refilled returning the available tokens
+    ///
+    /// `bucket` The bucket to refill
+    /// `now`    The current time
+    fn refill(&self, bucket: &mut TokenBucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled = now;
+        bucket.tokens
+    }
+}
+
+/// Rate limiters for the connections and packets of clients
+pub struct ClientLimits {
+    /// Limiter for new connections
+    connections: RateLimiter,
+    /// Limiter for packets sent by clients
+    packets: RateLimiter,
+}
+
+impl ClientLimits {
+    /// Creates the client limits from the provided config returning
+    /// None if rate limiting is disabled
+    ///
+    /// `config` The rate limiting config
+    pub fn from_config(config: &RateLimitConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        Some(Self {
+            connections: RateLimiter::new(config.connections_per_sec, config.connection_burst),
+            packets: RateLimiter::new(config.packets_per_sec, config.packet_burst),
+        })
+    }
+
+    /// Checks whether a new connection from the provided address is
+    /// allowed. Rejected connections are logged and counted
+    ///
+    /// `addr` The address of the client
+    pub fn allow_connection(&self, addr: IpAddr) -> bool {
+        if self.connections.check(addr).is_ok() {
+            return true;
+        }
+        warn!("Rejected connection from rate limited client (Addr: {addr})");
+        METRICS.rate_limited();
+        false
+    }
+}
+
+/// Packet rate limit for a single client connection
+#[derive(Clone)]
+pub struct PacketLimit {
+    /// The shared client limits
+    limits: Arc<ClientLimits>,
+    /// The address of the client
+    addr: IpAddr,
+}
+
+impl PacketLimit {
+    /// Creates a new packet limit for the provided client
+    ///
+    /// `limits` The shared client limits
+    /// `addr`   The address of the client
+    pub fn new(limits: Arc<ClientLimits>, addr: IpAddr) -> Self {
+        Self { limits, addr }
+    }
+
+    /// Waits until the client is allowed to send another packet.
+    /// Delayed packets are logged and counted
+    pub async fn wait(&self) {
+        let mut limited = false;
+        while let Err(delay) = self.limits.packets.check(self.addr) {
+            if !limited {
+                limited = true;
+                warn!(
+                    "Delaying packet from rate limited client (Addr: {})",
+                    self.addr
+                );
+                METRICS.rate_limited();
+            }
+            time::sleep(delay).await;
+        }
+    }
+}
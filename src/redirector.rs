@@ -3,45 +3,184 @@
 
 use crate::{
     components::{Components, Redirector},
-    models::{InstanceDetails, InstanceNet},
-    EXTERNAL_HOST, MAIN_PORT, REDIRECTOR_PORT,
+    config::{
+        Config, RedirectConfig, RedirectRoute, RedirectTarget, RedirectorConfig, SharedRedirect,
+        UnspecifiedConfig,
+    },
+    dns,
+    events::{self, Event, SessionEvent},
+    login::{self, LoginStage, LoginTracker},
+    metrics::METRICS,
+    mitm::read_packet,
+    models::{
+        IdentityProfile, InstanceAddress, InstanceDetails, InstanceHost, InstanceNet,
+        InstanceRequest, NetAddress, ServerInstanceError,
+    },
+    ratelimit::ClientLimits,
+    retriever::Retriever,
 };
-use blaze_pk::packet::Packet;
-use blaze_ssl_async::{BlazeAccept, BlazeListener};
-use log::{debug, error, info};
-use std::io;
-use tokio::{io::AsyncWriteExt, select};
+use blaze_pk::packet::{Packet, PacketComponents};
+use blaze_ssl_async::{
+    data::{BlazeServerData, Certificate, DecodePrivateKey, RsaPrivateKey},
+    BlazeAccept, BlazeError, BlazeListener,
+};
+use log::{debug, error, info, warn};
+use std::{
+    fs, io,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream, select, time};
+use tokio_util::sync::CancellationToken;
 
 /// Starts the Redirector server this server is what the Mass Effect 3 game
 /// client initially reaches out to. This server is responsible for telling
 /// the client where the server is and whether it should use SSLv3 to connect.
-pub async fn start_server() {
-    // Initializing the underlying TCP listener
-    let listener = {
-        let port = REDIRECTOR_PORT;
-        match BlazeListener::bind(("0.0.0.0", port)).await {
-            Ok(value) => {
-                info!("Started Redirector server (Port: {})", port);
-                value
-            }
-            Err(_) => {
-                error!("Failed to bind Redirector server (Port: {})", port);
-                panic!()
-            }
+///
+/// `listeners` The listeners bound to the configured addresses
+/// `config`    The server configuration
+/// `redirect`  The redirect configuration which may be reloaded
+/// `retriever` The retriever used for checking the official server
+/// `limits`    The optional rate limits for clients
+/// `shutdown`  Token cancelled when the server is shutting down
+pub async fn start_server(
+    listeners: Vec<BlazeListener>,
+    config: Arc<Config>,
+    redirect: Arc<SharedRedirect>,
+    retriever: Arc<Retriever>,
+    limits: Option<Arc<ClientLimits>>,
+    shutdown: CancellationToken,
+) {
+    warn_local_targets(&redirect.current()).await;
+
+    let data = server_data(&config.redirector);
+    for addr in &config.redirector.bind {
+        info!("Started Redirector server (Addr: {})", addr);
+    }
+
+    // Accept incoming connections on each of the listeners
+    let tasks: Vec<_> = listeners
+        .into_iter()
+        .map(|mut listener| {
+            listener.set_server_data(data.clone());
+            tokio::spawn(accept_clients(
+                listener,
+                config.clone(),
+                redirect.clone(),
+                retriever.clone(),
+                limits.clone(),
+                shutdown.clone(),
+            ))
+        })
+        .collect();
+    for task in tasks {
+        task.await.ok();
+    }
+
+    info!("Stopped accepting redirector connections");
+}
+
+/// Returns the certificate and private key presented to clients. These
+/// are loaded from the configured files falling back to the embedded
+/// certificate when they aren't set or fail to load
+///
+/// `config` The redirector configuration
+fn server_data(config: &RedirectorConfig) -> Arc<BlazeServerData> {
+    let (certificate, private_key) = match (&config.certificate, &config.private_key) {
+        (Some(certificate), Some(private_key)) => (certificate, private_key),
+        (None, None) => return Arc::default(),
+        _ => {
+            warn!("Both the redirector certificate and private key must be set, using the embedded certificate");
+            return Arc::default();
         }
     };
+    match load_server_data(certificate, private_key) {
+        Ok(value) => {
+            info!("Loaded redirector certificate from {certificate}");
+            Arc::new(value)
+        }
+        Err(err) => {
+            error!("Failed to load redirector certificate, using the embedded certificate: {err}");
+            Arc::default()
+        }
+    }
+}
+
+/// Loads the certificate and private key from the provided files which
+/// may be either PEM or DER encoded. The private key must be a PKCS#8
+/// RSA key
+///
+/// `certificate` The path of the certificate
+/// `private_key` The path of the private key
+fn load_server_data(certificate: &str, private_key: &str) -> io::Result<BlazeServerData> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
 
-    // Accept incoming connections
+    let certificate = fs::read(certificate)?;
+    let certificate = if certificate.starts_with(b"-----BEGIN") {
+        let (label, der) = pem_rfc7468::decode_vec(&certificate)
+            .map_err(|err| invalid(format!("Invalid certificate PEM: {err}")))?;
+        if label != "CERTIFICATE" {
+            return Err(invalid(format!(
+                "Expected a CERTIFICATE PEM but found {label}"
+            )));
+        }
+        der
+    } else {
+        certificate
+    };
+
+    let private_key = fs::read(private_key)?;
+    let private_key = match std::str::from_utf8(&private_key) {
+        Ok(pem) if pem.starts_with("-----BEGIN") => RsaPrivateKey::from_pkcs8_pem(pem),
+        _ => RsaPrivateKey::from_pkcs8_der(&private_key),
+    }
+    .map_err(|err| invalid(format!("Invalid PKCS#8 RSA private key: {err}")))?;
+
+    Ok(BlazeServerData {
+        private_key,
+        certificate: Arc::new(Certificate(certificate)),
+    })
+}
+
+/// Accepts incoming connections from the provided listener
+///
+/// `listener`  The listener to accept from
+/// `config`    The server configuration
+/// `redirect`  The redirect configuration which may be reloaded
+/// `retriever` The retriever used for checking the official server
+/// `limits`    The optional rate limits for clients
+/// `shutdown`  Token cancelled when the server is shutting down
+async fn accept_clients(
+    listener: BlazeListener,
+    config: Arc<Config>,
+    redirect: Arc<SharedRedirect>,
+    retriever: Arc<Retriever>,
+    limits: Option<Arc<ClientLimits>>,
+    shutdown: CancellationToken,
+) {
+    let timeout = config.login.timeout();
     loop {
-        let accept = match listener.accept().await {
+        let accept = select! {
+            _ = shutdown.cancelled() => break,
+            accept = listener.accept() => accept,
+        };
+        let accept = match accept {
             Ok(value) => value,
             Err(err) => {
                 error!("Failed to accept redirector connection: {err:?}");
                 continue;
             }
         };
+        // New connections use the redirect configuration at the time
+        // they are accepted
+        let redirect = redirect.current();
+        let config = config.clone();
+        let retriever = retriever.clone();
+        let limits = limits.clone();
         tokio::spawn(async move {
-            if let Err(err) = handle_client(accept).await {
+            let tracker = LoginTracker::new(LoginStage::Handshake);
+            let flow = handle_client(accept, &config, redirect, retriever, limits, &tracker);
+            if let Some(Err(err)) = login::with_deadline(timeout, &tracker, flow).await {
                 error!("Unable to handle redirect: {err}");
             };
         });
@@ -51,42 +190,128 @@ pub async fn start_server() {
 /// The component to look for when waiting for redirects
 const REDIRECT_COMPONENT: Components = Components::Redirector(Redirector::GetServerInstance);
 
+/// Error code sent to clients when the official server is unreachable
+/// and there is no fallback target to redirect them to
+const UPSTREAM_UNAVAILABLE: u16 = 0x4001;
+
+/// Error code sent to remote clients when the redirect target is a
+/// loopback address and refusing these redirects is enabled
+const REDIRECT_REFUSED: u16 = 0x4002;
+
 /// Handles dealing with a redirector client
 ///
-/// `stream`   The stream to the client
-/// `addr`     The client address
-/// `instance` The server instance information
-async fn handle_client(accept: BlazeAccept) -> io::Result<()> {
+/// `accept`    The accepted client connection
+/// `config`    The server configuration
+/// `redirect`  The redirect configuration
+/// `retriever` The retriever used for checking the official server
+/// `limits`    The optional rate limits for clients
+/// `tracker`   The tracker for the current step of the flow
+async fn handle_client(
+    accept: BlazeAccept,
+    config: &Config,
+    redirect: Arc<RedirectConfig>,
+    retriever: Arc<Retriever>,
+    limits: Option<Arc<ClientLimits>>,
+    tracker: &LoginTracker,
+) -> io::Result<()> {
     let (mut stream, addr) = match accept.finish_accept().await {
         Ok(value) => value,
         Err(err) => {
-            error!(
-                "Unable to establish SSL connection within redirector: {:?}",
-                err
-            );
+            METRICS.client_handshake_failed();
+            log_handshake_failure(&err, &config.redirector);
             return Ok(());
         }
     };
+    // The client address is only known once the handshake is complete
+    if !config.access.allow_connection(addr.ip()) {
+        return Ok(());
+    }
+    if let Some(limits) = &limits {
+        if !limits.allow_connection(addr.ip()) {
+            return Ok(());
+        }
+    }
+    events::emit(Event::Session(SessionEvent::Accepted {
+        time: SystemTime::now(),
+        client: addr,
+    }));
 
     loop {
-        let (component, packet): (Components, Packet) = select! {
+        tracker.enter(LoginStage::AwaitingRequest);
+        let packet = select! {
             // Attempt to read packets from the stream
-            result = Packet::read_async_typed(&mut stream) => result,
+            result = read_packet(&mut stream, config.decode.max_packet_size) => result,
         }?;
+        let component = Components::from_header(&packet.header);
 
         if component == REDIRECT_COMPONENT {
             debug!("Redirecting client (Addr: {addr:?})");
+            tracker.enter(LoginStage::Redirecting);
 
-            let host = EXTERNAL_HOST;
-            let port = MAIN_PORT;
-            let instance = InstanceDetails {
-                net: InstanceNet::from((host.to_string(), port)),
-                secure: false,
+            let request = packet.decode::<InstanceRequest>().ok();
+            if redirect.unknown_clients.reject {
+                let client = match &request {
+                    Some(request) => Some(request.client.clone()),
+                    None => InstanceRequest::find_client(&packet.contents).ok(),
+                };
+                let known = client
+                    .as_deref()
+                    .is_some_and(|client| IdentityProfile::find_client(client).is_some());
+                if !known {
+                    warn!("Rejecting unknown client (Addr: {addr:?}, Client: {client:?})");
+                    let response = Packet::error(
+                        &packet,
+                        ServerInstanceError::CLIENT_NOT_COMPATIBLE,
+                        ServerInstanceError::new(redirect.unknown_clients.message.as_str()),
+                    );
+                    response.write_async(&mut stream).await?;
+                    stream.flush().await?;
+                    break;
+                }
+            }
+            let address = NetAddress::from_ip_addr(addr.ip());
+            let target = redirect.route(address, request.as_ref());
+            let instance = match redirect_instance(&redirect, target, &retriever).await {
+                Some(value) => replace_unspecified(value, &redirect.unspecified).await,
+                None => {
+                    error!(
+                        "Official server and fallback targets are unreachable, not redirecting client (Addr: {addr:?})"
+                    );
+                    let response = Packet::error_empty(&packet, UPSTREAM_UNAVAILABLE);
+                    response.write_async(&mut stream).await?;
+                    stream.flush().await?;
+                    break;
+                }
             };
 
-            let response = Packet::response(&packet, instance);
+            let net = instance.address.clone().into_net();
+            if !addr.ip().is_loopback() && is_local_only(&net).await {
+                warn!("Redirecting remote client to a loopback or unspecified address (Addr: {addr:?})");
+                if redirect.refuse_loopback {
+                    let response = Packet::error_empty(&packet, REDIRECT_REFUSED);
+                    response.write_async(&mut stream).await?;
+                    stream.flush().await?;
+                    break;
+                }
+            }
+
+            if config.redirector.dry_run {
+                let upstream = retriever.target();
+                info!(
+                    "Dry run, redirecting client without proxying (Addr: {addr:?}, Upstream Host: {}, Upstream Port: {}): {instance:?}",
+                    upstream.host, upstream.port
+                );
+            }
+
+            tracker.enter(LoginStage::Responding);
+            let response = Packet::response(&packet, instance.with_tags(config.encode.tags()));
             response.write_async(&mut stream).await?;
             stream.flush().await?;
+            events::emit(Event::Session(SessionEvent::Redirected {
+                time: SystemTime::now(),
+                client: addr,
+                instance: net,
+            }));
             break;
         } else {
             let response = Packet::response_empty(&packet);
@@ -97,3 +322,238 @@ async fn handle_client(accept: BlazeAccept) -> io::Result<()> {
 
     Ok(())
 }
+
+/// The protocol and cipher suites offered to clients, these are the
+/// only ones implemented by the SSL library
+const OFFERED_PARAMETERS: &str = "SSLv3, TLS_RSA_WITH_RC4_128_SHA or TLS_RSA_WITH_RC4_128_MD5";
+
+/// Logs why the SSL handshake with a client failed along with the
+/// parameters and certificate that were offered. The client address
+/// isn't known until the handshake completes
+///
+/// `err`    The handshake error
+/// `config` The redirector configuration
+fn log_handshake_failure(err: &BlazeError, config: &RedirectorConfig) {
+    let reason = match err {
+        BlazeError::IO(err) => format!("Connection lost during the handshake: {err}"),
+        BlazeError::Stopped => "Connection closed during the handshake".to_string(),
+        // The alert type isn't exported by the SSL library
+        BlazeError::Alert(alert) => match format!("{alert:?}").as_str() {
+            alert @ ("BadCertificate"
+            | "UnsupportedCertificate"
+            | "CertificateRevoked"
+            | "CertificateExpired"
+            | "CertificateUnknown") => {
+                format!("Client rejected the certificate ({alert})")
+            }
+            alert @ ("HandshakeFailure" | "IllegalParameter") => {
+                format!("Client sent parameters that couldn't be negotiated ({alert})")
+            }
+            alert => format!("Fatal alert ({alert})"),
+        },
+    };
+    let certificate = config.certificate.as_deref().unwrap_or("Embedded");
+    warn!(
+        "Client SSL handshake failed: {reason} (Offered: {OFFERED_PARAMETERS}, Certificate: {certificate})"
+    );
+}
+
+impl RedirectConfig {
+    /// Returns the target of the first route matching the provided
+    /// client or the primary target if no routes match
+    ///
+    /// `address` The IPv4 address of the client if it has one
+    /// `request` The decoded redirect request from the client
+    fn route(
+        &self,
+        address: Option<NetAddress>,
+        request: Option<&InstanceRequest>,
+    ) -> &RedirectTarget {
+        self.routes
+            .iter()
+            .find(|route| route.matches(address, request))
+            .map_or(&self.target, |route| &route.target)
+    }
+}
+
+impl RedirectRoute {
+    /// Returns whether the provided client matches all the conditions
+    /// of this route. Conditions can't match when the client value
+    /// they check is unknown
+    ///
+    /// `address` The IPv4 address of the client if it has one
+    /// `request` The decoded redirect request from the client
+    fn matches(&self, address: Option<NetAddress>, request: Option<&InstanceRequest>) -> bool {
+        let subnet = self
+            .subnet
+            .is_none_or(|subnet| address.is_some_and(|address| subnet.contains(address)));
+        let sku = self
+            .sku
+            .as_ref()
+            .is_none_or(|sku| request.is_some_and(|request| &request.sku == sku));
+        let locale = self
+            .locale
+            .is_none_or(|locale| request.is_some_and(|request| request.locale == locale));
+        subnet && sku && locale
+    }
+}
+
+/// Determines the instance that clients should be redirected to. When
+/// the health check is enabled the official server is checked first and
+/// if its unreachable the first reachable fallback target is used. None
+/// is returned if the official server and every fallback are unreachable
+///
+/// The result of the official server check is reused for the configured
+/// cache duration. The official server check makes a full upstream
+/// connection while fallbacks only need to accept a TCP connection as
+/// they may be servers that don't use the upstream protocol
+///
+/// `redirect`  The redirect configuration
+/// `target`    The target routed to for the client
+/// `retriever` The retriever used for checking the official server
+async fn redirect_instance(
+    redirect: &RedirectConfig,
+    target: &RedirectTarget,
+    retriever: &Retriever,
+) -> Option<InstanceDetails> {
+    let health_check = &redirect.health_check;
+    let timeout = health_check.timeout();
+    if !health_check.enabled
+        || retriever
+            .is_reachable_cached(timeout, health_check.cache())
+            .await
+    {
+        return valid_instance(target);
+    }
+    for fallback in &redirect.fallbacks {
+        if is_target_reachable(fallback, timeout).await {
+            warn!(
+                "Official server failed health check, using fallback target (Host: {}, Port: {})",
+                fallback.host, fallback.port
+            );
+            return valid_instance(fallback);
+        }
+        warn!(
+            "Fallback target failed health check (Host: {}, Port: {})",
+            fallback.host, fallback.port
+        );
+    }
+    None
+}
+
+/// Creates the instance details for the provided target logging an
+/// error and returning None if the target is invalid
+///
+/// `target` The redirect target
+fn valid_instance(target: &RedirectTarget) -> Option<InstanceDetails> {
+    match target.instance() {
+        Ok(value) => Some(value),
+        Err(err) => {
+            error!(
+                "Unable to redirect to target (Host: {}, Port: {}): {err}",
+                target.host, target.port
+            );
+            None
+        }
+    }
+}
+
+/// Checks whether a TCP connection can be made to the provided target
+/// within the provided timeout
+///
+/// `target`  The target to check
+/// `timeout` The maximum time to wait for the connection
+async fn is_target_reachable(target: &RedirectTarget, timeout: Duration) -> bool {
+    let host = InstanceHost::from(target.host.clone());
+    let connect = async {
+        let addr = dns::resolve(&host, target.port).await.ok()?;
+        TcpStream::connect(addr).await.ok()
+    };
+    matches!(time::timeout(timeout, connect).await, Ok(Some(_)))
+}
+
+/// Replaces the host of the provided instance with the configured
+/// replacement address when it is the unspecified address (0.0.0.0)
+/// which clients would otherwise try to connect to literally
+///
+/// `instance` The instance to replace the host of
+/// `config`   The replacement address configuration
+async fn replace_unspecified(
+    mut instance: InstanceDetails,
+    config: &UnspecifiedConfig,
+) -> InstanceDetails {
+    let net = match &mut instance.address {
+        InstanceAddress::Server(value)
+        | InstanceAddress::Client(value)
+        | InstanceAddress::IpAddress(value)
+        | InstanceAddress::HostnameAddress(value) => value,
+        InstanceAddress::Pair(_) => return instance,
+    };
+    if !matches!(&net.host, InstanceHost::Address(value) if value.is_unspecified()) {
+        return instance;
+    }
+    match dns::local_address(config).await {
+        Some(ip) => {
+            debug!("Replacing unspecified redirect host with {ip}");
+            net.host = InstanceHost::Address(NetAddress(ip));
+        }
+        None => warn!("Unable to determine an address to replace the unspecified redirect host"),
+    }
+    instance
+}
+
+/// Reloads the redirect configuration from the config file. Only
+/// redirects made after the reload use the new configuration
+///
+/// `redirect` The redirect configuration to reload
+pub async fn reload(redirect: &SharedRedirect) {
+    let config = match redirect.reload() {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to reload redirect configuration: {err}");
+            return;
+        }
+    };
+    info!(
+        "Reloaded redirect configuration (Host: {}, Port: {}, Secure: {})",
+        config.target.host, config.target.port, config.target.secure
+    );
+    warn_local_targets(&config).await;
+}
+
+/// Logs a warning for each redirect target that only clients on this
+/// machine will be able to connect to and an error for each target
+/// that is invalid
+///
+/// `redirect` The redirect configuration to check
+async fn warn_local_targets(redirect: &RedirectConfig) {
+    for target in redirect.targets() {
+        let Some(instance) = valid_instance(target) else {
+            continue;
+        };
+        let instance = replace_unspecified(instance, &redirect.unspecified).await;
+        if is_local_only(&instance.address.into_net()).await {
+            warn!(
+                "Redirect target (Host: {}, Port: {}) is a loopback or unspecified address, \
+                only clients on this machine will be able to connect",
+                target.host, target.port
+            );
+        }
+    }
+}
+
+/// Returns whether the provided instance can only be reached by clients
+/// on this machine because its host is, or resolves to, a loopback or
+/// unspecified address
+///
+/// `net` The instance networking details
+async fn is_local_only(net: &InstanceNet) -> bool {
+    let addr = match net.to_socket_addr() {
+        Some(value) => value,
+        None => match dns::resolve(&net.host, net.port).await {
+            Ok(value) => value,
+            Err(_) => return false,
+        },
+    };
+    addr.ip().is_loopback() || addr.ip().is_unspecified()
+}
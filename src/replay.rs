@@ -0,0 +1,244 @@
+//! Module for recording the packets of client sessions to disk and
+//! replaying them through the proxy decode and forward path without
+//! a connection to the official server.
+//!
+//! Recordings are stored as JSON lines with one recorded packet per line
+
+use crate::{
+    config::{BufferConfig, FlushStrategy, RecordConfig},
+    intercept::{InterceptorRegistry, PacketSequences, SessionInfo, SessionInterceptors},
+    mitm::{Direction, Reader, Writer},
+};
+use blaze_pk::packet::Packet;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{duplex, sink, AsyncWriteExt},
+    time,
+};
+use tokio_util::sync::CancellationToken;
+
+/// The size of the in memory pipes used for replaying
+const REPLAY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A single packet within a session recording
+#[derive(Serialize, Deserialize)]
+struct RecordedPacket {
+    /// The direction the packet was travelling in
+    direction: Direction,
+    /// The number of milliseconds since the start of the session
+    time_ms: u64,
+    /// The raw packet frame encoded as hex
+    bytes: String,
+}
+
+/// Recorder for the packets of a single client session
+pub struct SessionRecorder {
+    /// The time the session started
+    start: Instant,
+    /// The recording file
+    file: Mutex<BufWriter<File>>,
+}
+
+impl SessionRecorder {
+    /// Creates a new recorder for a session if recording is enabled
+    /// and the recording file could be created
+    ///
+    /// `config` The recording configuration
+    pub fn start(config: &RecordConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        match Self::create(&config.directory) {
+            Ok((path, file)) => {
+                info!("Recording session to {}", path.display());
+                Some(Self {
+                    start: Instant::now(),
+                    file: Mutex::new(file),
+                })
+            }
+            Err(err) => {
+                error!("Failed to create session recording: {err}");
+                None
+            }
+        }
+    }
+
+    /// Creates a new uniquely named recording file in the provided directory
+    ///
+    /// `directory` The directory to create the recording in
+    fn create(directory: &str) -> io::Result<(PathBuf, BufWriter<File>)> {
+        static SESSION_COUNT: AtomicU32 = AtomicU32::new(0);
+
+        fs::create_dir_all(directory)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let count = SESSION_COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = Path::new(directory).join(format!("session-{time}-{count}.jsonl"));
+        let file = BufWriter::new(File::create(&path)?);
+        Ok((path, file))
+    }
+
+    /// Records the provided packet
+    ///
+    /// `direction` The direction the packet was travelling in
+    /// `packet`    The packet to record
+    pub fn record(&self, direction: Direction, packet: &Packet) {
+        let mut bytes = Vec::with_capacity(12 + packet.contents.len());
+        packet.write_bytes(&mut bytes);
+        let record = RecordedPacket {
+            direction,
+            time_ms: self.start.elapsed().as_millis() as u64,
+            bytes: encode_hex(&bytes),
+        };
+        let mut file = match self.file.lock() {
+            Ok(value) => value,
+            Err(err) => err.into_inner(),
+        };
+        let result = serde_json::to_writer(&mut *file, &record)
+            .map_err(io::Error::from)
+            .and_then(|_| file.write_all(b"\n"));
+        if let Err(err) = result {
+            error!("Failed to write session recording: {err}");
+        }
+    }
+}
+
+/// Replays the session recording at the provided path through the proxy
+/// decode and forward path. Packets are passed through the interceptors
+/// and logged just like packets from a live session
+///
+/// `path`            The path of the session recording
+/// `realtime`        Whether to replay the packets at the captured timing
+/// `interceptors`    The interceptors to pass the packets through
+/// `max_packet_size` The maximum length of packet contents in bytes
+pub async fn replay(
+    path: &Path,
+    realtime: bool,
+    interceptors: Arc<InterceptorRegistry>,
+    max_packet_size: usize,
+) -> io::Result<()> {
+    let records = load(path)?;
+    info!(
+        "Replaying {} packets from {}",
+        records.len(),
+        path.display()
+    );
+
+    let (mut client, client_read) = duplex(REPLAY_BUFFER_SIZE);
+    let (mut server, server_read) = duplex(REPLAY_BUFFER_SIZE);
+
+    let buffers = BufferConfig::default();
+    // Recordings have no client address to scope the capture by
+    let session = SessionInfo {
+        id: 0,
+        addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        captured: true,
+        sequences: Some(Arc::new(PacketSequences::default())),
+    };
+    let interceptors = SessionInterceptors::new(interceptors, session);
+    let client_reader = Reader::new(
+        client_read,
+        Writer::start(
+            sink(),
+            buffers.capacity(Direction::ServerToClient),
+            FlushStrategy::Immediate,
+            CancellationToken::new(),
+        ),
+        Direction::ClientToServer,
+        interceptors.clone(),
+    )
+    .max_packet_size(max_packet_size)
+    .spawn();
+    let server_reader = Reader::new(
+        server_read,
+        Writer::start(
+            sink(),
+            buffers.capacity(Direction::ClientToServer),
+            FlushStrategy::Immediate,
+            CancellationToken::new(),
+        ),
+        Direction::ServerToClient,
+        interceptors,
+    )
+    .max_packet_size(max_packet_size)
+    .spawn();
+
+    let start = time::Instant::now();
+    for (direction, time_ms, bytes) in records {
+        if realtime {
+            time::sleep_until(start + Duration::from_millis(time_ms)).await;
+        }
+        match direction {
+            Direction::ClientToServer => client.write_all(&bytes).await?,
+            Direction::ServerToClient => server.write_all(&bytes).await?,
+        }
+    }
+
+    // Closing the pipes stops the readers once they are drained
+    drop(client);
+    drop(server);
+    client_reader.await.ok();
+    server_reader.await.ok();
+
+    info!("Replay complete");
+    Ok(())
+}
+
+/// Loads the recorded packets from the session recording at the
+/// provided path
+///
+/// `path` The path of the session recording
+fn load(path: &Path) -> io::Result<Vec<(Direction, u64, Vec<u8>)>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for line in file.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: RecordedPacket = serde_json::from_str(&line)?;
+        let bytes = decode_hex(&record.bytes).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Invalid recorded packet bytes")
+        })?;
+        records.push((record.direction, record.time_ms, bytes));
+    }
+    Ok(records)
+}
+
+/// Encodes the provided bytes as a lowercase hex string
+///
+/// `bytes` The bytes to encode
+pub fn encode_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut output = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(output, "{byte:02x}");
+    }
+    output
+}
+
+/// Decodes the provided hex string returning None if the
+/// string is not valid hex
+///
+/// `value` The hex string to decode
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
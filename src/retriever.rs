@@ -4,24 +4,77 @@ use blaze_pk::{
     codec::{Decodable, Encodable},
     error::DecodeError,
     packet::{Packet, PacketComponents, PacketDebug, PacketType},
+    reader::TdfReader,
 };
-use blaze_ssl_async::stream::BlazeStream;
-use log::{debug, error, log_enabled};
+use log::{debug, error, info, log_enabled, warn};
+use rand::Rng;
 use serde::Deserialize;
-use tokio::io::{self, AsyncWriteExt};
+use std::{
+    fmt::Display,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    sync::Semaphore,
+    time,
+};
+use tracing::{debug_span, instrument};
 
 use crate::{
     components::{Components, Redirector},
-    models::{InstanceDetails, InstanceRequest, Port},
+    config::{ComponentRoute, Config, Protocol, ReconnectConfig},
+    dns::{self, ResolveError},
+    dump,
+    events::{self, Event},
+    metrics::METRICS,
+    mitm::read_packet,
+    models::{InstanceHost, InstanceNet, InstanceRequest, Port, ServerInstanceError, ServerList},
+    stream::{ConnectError, ConnectOptions, UpstreamStream},
 };
 
 /// Structure for the retrievier system which contains the host address
 /// for the official game server in order to make further connections
 pub struct Retriever {
-    /// The host address of the official server
-    host: String,
-    /// The port of the official server.
-    port: u16,
+    /// The resolved address of the official redirector
+    redirector_host: String,
+    /// The port of the official redirector
+    redirector_port: Port,
+    /// The instance request sent to the redirector
+    request: InstanceRequest,
+    /// The host address and port of the official server. This is
+    /// updated when the instance is requested again on reconnect
+    target: RwLock<InstanceNet>,
+    /// Reconnection behavior for lost connections
+    reconnect: ReconnectConfig,
+    /// Slots limiting the sessions reconnecting at the same time
+    reconnect_slots: Option<Semaphore>,
+    /// The maximum time to wait when connecting
+    connect_timeout: Duration,
+    /// The protocol used to connect to the redirector
+    redirector_protocol: Protocol,
+    /// The protocol used to connect to the main server
+    server_protocol: Protocol,
+    /// Optional SRV record name used to discover the main server
+    srv: Option<String>,
+    /// The maximum number of chained redirectors that are followed
+    max_redirects: usize,
+    /// Routes sending the packets of components to other upstreams
+    component_routes: Vec<ComponentRoute>,
+    /// The result of the last reachability check along with the time
+    /// it was made if one was made
+    reachable: RwLock<Option<(bool, Instant)>>,
+    /// Whether unsupported address types in instance details are skipped
+    lenient_decode: bool,
+    /// The number of times the instance request is sent again on the
+    /// same connection when the response fails to decode
+    instance_retries: u32,
+    /// Whether the instance requests sent to the redirector are logged
+    log_instance_request: bool,
+    /// The maximum length of packet contents read from the redirector
+    max_packet_size: usize,
+    /// Options for establishing the upstream connections
+    connect: ConnectOptions,
 }
 
 impl Retriever {
@@ -34,45 +87,386 @@ impl Retriever {
     /// ip address of the gosredirector.ea.com host and then creates a
     /// connection to the redirector server and obtains the IP and Port
     /// of the Official server.
-    pub async fn new() -> Option<Retriever> {
-        let redirector_host = lookup_host(Self::REDIRECTOR_HOST).await?;
-        debug!("Completed host lookup: {}", &redirector_host);
-        let (host, port) = Self::get_main_host(redirector_host).await?;
-        debug!("Retriever setup complete. (Host: {} Port: {})", &host, port);
-        Some(Retriever { host, port })
+    ///
+    /// `request` The instance request to send to the redirector
+    /// `config`  The server configuration
+    pub async fn new(request: InstanceRequest, config: &Config) -> RetrieverResult<Retriever> {
+        let lenient_decode = config.decode.lenient_address();
+        let max_packet_size = config.decode.max_packet_size;
+        let connect = ConnectOptions::new(config);
+        let config = &config.retriever;
+        let (redirector_host, redirector_port) = match config.redirector {
+            Some(addr) => (addr.ip().to_string(), addr.port()),
+            None => {
+                let host = lookup_host(Self::REDIRECTOR_HOST)
+                    .await
+                    .ok_or(RetrieverError::Resolve(ResolveError::NoAddresses))?;
+                debug!("Completed host lookup: {}", &host);
+                (host, Self::REDIRECT_PORT)
+            }
+        };
+        let mut retriever = Retriever {
+            redirector_host,
+            redirector_port,
+            request,
+            target: RwLock::new(InstanceNet::unspecified()),
+            reconnect: config.reconnect.clone(),
+            reconnect_slots: (config.reconnect.max_concurrent > 0)
+                .then(|| Semaphore::new(config.reconnect.max_concurrent)),
+            connect_timeout: config.connect_timeout(),
+            redirector_protocol: config.redirector_protocol,
+            server_protocol: config.server_protocol,
+            srv: config.srv.clone(),
+            max_redirects: config.max_redirects,
+            component_routes: config.component_routes.clone(),
+            reachable: RwLock::new(None),
+            lenient_decode,
+            instance_retries: config.instance_retries,
+            log_instance_request: config.log_instance_request,
+            max_packet_size,
+            connect,
+        };
+        let target = retriever.find_target().await?;
+        debug!(
+            "Retriever setup complete. (Host: {} Port: {})",
+            &target.host, target.port
+        );
+        *retriever
+            .target
+            .get_mut()
+            .unwrap_or_else(|err| err.into_inner()) = target;
+        Ok(retriever)
+    }
+
+    /// Finds the host and port of the official server. When a SRV record
+    /// name is configured the record is looked up first, otherwise or if
+    /// no record exists the instance is requested from the redirector
+    async fn find_target(&self) -> RetrieverResult<InstanceNet> {
+        if let Some(srv) = &self.srv {
+            if let Some(target) = dns::lookup_srv(srv).await {
+                debug!(
+                    "Discovered official server from SRV record (Host: {} Port: {})",
+                    &target.host, target.port
+                );
+                return Ok(target);
+            }
+            info!("No SRV record found for {srv}, using official redirector");
+        }
+        self.get_main_host().await
+    }
+
+    /// Makes a instance request to the redirect server and returns the
+    /// primary instance from the response.
+    ///
+    /// Instances on the redirector port are redirectors themselves so
+    /// the request is made again against them until an instance on
+    /// another port is found. An error is returned if a redirector is
+    /// visited twice or more than `max_redirects` redirectors are followed
+    async fn get_main_host(&self) -> RetrieverResult<InstanceNet> {
+        let max_redirects = self.max_redirects;
+        let mut target = self.redirector_target();
+        let mut visited: Vec<InstanceNet> = Vec::new();
+        loop {
+            let list = self.get_server_list(&target).await?;
+            let instance = list.primary().ok_or(RetrieverError::NoInstances)?;
+            events::emit(Event::from(instance));
+            let next = instance.address.clone().into_net();
+            if next.port != Self::REDIRECT_PORT {
+                return Ok(next);
+            }
+
+            visited.push(target);
+            if visited.contains(&next) {
+                return Err(RetrieverError::RedirectLoop(next));
+            }
+            if visited.len() > max_redirects {
+                return Err(RetrieverError::TooManyRedirects(max_redirects));
+            }
+            debug!(
+                "Following redirect to another redirector (Host: {} Port: {})",
+                &next.host, next.port
+            );
+            target = next;
+        }
     }
 
     /// Makes a instance request to the redirect server at the provided
-    /// host and returns the instance response.
-    async fn get_main_host(host: String) -> Option<(String, Port)> {
+    /// target and returns all the instances from the response.
+    ///
+    /// `target` The host and port of the redirector server
+    async fn get_server_list(&self, target: &InstanceNet) -> RetrieverResult<ServerList> {
         debug!("Connecting to official redirector");
-        let stream = Self::stream_to(&host, Self::REDIRECT_PORT).await?;
-        let mut session = RetSession::new(stream)?;
+        let stream = self
+            .stream_to(&target.host, target.port, self.redirector_protocol)
+            .await?;
+        let mut session = RetSession::new(stream, self.max_packet_size);
         debug!("Connected to official redirector");
         debug!("Requesting details from official server");
-        let instance = session.get_main_instance().await.ok()?;
-        let net = instance.net;
-        Some((net.host.into(), net.port))
+        if self.log_instance_request {
+            log_instance_request(&self.request);
+        }
+        session
+            .get_main_instance(
+                self.request.clone(),
+                self.lenient_decode,
+                self.instance_retries,
+            )
+            .await
+    }
+
+    /// Requests the list of server instances from the official
+    /// redirector
+    pub async fn server_list(&self) -> RetrieverResult<ServerList> {
+        self.get_server_list(&self.redirector_target()).await
     }
 
     /// Returns a new stream to the mian server
-    pub async fn stream_to(host: &String, port: Port) -> Option<BlazeStream> {
-        let addr = (host.clone(), port);
-        match BlazeStream::connect(addr).await {
-            Ok(value) => Some(value),
-            Err(err) => {
-                error!(
-                    "Failed to connect to server at {}:{}; Cause: {err:?}",
-                    host, port
-                );
-                None
-            }
+    ///
+    /// `host`     The host of the server
+    /// `port`     The port of the server
+    /// `protocol` The protocol to connect with
+    #[instrument(level = "debug", name = "connect", skip_all, fields(host = %host, port))]
+    pub async fn stream_to(
+        &self,
+        host: &InstanceHost,
+        port: Port,
+        protocol: Protocol,
+    ) -> RetrieverResult<UpstreamStream> {
+        let addr = dns::resolve(host, port)
+            .await
+            .map_err(RetrieverError::Resolve)?;
+        let name = host.to_string();
+        let connect = UpstreamStream::connect(&name, addr, protocol, &self.connect);
+        match time::timeout(self.connect_timeout, connect).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(RetrieverError::Timeout(self.connect_timeout)),
         }
     }
 
     /// Returns a new stream to the main server
-    pub async fn stream(&self) -> Option<BlazeStream> {
-        Self::stream_to(&self.host, self.port).await
+    pub async fn stream(&self) -> RetrieverResult<UpstreamStream> {
+        let target = self.target();
+        self.stream_to(&target.host, target.port, self.server_protocol)
+            .await
+    }
+
+    /// Returns the index of the route for the provided component if
+    /// its packets are sent to another upstream
+    ///
+    /// `component` The component ID of the packet
+    pub fn component_route(&self, component: u16) -> Option<usize> {
+        self.component_routes
+            .iter()
+            .position(|route| route.components.contains(&component))
+    }
+
+    /// Returns a new stream to the upstream of a component route
+    ///
+    /// `route` The index of the route from `component_route`
+    pub async fn route_stream(&self, route: usize) -> RetrieverResult<UpstreamStream> {
+        let route = &self.component_routes[route];
+        self.stream_to(
+            &InstanceHost::from(route.host.clone()),
+            route.port,
+            self.server_protocol,
+        )
+        .await
+    }
+
+    /// Returns the host and port of the redirector
+    fn redirector_target(&self) -> InstanceNet {
+        InstanceNet {
+            host: InstanceHost::from(self.redirector_host.clone()),
+            port: self.redirector_port,
+        }
+    }
+
+    /// Returns a copy of the current host and port of the main server
+    pub fn target(&self) -> InstanceNet {
+        match self.target.read() {
+            Ok(value) => value.clone(),
+            Err(err) => err.into_inner().clone(),
+        }
+    }
+
+    /// Creates a new backoff for reconnecting using the configured
+    /// reconnection behavior
+    pub fn backoff(&self) -> Backoff {
+        Backoff::new(&self.reconnect)
+    }
+
+    /// Attempts to reconnect to the main server waiting between each
+    /// attempt using the provided backoff. The official server is found
+    /// again before each attempt in case it has moved. None is returned once
+    /// the attempts are exhausted or the connection fails with an error that
+    /// is not transient
+    ///
+    /// Attempts wait for a reconnect slot so that only a limited number of
+    /// sessions hit the official servers at once after an outage
+    ///
+    /// `backoff` The backoff for the connection being re-established
+    pub async fn reconnect(&self, backoff: &mut Backoff) -> Option<UpstreamStream> {
+        while let Some((state, delay)) = backoff.next_attempt() {
+            info!("Reconnecting to official server, {state}");
+            METRICS.retriever_reconnect();
+            time::sleep(delay).await;
+            let _slot = match &self.reconnect_slots {
+                Some(slots) => slots.acquire().await.ok(),
+                None => None,
+            };
+
+            match self.find_target().await {
+                Ok(target) => match self.target.write() {
+                    Ok(mut value) => *value = target,
+                    Err(err) => *err.into_inner() = target,
+                },
+                Err(err) => error!("Failed to find official server: {err}"),
+            }
+
+            match self.stream().await {
+                Ok(stream) => {
+                    backoff.connected();
+                    return Some(stream);
+                }
+                Err(err) if err.is_transient() => {
+                    error!("Failed to connect to official server: {err}")
+                }
+                Err(err) => {
+                    error!("Failed to connect to official server, not retrying: {err}");
+                    return None;
+                }
+            }
+        }
+        error!(
+            "Unable to reconnect to official server, {}",
+            backoff.state()
+        );
+        None
+    }
+
+    /// Checks whether the main server is reachable by attempting to
+    /// create a new stream within the provided timeout
+    ///
+    /// `timeout` The maximum time to wait for the connection
+    pub async fn is_reachable(&self, timeout: Duration) -> bool {
+        let reachable = matches!(time::timeout(timeout, self.stream()).await, Ok(Ok(_)));
+        let checked = Some((reachable, Instant::now()));
+        match self.reachable.write() {
+            Ok(mut value) => *value = checked,
+            Err(err) => *err.into_inner() = checked,
+        }
+        reachable
+    }
+
+    /// Returns the result of the last reachability check when it was
+    /// made within the provided duration, otherwise the main server is
+    /// checked again using [`Retriever::is_reachable`]
+    ///
+    /// `timeout` The maximum time to wait for the connection
+    /// `cache`   The duration the result of the last check is reused for
+    pub async fn is_reachable_cached(&self, timeout: Duration, cache: Duration) -> bool {
+        match self.last_check() {
+            Some((reachable, checked)) if checked.elapsed() < cache => reachable,
+            _ => self.is_reachable(timeout).await,
+        }
+    }
+
+    /// Returns the result of the last reachability check or [`None`]
+    /// if the main server hasn't been checked yet
+    pub fn last_reachable(&self) -> Option<bool> {
+        self.last_check().map(|(reachable, _)| reachable)
+    }
+
+    /// Returns the result of the last reachability check along with the
+    /// time it was made
+    fn last_check(&self) -> Option<(bool, Instant)> {
+        match self.reachable.read() {
+            Ok(value) => *value,
+            Err(err) => *err.into_inner(),
+        }
+    }
+}
+
+/// Exponential backoff for reconnection attempts. The delay between
+/// attempts doubles each time and the attempts are reset once a
+/// connection has stayed up for long enough
+pub struct Backoff {
+    /// The delay before the first attempt
+    base_delay: Duration,
+    /// The maximum number of attempts
+    max_retries: u32,
+    /// The connection time required to reset the attempts
+    reset_after: Duration,
+    /// The fraction of each delay that is random
+    jitter: f64,
+    /// The number of attempts made so far
+    attempt: u32,
+    /// The time the last successful connection was made
+    connected_at: Option<Instant>,
+}
+
+/// The current retry state of a backoff
+#[derive(Debug, Clone, Copy)]
+pub struct RetryState {
+    /// The current attempt number
+    pub attempt: u32,
+    /// The maximum number of attempts
+    pub max_retries: u32,
+}
+
+impl Display for RetryState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "attempt {}/{}", self.attempt, self.max_retries)
+    }
+}
+
+impl Backoff {
+    /// Creates a new backoff from the provided config
+    ///
+    /// `config` The reconnection config
+    pub fn new(config: &ReconnectConfig) -> Self {
+        Self {
+            base_delay: Duration::from_millis(config.base_delay_ms),
+            max_retries: config.max_retries,
+            reset_after: Duration::from_secs(config.reset_after_secs),
+            jitter: config.jitter.clamp(0.0, 1.0),
+            attempt: 0,
+            connected_at: None,
+        }
+    }
+
+    /// Marks the connection as successfully established
+    pub fn connected(&mut self) {
+        self.connected_at = Some(Instant::now());
+    }
+
+    /// Returns the current retry state
+    pub fn state(&self) -> RetryState {
+        RetryState {
+            attempt: self.attempt,
+            max_retries: self.max_retries,
+        }
+    }
+
+    /// Moves onto the next attempt returning the retry state and the
+    /// delay to wait before the attempt. The jitter fraction of the delay
+    /// is randomly removed. The attempts are reset if the last connection
+    /// was sustained. None is returned when there are no attempts remaining
+    pub fn next_attempt(&mut self) -> Option<(RetryState, Duration)> {
+        if let Some(connected_at) = self.connected_at.take() {
+            if connected_at.elapsed() >= self.reset_after {
+                self.attempt = 0;
+            }
+        }
+        if self.attempt >= self.max_retries {
+            return None;
+        }
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(self.attempt));
+        let delay = delay.mul_f64(1.0 - self.jitter * rand::thread_rng().gen::<f64>());
+        self.attempt += 1;
+        Some((self.state(), delay))
     }
 }
 
@@ -81,17 +475,50 @@ pub struct RetSession {
     /// The ID for the next request packet
     id: u16,
     /// The underlying SSL / TCP stream connection
-    stream: BlazeStream,
+    stream: UpstreamStream,
+    /// The maximum length of packet contents that will be read
+    max_packet_size: usize,
 }
 
 /// Error type for retriever errors
+#[derive(Debug)]
 pub enum RetrieverError {
+    /// The server host could not be resolved
+    Resolve(ResolveError),
+    /// The TCP connection to the server could not be established
+    Connect(io::Error),
+    /// The SSLv3 or TLS handshake with the server failed
+    Tls(io::Error),
+    /// The SOCKS5 proxy failed to connect to the server
+    Proxy(io::Error),
+    /// The connection was not established within the timeout
+    Timeout(Duration),
+    /// IO error on an established connection
+    IO(io::Error),
     /// Packet decode errror
     Decode(DecodeError),
-    /// IO Error
-    IO(io::Error),
-    /// Error response packet
-    Packet(Packet),
+    /// The server responded with an error response packet
+    UnexpectedResponse(Packet),
+    /// The redirector response did not contain any instances
+    NoInstances,
+    /// A redirector redirected back to a redirector that was
+    /// already visited
+    RedirectLoop(InstanceNet),
+    /// More than the maximum number of chained redirectors were
+    /// followed without reaching a server
+    TooManyRedirects(usize),
+}
+
+impl RetrieverError {
+    /// Returns whether the error is likely to be transient so that
+    /// the operation may succeed if it is retried. Protocol errors
+    /// such as handshake and decode failures are not transient
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Resolve(_) | Self::Connect(_) | Self::Timeout(_) | Self::IO(_)
+        )
+    }
 }
 
 pub type RetrieverResult<T> = Result<T, RetrieverError>;
@@ -99,9 +526,15 @@ pub type RetrieverResult<T> = Result<T, RetrieverError>;
 impl RetSession {
     /// Creates a new retriever session for the provided host and
     /// port. This will create the underlying connection aswell.
-    /// If creating the connection fails then None is returned instead.
-    pub fn new(stream: BlazeStream) -> Option<Self> {
-        Some(Self { id: 0, stream })
+    ///
+    /// `stream`          The connection to the server
+    /// `max_packet_size` The maximum length of packet contents to read
+    pub fn new(stream: UpstreamStream, max_packet_size: usize) -> Self {
+        Self {
+            id: 0,
+            stream,
+            max_packet_size,
+        }
     }
 
     /// Writes a request packet and waits until the response packet is
@@ -111,21 +544,34 @@ impl RetSession {
         component: Components,
         contents: Req,
     ) -> RetrieverResult<Res> {
+        let response = self.request_packet(component, contents).await?;
+        let _decode = debug_span!("decode").entered();
+        let contents = response
+            .decode::<Res>()
+            .inspect_err(|_| METRICS.decode_error(&response.header))?;
+        Ok(contents)
+    }
+
+    /// Writes a request packet and waits until the response packet is
+    /// recieved returning the response packet without decoding it.
+    pub async fn request_packet<Req: Encodable>(
+        &mut self,
+        component: Components,
+        contents: Req,
+    ) -> RetrieverResult<Packet> {
         let request = Packet::request(self.id, component, contents);
         request.write_async(&mut self.stream).await?;
         debug_log_packet(&request, "Sent to Official");
         self.stream.flush().await?;
         self.id += 1;
-        let response = self.expect_response(&request).await?;
-        let contents = response.decode::<Res>()?;
-        Ok(contents)
+        self.expect_response(&request).await
     }
 
     /// Waits for a response packet to be recieved any notification packets
     /// that are recieved are handled in the handle_notify function.
     async fn expect_response(&mut self, request: &Packet) -> RetrieverResult<Packet> {
         loop {
-            let response = Packet::read_async(&mut self.stream).await?;
+            let response = read_packet(&mut self.stream, self.max_packet_size).await?;
             debug_log_packet(&response, "Received from Official");
             let header = &response.header;
 
@@ -134,22 +580,122 @@ impl RetSession {
                     return Ok(response);
                 }
             } else if let PacketType::Error = header.ty {
-                return Err(RetrieverError::Packet(response));
+                return Err(RetrieverError::UnexpectedResponse(response));
             }
         }
     }
 
     /// Function for making the request for the official server instance
     /// from the redirector server.
-    async fn get_main_instance(&mut self) -> RetrieverResult<InstanceDetails> {
-        self.request::<InstanceRequest, InstanceDetails>(
-            Components::Redirector(Redirector::GetServerInstance),
-            InstanceRequest,
-        )
-        .await
+    ///
+    /// `request` The instance request to send
+    /// `lenient` Whether unsupported address types are skipped
+    /// `retries` The number of times the request is sent again when the
+    ///           response fails to decode
+    #[instrument(level = "debug", name = "instance_request", skip_all)]
+    async fn get_main_instance(
+        &mut self,
+        request: InstanceRequest,
+        lenient: bool,
+        mut retries: u32,
+    ) -> RetrieverResult<ServerList> {
+        loop {
+            let result = self.request_server_list(request.clone(), lenient).await;
+            match &result {
+                // The whole response frame was read so the connection can be reused
+                Err(RetrieverError::Decode(err)) if retries > 0 && is_retryable_decode(err) => {
+                    retries -= 1;
+                    warn!(
+                        "Failed to decode instance details, requesting again ({retries} retries remaining): {err:?}"
+                    );
+                    continue;
+                }
+                Err(RetrieverError::UnexpectedResponse(response)) => {
+                    log_version_mismatch(&request, response)
+                }
+                _ => {}
+            }
+            return result;
+        }
+    }
+
+    /// Sends the instance request to the redirector and decodes the
+    /// server list from the response
+    ///
+    /// `request` The instance request to send
+    /// `lenient` Whether unsupported address types are skipped
+    async fn request_server_list(
+        &mut self,
+        request: InstanceRequest,
+        lenient: bool,
+    ) -> RetrieverResult<ServerList> {
+        let response = self
+            .request_packet(
+                Components::Redirector(Redirector::GetServerInstance),
+                request,
+            )
+            .await?;
+        let _decode = debug_span!("decode").entered();
+        let mut reader = TdfReader::new(&response.contents);
+        let list = ServerList::decode_with(&mut reader, lenient)
+            .inspect_err(|_| METRICS.decode_error(&response.header))?;
+        Ok(list)
     }
 }
 
+/// Returns whether the provided decode error could have been caused by
+/// a damaged response so that requesting again may succeed. Responses
+/// with missing tags or mismatched types are a protocol mismatch that
+/// requesting again won't fix
+///
+/// `err` The decode error
+fn is_retryable_decode(err: &DecodeError) -> bool {
+    matches!(
+        err,
+        DecodeError::UnexpectedEof { .. } | DecodeError::UnknownType { .. }
+    )
+}
+
+/// Logs the encoded bytes of the provided instance request as both an
+/// annotated view of each field and a hex dump along with the request
+/// decoded back from the bytes
+///
+/// `request` The instance request being sent
+fn log_instance_request(request: &InstanceRequest) {
+    let bytes = request.encode_bytes();
+    let mut out = String::new();
+    dump::dump_contents(&bytes, &mut out);
+    dump::hex_dump(&bytes, 0, 1, &mut out);
+    let decoded = InstanceRequest::decode(&mut TdfReader::new(&bytes));
+    info!(
+        "Sending instance request ({} bytes)\n{out}Decoded: {decoded:?}",
+        bytes.len()
+    );
+}
+
+/// Logs the versions sent in the provided instance request when the
+/// error response from the redirector indicates that they were rejected
+///
+/// `request`  The instance request that was sent
+/// `response` The error response from the redirector
+fn log_version_mismatch(request: &InstanceRequest, response: &Packet) {
+    let Ok(err) = response.decode::<ServerInstanceError>() else {
+        return;
+    };
+    if !err.is_version_mismatch(response.header.error) {
+        return;
+    }
+    error!(
+        "Official redirector rejected the client versions (Error: {}, BSDK: {}, CVER: {}, DSDK: {}, Messages: {:?}). \
+        The server may expect newer versions, try another identity.profile",
+        response.header.error,
+        request.sdk_version,
+        request.version,
+        request.dirty_sdk_version,
+        err.messages
+    );
+}
+
 /// Logs the contents of the provided packet to the debug output along with
 /// the header information.
 ///
@@ -170,12 +716,50 @@ fn debug_log_packet(packet: &Packet, action: &str) {
     debug!("\n{}\n{:?}", action, debug);
 }
 
+impl Display for RetrieverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Resolve(err) => write!(f, "Failed to resolve host: {err}"),
+            Self::Connect(err) => write!(f, "Failed to connect: {err}"),
+            Self::Tls(err) => write!(f, "Handshake failed: {err}"),
+            Self::Proxy(err) => write!(f, "Proxy negotiation failed: {err}"),
+            Self::Timeout(timeout) => {
+                write!(f, "Timed out connecting after {}s", timeout.as_secs_f32())
+            }
+            Self::IO(err) => write!(f, "IO error: {err}"),
+            Self::Decode(err) => write!(f, "Failed to decode response: {err:?}"),
+            Self::UnexpectedResponse(packet) => {
+                write!(f, "Error response packet (Error: {})", packet.header.error)
+            }
+            Self::NoInstances => f.write_str("No server instances provided"),
+            Self::RedirectLoop(target) => write!(
+                f,
+                "Redirect loop detected (Host: {}, Port: {})",
+                target.host, target.port
+            ),
+            Self::TooManyRedirects(max) => {
+                write!(f, "Followed more than the maximum of {max} redirects")
+            }
+        }
+    }
+}
+
 impl From<DecodeError> for RetrieverError {
     fn from(err: DecodeError) -> Self {
         RetrieverError::Decode(err)
     }
 }
 
+impl From<ConnectError> for RetrieverError {
+    fn from(err: ConnectError) -> Self {
+        match err {
+            ConnectError::Connect(err) => RetrieverError::Connect(err),
+            ConnectError::Handshake(err) => RetrieverError::Tls(err),
+            ConnectError::Proxy(err) => RetrieverError::Proxy(err),
+        }
+    }
+}
+
 impl From<io::Error> for RetrieverError {
     fn from(err: io::Error) -> Self {
         RetrieverError::IO(err)
@@ -186,7 +770,7 @@ impl From<io::Error> for RetrieverError {
 ///
 /// # Structure
 ///
-/// ```
+/// ```json
 /// {
 ///   "Status": 0,
 ///   "TC": false,
@@ -221,7 +805,7 @@ struct LookupResponse {
 /// being used so only that is present here.
 ///
 /// # Structure
-/// ```
+/// ```json
 /// {
 ///   "name": "gosredirector.ea.com.",
 ///   "type": 1,
@@ -0,0 +1,380 @@
+//! Module for running the redirector and MITM servers together. This
+//! is what the binary runs and what allows the servers to be embedded
+//! within another process on its own runtime
+
+use crate::{
+    auth,
+    capture::Capture,
+    config::{Config, ConfigError, RedirectTarget, SharedRedirect},
+    console, events, filter,
+    intercept::{InterceptorHandle, InterceptorRegistry},
+    latency::LatencyInjector,
+    metrics::METRICS,
+    mitm, qos,
+    ratelimit::ClientLimits,
+    redirector, replay,
+    retriever::{Retriever, RetrieverError},
+    telemetry, validate,
+};
+use blaze_ssl_async::stream::BlazeListener;
+use log::info;
+use std::{error::Error, fmt::Display, io, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, time};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// Builder for a server which starts from the default configuration
+#[derive(Default)]
+pub struct ServerBuilder {
+    /// The configuration for the server
+    config: Config,
+    /// Whether console commands are read from stdin
+    console: bool,
+    /// Whether the redirect configuration is reloaded on SIGHUP
+    reload_signal: bool,
+}
+
+impl ServerBuilder {
+    /// Replaces the whole configuration, the other builder functions
+    /// modify the configuration set here
+    ///
+    /// `config` The server configuration
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the local addresses the redirector listens on
+    ///
+    /// `bind` The local addresses
+    pub fn redirector_bind(mut self, bind: Vec<SocketAddr>) -> Self {
+        self.config.redirector.bind = bind;
+        self
+    }
+
+    /// Sets the local address the MITM server listens on
+    ///
+    /// `bind` The local address
+    pub fn mitm_bind(mut self, bind: SocketAddr) -> Self {
+        self.config.mitm.bind = bind;
+        self
+    }
+
+    /// Sets the certificate and private key files presented to clients
+    /// by the redirector
+    ///
+    /// `certificate` The path of the certificate
+    /// `private_key` The path of the private key
+    pub fn certificate(
+        mut self,
+        certificate: impl Into<String>,
+        private_key: impl Into<String>,
+    ) -> Self {
+        self.config.redirector.certificate = Some(certificate.into());
+        self.config.redirector.private_key = Some(private_key.into());
+        self
+    }
+
+    /// Sets the primary target that clients are redirected to
+    ///
+    /// `target` The redirect target
+    pub fn redirect_target(mut self, target: RedirectTarget) -> Self {
+        self.config.redirect.target = target;
+        self
+    }
+
+    /// Sets the time to wait when connecting to the official servers
+    ///
+    /// `timeout` The connection timeout
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.retriever.connect_timeout_secs = timeout.as_secs();
+        self
+    }
+
+    /// Sets the deadline for the login flow of each connection
+    ///
+    /// `timeout` The login timeout
+    pub fn login_timeout(mut self, timeout: Duration) -> Self {
+        self.config.login.timeout_secs = timeout.as_secs();
+        self
+    }
+
+    /// Sets the time active sessions are given to finish when the
+    /// server is shutting down
+    ///
+    /// `grace_period` The shutdown grace period
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.config.shutdown.grace_period_secs = grace_period.as_secs();
+        self
+    }
+
+    /// Sets whether console commands are read from stdin
+    ///
+    /// `console` Whether the console is enabled
+    pub fn console(mut self, console: bool) -> Self {
+        self.console = console;
+        self
+    }
+
+    /// Sets whether the redirect configuration is reloaded when SIGHUP
+    /// is received, this only applies to unix systems
+    ///
+    /// `reload_signal` Whether SIGHUP reloads the configuration
+    pub fn reload_signal(mut self, reload_signal: bool) -> Self {
+        self.reload_signal = reload_signal;
+        self
+    }
+
+    /// Creates the server registering the configured packet interceptors
+    pub fn build(self) -> Server {
+        let config = self.config;
+        METRICS.start();
+
+        let interceptors = Arc::new(InterceptorRegistry::default());
+        let capture = Capture::start(&config.capture).map(Arc::new);
+        // Only the packets matching the log rules are captured when present
+        let log_capture = capture
+            .as_ref()
+            .filter(|_| !config.filter.log.is_empty())
+            .cloned();
+        interceptors.register(filter::PacketFilter::new(&config.filter, log_capture));
+        interceptors.register(auth::LoginObserver);
+        if config.decode.telemetry {
+            interceptors.register(telemetry::TelemetryObserver);
+        }
+        let validator = config
+            .decode
+            .validate
+            .then(|| interceptors.register(validate::DecodeValidator::new(&config.decode)));
+        if let Some(rewriter) = qos::QosRewriter::new(&config.qos) {
+            interceptors.register(rewriter);
+        }
+        let latency = Arc::new(LatencyInjector::new(&config.latency));
+        interceptors.register(latency.clone());
+        if let Some(capture) = capture.filter(|_| config.filter.log.is_empty()) {
+            interceptors.register(capture);
+        }
+
+        Server {
+            config: Arc::new(config),
+            interceptors,
+            latency,
+            validator,
+            console: self.console,
+            reload_signal: self.reload_signal,
+        }
+    }
+}
+
+/// The redirector and MITM servers along with the packet interceptors
+/// shared by their sessions
+pub struct Server {
+    /// The configuration for the server
+    config: Arc<Config>,
+    /// The interceptors for the proxied packets
+    interceptors: Arc<InterceptorRegistry>,
+    /// The latency injector controlled by the console
+    latency: Arc<LatencyInjector>,
+    /// The handle of the decode validator when it is registered
+    validator: Option<InterceptorHandle>,
+    /// Whether console commands are read from stdin
+    console: bool,
+    /// Whether the redirect configuration is reloaded on SIGHUP
+    reload_signal: bool,
+}
+
+impl Server {
+    /// Creates a builder for a server using the default configuration
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Returns the interceptors for the proxied packets which further
+    /// interceptors can be registered with
+    pub fn interceptors(&self) -> &Arc<InterceptorRegistry> {
+        &self.interceptors
+    }
+
+    /// Replays the session recording at the provided path through the
+    /// interceptors of this server instead of running it
+    ///
+    /// `path`     The path of the session recording
+    /// `realtime` Whether to replay the packets at the captured timing
+    pub async fn replay(&self, path: &Path, realtime: bool) -> io::Result<()> {
+        replay::replay(
+            path,
+            realtime,
+            self.interceptors.clone(),
+            self.config.decode.max_packet_size,
+        )
+        .await
+    }
+
+    /// Runs the servers until the provided token is cancelled and the
+    /// active sessions have finished or the grace period has passed.
+    /// An error is returned if the configuration is invalid, the listeners
+    /// couldn't be bound or the official server couldn't be found
+    ///
+    /// `shutdown` Token cancelled to shut down the server
+    pub async fn run(self, shutdown: CancellationToken) -> Result<(), ServerError> {
+        let config = self.config;
+        config.validate().map_err(ServerError::Config)?;
+
+        // Bind the listeners before anything is started so that an address
+        // in use is reported to the caller
+        let mut redirector_listeners = Vec::with_capacity(config.redirector.bind.len());
+        for addr in &config.redirector.bind {
+            let listener = BlazeListener::bind(addr)
+                .await
+                .map_err(|err| ServerError::Bind(*addr, err))?;
+            redirector_listeners.push(listener);
+        }
+        let mitm_listener = if config.redirector.dry_run {
+            None
+        } else {
+            let addr = config.mitm.bind;
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|err| ServerError::Bind(addr, err))?;
+            Some(listener)
+        };
+
+        // Subscribe before anything is able to emit events
+        tokio::spawn(events::log_events(events::subscribe()));
+
+        let retriever = Arc::new(Retriever::new(config.identity.request(), &config).await?);
+        let limits = ClientLimits::from_config(&config.rate_limit).map(Arc::new);
+        let redirect = Arc::new(SharedRedirect::new(config.redirect.clone()));
+        let sessions = TaskTracker::new();
+        let force_close = CancellationToken::new();
+
+        // Spawn redirector in its own task
+        tokio::spawn(redirector::start_server(
+            redirector_listeners,
+            config.clone(),
+            redirect.clone(),
+            retriever.clone(),
+            limits.clone(),
+            shutdown.clone(),
+        ));
+
+        // Start the MITM server unless clients are only being redirected
+        if let Some(listener) = mitm_listener {
+            tokio::spawn(mitm::start_server(
+                listener,
+                config.clone(),
+                retriever.clone(),
+                self.interceptors.clone(),
+                limits,
+                shutdown.clone(),
+                sessions.clone(),
+                force_close.clone(),
+            ));
+        } else {
+            info!("Dry run enabled, clients are redirected without being proxied");
+        }
+
+        // Answer liveness and readiness checks
+        if config.health.enabled {
+            #[cfg(feature = "health")]
+            tokio::spawn(crate::health::start_server(
+                config.clone(),
+                retriever.clone(),
+                shutdown.clone(),
+            ));
+            #[cfg(not(feature = "health"))]
+            log::warn!("Health endpoint is enabled but the health feature isn't built");
+        }
+
+        // Handle console commands
+        if self.console {
+            tokio::spawn(console::listen(
+                config.clone(),
+                self.latency,
+                retriever,
+                redirect.clone(),
+                self.interceptors,
+                self.validator,
+            ));
+        }
+
+        // Reload the redirect configuration on SIGHUP
+        #[cfg(unix)]
+        if self.reload_signal {
+            tokio::spawn(reload_signal(redirect));
+        }
+
+        shutdown.cancelled().await;
+
+        // Wait for the active sessions to finish
+        sessions.close();
+        let active = METRICS.snapshot().active_sessions;
+        let grace_period = config.shutdown.grace_period();
+        if time::timeout(grace_period, sessions.wait()).await.is_ok() {
+            info!("Drained {} sessions", active);
+        } else {
+            let remaining = METRICS.snapshot().active_sessions;
+            info!(
+                "Drained {} sessions, force closing {} sessions",
+                active.saturating_sub(remaining),
+                remaining
+            );
+            force_close.cancel();
+            sessions.wait().await;
+        }
+        Ok(())
+    }
+}
+
+/// Errors that can occur while starting the servers
+#[derive(Debug)]
+pub enum ServerError {
+    /// The configuration was invalid
+    Config(ConfigError),
+    /// A listener couldn't be bound to its configured address
+    Bind(SocketAddr, io::Error),
+    /// The official server couldn't be found
+    Retriever(RetrieverError),
+}
+
+impl From<RetrieverError> for ServerError {
+    fn from(value: RetrieverError) -> Self {
+        Self::Retriever(value)
+    }
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(err) => write!(f, "{err}"),
+            Self::Bind(addr, err) => write!(f, "Failed to bind {addr}: {err}"),
+            Self::Retriever(err) => {
+                write!(
+                    f,
+                    "Failed to initialize connection to official server: {err}"
+                )
+            }
+        }
+    }
+}
+
+impl Error for ServerError {}
+
+/// Reloads the redirect configuration each time SIGHUP is received
+///
+/// `redirect` The redirect configuration to reload
+#[cfg(unix)]
+async fn reload_signal(redirect: Arc<SharedRedirect>) {
+    use log::error;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to listen for SIGHUP: {err}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        redirector::reload(&redirect).await;
+    }
+}
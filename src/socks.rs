@@ -0,0 +1,162 @@
+//! Module for tunneling connections to the official servers through a
+//! SOCKS5 proxy (RFC 1928) using the optional username and password
+//! authentication (RFC 1929)
+
+use crate::config::ProxyConfig;
+use std::{io, net::SocketAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// The SOCKS protocol version
+const VERSION: u8 = 0x05;
+/// Authentication method for proxies without authentication
+const METHOD_NONE: u8 = 0x00;
+/// Authentication method for username and password authentication
+const METHOD_PASSWORD: u8 = 0x02;
+/// Response from the proxy when none of the methods are acceptable
+const METHOD_UNACCEPTABLE: u8 = 0xFF;
+/// The version of the username and password authentication
+const PASSWORD_VERSION: u8 = 0x01;
+/// Command for establishing a TCP connection
+const COMMAND_CONNECT: u8 = 0x01;
+/// Address type for IPv4 addresses
+const ADDRESS_IPV4: u8 = 0x01;
+/// Address type for domain names
+const ADDRESS_DOMAIN: u8 = 0x03;
+/// Address type for IPv6 addresses
+const ADDRESS_IPV6: u8 = 0x04;
+
+/// Negotiates a connection to the provided target over a stream that
+/// is connected to the proxy. Once this completes the stream is
+/// tunneled to the target
+///
+/// `stream` The stream connected to the proxy
+/// `target` The address to connect to through the proxy
+/// `config` The proxy configuration
+pub async fn connect(
+    stream: &mut TcpStream,
+    target: SocketAddr,
+    config: &ProxyConfig,
+) -> io::Result<()> {
+    let credentials = config.username.as_deref().map(|username| {
+        let password = config.password.as_deref().unwrap_or_default();
+        (username, password)
+    });
+    let method = if credentials.is_some() {
+        METHOD_PASSWORD
+    } else {
+        METHOD_NONE
+    };
+    stream.write_all(&[VERSION, 1, method]).await?;
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await?;
+    if response[0] != VERSION {
+        return Err(invalid_data("Proxy responded with an unsupported version"));
+    }
+    match (response[1], credentials) {
+        (METHOD_NONE, _) => {}
+        (METHOD_PASSWORD, Some((username, password))) => {
+            authenticate(stream, username, password).await?
+        }
+        (METHOD_UNACCEPTABLE, _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Proxy rejected the authentication method",
+            ))
+        }
+        _ => return Err(invalid_data("Proxy selected an unsupported method")),
+    }
+
+    let mut request = vec![VERSION, COMMAND_CONNECT, 0x00];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ADDRESS_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(ADDRESS_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(invalid_data("Proxy responded with an unsupported version"));
+    }
+    if reply[1] != 0x00 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("Proxy failed to connect: {}", reply_message(reply[1])),
+        ));
+    }
+    // Skip the bound address and port which aren't needed
+    let length = match reply[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(invalid_data("Proxy responded with an unknown address type")),
+    };
+    let mut bound = vec![0u8; length + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Authenticates with the proxy using the provided username and password
+///
+/// `stream`   The stream connected to the proxy
+/// `username` The username to authenticate with
+/// `password` The password to authenticate with
+async fn authenticate(stream: &mut TcpStream, username: &str, password: &str) -> io::Result<()> {
+    let (Ok(username_length), Ok(password_length)) =
+        (u8::try_from(username.len()), u8::try_from(password.len()))
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Proxy username and password must be at most 255 bytes",
+        ));
+    };
+    let mut request = vec![PASSWORD_VERSION, username_length];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password_length);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await?;
+    if response[1] != 0x00 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Proxy rejected the username and password",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the message for the provided reply code
+///
+/// `code` The reply code from the proxy
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "General failure",
+        0x02 => "Connection not allowed by ruleset",
+        0x03 => "Network unreachable",
+        0x04 => "Host unreachable",
+        0x05 => "Connection refused",
+        0x06 => "TTL expired",
+        0x07 => "Command not supported",
+        0x08 => "Address type not supported",
+        _ => "Unknown error",
+    }
+}
+
+/// Creates an error for a malformed response from the proxy
+///
+/// `message` The error message
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,384 @@
+//! Module for the streams used to connect to the official servers. The
+//! official servers use SSLv3 however modernized private servers may
+//! instead use TLS so the protocol is selected per target. The SSLv3
+//! stream can only create its own connection so when the connection
+//! must be bound or tunneled through a proxy the handshake is piped
+//! over loopback to a connection made here
+
+use crate::{
+    config::{Config, Protocol, ProxyConfig, SocketConfig},
+    socks,
+};
+use blaze_ssl_async::stream::{BlazeError, BlazeStream};
+use log::warn;
+use socket2::SockRef;
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{self as tokio_io, AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpSocket, TcpStream},
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+use tokio_util::task::AbortOnDropHandle;
+
+/// Options for how upstream TCP connections are established
+#[derive(Default, Clone)]
+pub struct ConnectOptions {
+    /// The local address that connections are bound to before
+    /// connecting
+    pub source_address: Option<SocketAddr>,
+    /// The SOCKS5 proxy that connections are tunneled through
+    pub proxy: Option<ProxyConfig>,
+    /// The options set on connections once they are connected
+    pub sockets: SocketConfig,
+}
+
+impl ConnectOptions {
+    /// Creates the connect options from the server configuration
+    ///
+    /// `config` The server configuration
+    pub fn new(config: &Config) -> Self {
+        Self {
+            source_address: config.retriever.source_address,
+            proxy: config.retriever.proxy.clone(),
+            sockets: config.sockets.clone(),
+        }
+    }
+
+    /// Creates a TCP connection to the provided address using these
+    /// options. The socket options are set once it is connected and
+    /// when a proxy is used the stream is tunneled through it
+    ///
+    /// `addr` The address to connect to
+    async fn connect(&self, addr: SocketAddr) -> Result<TcpStream, ConnectError> {
+        let proxy = self.proxy.as_ref();
+        let target = proxy.map_or(addr, |proxy| proxy.address);
+        let mut stream = connect_tcp(target, self.source_address)
+            .await
+            .map_err(ConnectError::Connect)?;
+        self.sockets.apply(&stream);
+        if let Some(proxy) = proxy {
+            socks::connect(&mut stream, addr, proxy)
+                .await
+                .map_err(ConnectError::Proxy)?;
+        }
+        Ok(stream)
+    }
+}
+
+impl SocketConfig {
+    /// Sets these options on the provided connected stream. Options that
+    /// fail to be set are logged and the stream is used regardless
+    ///
+    /// `stream` The stream to set the options on
+    pub fn apply(&self, stream: &TcpStream) {
+        if let Err(err) = self.try_apply(stream) {
+            warn!("Failed to set socket options: {err}");
+        }
+    }
+
+    /// Sets these options on the provided connected stream
+    ///
+    /// `stream` The stream to set the options on
+    fn try_apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        let socket = SockRef::from(stream);
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates a TCP connection to the provided address which is bound
+/// to the source address when one is set
+///
+/// `addr`   The address to connect to
+/// `source` The optional local address to bind
+async fn connect_tcp(addr: SocketAddr, source: Option<SocketAddr>) -> io::Result<TcpStream> {
+    let Some(source) = source else {
+        return TcpStream::connect(addr).await;
+    };
+    let socket = match source {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.bind(source)?;
+    socket.connect(addr).await
+}
+
+/// Errors that can occur while connecting to an upstream server
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The TCP connection could not be established
+    Connect(io::Error),
+    /// The SSLv3 or TLS handshake failed
+    Handshake(io::Error),
+    /// The SOCKS5 proxy negotiation failed
+    Proxy(io::Error),
+}
+
+/// Stream connected to an upstream server using one of the
+/// supported protocols
+pub enum UpstreamStream {
+    /// Legacy SSLv3 stream
+    Ssl3(BlazeStream),
+    /// Modern TLS stream
+    Tls(TlsStream<TcpStream>),
+}
+
+impl UpstreamStream {
+    /// Connects to the server at the provided address using the
+    /// provided protocol. The socket options are set on the TCP
+    /// connection once it is connected
+    ///
+    /// `host`     The host name of the server used for TLS verification
+    /// `addr`     The resolved address of the server
+    /// `protocol` The protocol to connect with
+    /// `options`  The options for establishing the connection
+    pub async fn connect(
+        host: &str,
+        addr: SocketAddr,
+        protocol: Protocol,
+        options: &ConnectOptions,
+    ) -> Result<Self, ConnectError> {
+        match protocol {
+            Protocol::Ssl3 if options.source_address.is_none() && options.proxy.is_none() => {
+                let stream = BlazeStream::connect(addr).await.map_err(ssl3_error)?;
+                // The SSLv3 stream connects itself so the options are
+                // only set after its handshake
+                options.sockets.apply(stream.get_ref());
+                Ok(Self::Ssl3(stream))
+            }
+            Protocol::Ssl3 => {
+                let stream = options.connect(addr).await?;
+                Ok(Self::Ssl3(connect_ssl3_piped(stream).await?))
+            }
+            Protocol::Tls => {
+                let name = ServerName::try_from(host).map_err(|err| {
+                    ConnectError::Handshake(io::Error::new(io::ErrorKind::InvalidInput, err))
+                })?;
+                let stream = options.connect(addr).await?;
+                let stream = tls_connector()
+                    .connect(name, stream)
+                    .await
+                    .map_err(ConnectError::Handshake)?;
+                Ok(Self::Tls(stream))
+            }
+        }
+    }
+}
+
+/// Completes the SSLv3 handshake over the provided connection. The
+/// SSLv3 stream connects to a loopback listener whose accepted
+/// connection is piped to the provided connection, the pipe stops once
+/// either side is closed
+///
+/// `upstream` The connection to the server
+async fn connect_ssl3_piped(upstream: TcpStream) -> Result<BlazeStream, ConnectError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .map_err(ConnectError::Connect)?;
+    let local = listener.local_addr().map_err(ConnectError::Connect)?;
+    // The pipe is stopped if the handshake fails or is cancelled
+    let pipe = AbortOnDropHandle::new(tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        stream.set_nodelay(true)?;
+        let mut upstream = upstream;
+        tokio_io::copy_bidirectional(&mut stream, &mut upstream).await
+    }));
+    let stream = BlazeStream::connect(local).await.map_err(ssl3_error)?;
+    if let Err(err) = stream.get_ref().set_nodelay(true) {
+        warn!("Failed to set socket options: {err}");
+    }
+    pipe.detach();
+    Ok(stream)
+}
+
+/// Maps the provided SSLv3 stream error to a connect error
+///
+/// `err` The SSLv3 stream error
+fn ssl3_error(err: BlazeError) -> ConnectError {
+    match err {
+        BlazeError::IO(err) => ConnectError::Connect(err),
+        err => ConnectError::Handshake(io::Error::other(err)),
+    }
+}
+
+/// Returns the shared TLS connector which trusts the webpki root
+/// certificates creating it if it has not already been created
+fn tls_connector() -> &'static TlsConnector {
+    static CONNECTOR: OnceLock<TlsConnector> = OnceLock::new();
+    CONNECTOR.get_or_init(|| {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    })
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Ssl3(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Ssl3(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Ssl3(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Ssl3(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blaze_ssl_async::stream::BlazeListener;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Starts a SOCKS5 server without authentication on the provided
+    /// address which tunnels one connection to its requested IPv4 target
+    ///
+    /// `addr` The address to listen on
+    async fn start_proxy(addr: SocketAddr) {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [0x05, 1, 0x00]);
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request[..4], &[0x05, 0x01, 0x00, 0x01]);
+            let ip = Ipv4Addr::new(request[4], request[5], request[6], request[7]);
+            let port = u16::from_be_bytes([request[8], request[9]]);
+            let mut target = TcpStream::connect((ip, port)).await.unwrap();
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            let _ = tokio_io::copy_bidirectional(&mut stream, &mut target).await;
+        });
+    }
+
+    /// Tests that SSLv3 connections are made from the source address and
+    /// that the piped stream carries data both ways
+    #[tokio::test]
+    async fn test_ssl3_source_address() {
+        let listener = BlazeListener::bind((Ipv4Addr::LOCALHOST, 42320))
+            .await
+            .unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, addr) = listener.blocking_accept().await.unwrap();
+            let mut buffer = [0u8; 4];
+            stream.read_exact(&mut buffer).await.unwrap();
+            stream.write_all(&buffer).await.unwrap();
+            stream.flush().await.unwrap();
+            addr
+        });
+
+        let options = ConnectOptions {
+            source_address: Some("127.0.0.2:0".parse().unwrap()),
+            ..Default::default()
+        };
+        let addr = "127.0.0.1:42320".parse().unwrap();
+        let mut stream = UpstreamStream::connect("localhost", addr, Protocol::Ssl3, &options)
+            .await
+            .unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut buffer = [0u8; 4];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        let addr = server.await.unwrap();
+        assert_eq!(addr.ip(), Ipv4Addr::new(127, 0, 0, 2));
+    }
+
+    /// Tests that SSLv3 connections are tunneled through the proxy and
+    /// that the piped stream carries data both ways
+    #[tokio::test]
+    async fn test_ssl3_proxy() {
+        let listener = BlazeListener::bind((Ipv4Addr::LOCALHOST, 42321))
+            .await
+            .unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.blocking_accept().await.unwrap();
+            let mut buffer = [0u8; 4];
+            stream.read_exact(&mut buffer).await.unwrap();
+            stream.write_all(&buffer).await.unwrap();
+            stream.flush().await.unwrap();
+        });
+        let proxy = "127.0.0.1:42322".parse().unwrap();
+        start_proxy(proxy).await;
+
+        let options = ConnectOptions {
+            proxy: Some(ProxyConfig {
+                address: proxy,
+                username: None,
+                password: None,
+            }),
+            ..Default::default()
+        };
+        let addr = "127.0.0.1:42321".parse().unwrap();
+        let mut stream = UpstreamStream::connect("localhost", addr, Protocol::Ssl3, &options)
+            .await
+            .unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut buffer = [0u8; 4];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! Stub of the official servers for testing the MITM server end to end
+//! without network access. The stub redirector answers instance requests
+//! with the address of the stub main server which echoes each request
+//! back as its response.
+//!
+//! Both stubs use SSLv3 with the embedded certificate so the retriever
+//! must use the `ssl3` protocols and have `retriever.redirector` set to
+//! the stub redirector address
+
+use crate::{
+    components::{Components, Redirector},
+    mitm::read_packet,
+    models::{InstanceDetails, InstanceRequest},
+};
+use blaze_pk::packet::{Packet, PacketComponents, PacketType};
+use blaze_ssl_async::{BlazeListener, BlazeStream};
+use log::{debug, error};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{io::AsyncWriteExt, select};
+use tokio_util::sync::CancellationToken;
+
+/// The stub redirector and main server which run until the stub
+/// is dropped
+pub struct StubUpstream {
+    /// The address of the stub redirector
+    redirector: SocketAddr,
+    /// The address of the stub main server
+    server: SocketAddr,
+    /// The instance requests received by the stub redirector
+    requests: Arc<Mutex<Vec<InstanceRequest>>>,
+    /// Token cancelled to stop the stub servers
+    shutdown: CancellationToken,
+}
+
+impl StubUpstream {
+    /// Starts the stub redirector and main server on the provided
+    /// addresses. The redirector sends clients to the main server
+    ///
+    /// `redirector` The address for the stub redirector
+    /// `server`     The address for the stub main server
+    pub async fn start(redirector: SocketAddr, server: SocketAddr) -> io::Result<Self> {
+        let redirector_listener = BlazeListener::bind(redirector).await?;
+        let server_listener = BlazeListener::bind(server).await?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = CancellationToken::new();
+        let instance =
+            Arc::new(InstanceDetails::builder(server.ip().to_string(), server.port()).build());
+
+        tokio::spawn(accept(redirector_listener, shutdown.clone(), {
+            let requests = requests.clone();
+            move |stream| redirect(stream, instance.clone(), requests.clone())
+        }));
+        tokio::spawn(accept(server_listener, shutdown.clone(), echo));
+
+        Ok(Self {
+            redirector,
+            server,
+            requests,
+            shutdown,
+        })
+    }
+
+    /// Returns the address of the stub redirector
+    pub fn redirector_addr(&self) -> SocketAddr {
+        self.redirector
+    }
+
+    /// Returns the address of the stub main server
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Returns the instance requests received by the stub redirector
+    pub fn requests(&self) -> Vec<InstanceRequest> {
+        match self.requests.lock() {
+            Ok(value) => value.clone(),
+            Err(err) => err.into_inner().clone(),
+        }
+    }
+}
+
+impl Drop for StubUpstream {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+/// Accepts connections on the provided listener handling each of them
+/// with the provided handler until the token is cancelled
+///
+/// `listener` The listener to accept from
+/// `shutdown` Token cancelled to stop accepting
+/// `handler`  The handler for each connection
+async fn accept<F, Fut>(listener: BlazeListener, shutdown: CancellationToken, handler: F)
+where
+    F: Fn(BlazeStream) -> Fut,
+    Fut: std::future::Future<Output = io::Result<()>> + Send + 'static,
+{
+    loop {
+        let accept = select! {
+            _ = shutdown.cancelled() => break,
+            accept = listener.accept() => accept,
+        };
+        let accept = match accept {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Stub failed to accept connection: {err}");
+                break;
+            }
+        };
+        let (stream, addr) = match accept.finish_accept().await {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Stub handshake failed: {err}");
+                continue;
+            }
+        };
+        debug!("Stub accepted connection (Addr: {addr})");
+        let handle = handler(stream);
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            select! {
+                _ = shutdown.cancelled() => {}
+                result = handle => if let Err(err) = result {
+                    debug!("Stub connection closed: {err}");
+                },
+            }
+        });
+    }
+}
+
+/// Responds to instance requests with the stub main server, other
+/// requests are given empty responses
+///
+/// `stream`   The redirector connection
+/// `instance` The instance details of the stub main server
+/// `requests` The received instance requests
+async fn redirect(
+    mut stream: BlazeStream,
+    instance: Arc<InstanceDetails>,
+    requests: Arc<Mutex<Vec<InstanceRequest>>>,
+) -> io::Result<()> {
+    loop {
+        let packet = read_packet(&mut stream, usize::MAX).await?;
+        let component = Components::from_header(&packet.header);
+        let response = if component == Components::Redirector(Redirector::GetServerInstance) {
+            if let Ok(request) = packet.decode::<InstanceRequest>() {
+                match requests.lock() {
+                    Ok(mut value) => value.push(request),
+                    Err(err) => err.into_inner().push(request),
+                }
+            }
+            Packet::response(&packet, instance.as_ref().clone())
+        } else {
+            Packet::response_empty(&packet)
+        };
+        response.write_async(&mut stream).await?;
+        stream.flush().await?;
+    }
+}
+
+/// Echoes each request back as its response with the same contents,
+/// other packets are echoed back unchanged
+///
+/// `stream` The main server connection
+async fn echo(mut stream: BlazeStream) -> io::Result<()> {
+    loop {
+        let packet = read_packet(&mut stream, usize::MAX).await?;
+        let response = match packet.header.ty {
+            PacketType::Request => Packet {
+                header: packet.header.response(),
+                contents: packet.contents,
+            },
+            _ => packet,
+        };
+        response.write_async(&mut stream).await?;
+        stream.flush().await?;
+    }
+}
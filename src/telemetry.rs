@@ -0,0 +1,64 @@
+//! Module for observing the telemetry and user settings packets that
+//! clients send to the official server. Packets that decode are emitted
+//! on the events channel and all of them are forwarded unchanged
+
+use crate::{
+    components::{Components, Util},
+    events::{self, Event},
+    intercept::{Action, PacketInterceptor, SessionInfo},
+    mitm::Direction,
+    models::{ClientMetrics, TelemetryMessage, UserSettingsSave},
+};
+use async_trait::async_trait;
+use blaze_pk::packet::{Packet, PacketComponents, PacketType};
+
+/// Interceptor which decodes the telemetry and user settings requests
+/// sent by clients
+pub struct TelemetryObserver;
+
+impl TelemetryObserver {
+    /// Decodes the provided packet if it is a telemetry or user settings
+    /// message. None is returned for other packets and packets that fail
+    /// to decode
+    ///
+    /// `component` The component of the packet
+    /// `packet`    The packet to decode
+    fn decode(component: &Components, packet: &Packet) -> Option<TelemetryMessage> {
+        match component {
+            Components::Util(Util::UserSettingsSave) => packet
+                .decode::<UserSettingsSave>()
+                .ok()
+                .map(TelemetryMessage::UserSettingsSave),
+            Components::Util(Util::SetClientMetrics) => packet
+                .decode::<ClientMetrics>()
+                .ok()
+                .map(TelemetryMessage::ClientMetrics),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl PacketInterceptor for TelemetryObserver {
+    async fn on_packet(
+        &self,
+        session: &SessionInfo,
+        direction: Direction,
+        packet: &mut Packet,
+    ) -> Action {
+        if !matches!(direction, Direction::ClientToServer)
+            || packet.header.ty != PacketType::Request
+        {
+            return Action::Forward;
+        }
+        let component = Components::from_header(&packet.header);
+        if let Some(message) = Self::decode(&component, packet) {
+            events::emit(Event::Telemetry {
+                session: session.id,
+                client: session.addr,
+                message,
+            });
+        }
+        Action::Forward
+    }
+}
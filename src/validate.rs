@@ -0,0 +1,178 @@
+//! Module for validating that the proxied packets can be decoded. This
+//! is a diagnostic layer for finding packets that the decoders don't
+//! understand, packets that fail to decode are still forwarded
+
+use crate::{
+    auth::LoginObserver,
+    components::{Components, Redirector, UserSessions, Util},
+    config::DecodeConfig,
+    intercept::{Action, PacketInterceptor, SessionInfo},
+    metrics::METRICS,
+    mitm::Direction,
+    models::{
+        ClientMetrics, LoginResponse, QosLatencyReport, QosSettings, ServerList, UserSettingsSave,
+    },
+    replay::encode_hex,
+};
+use async_trait::async_trait;
+use blaze_pk::{
+    codec::Decodable,
+    error::DecodeResult,
+    packet::{Packet, PacketComponents, PacketType},
+    reader::TdfReader,
+    tag::Tag,
+};
+use log::warn;
+
+/// Interceptor which attempts to decode each packet and logs a warning
+/// for packets that fail to decode
+pub struct DecodeValidator {
+    /// Whether unsupported network address types are skipped
+    lenient: bool,
+}
+
+impl DecodeValidator {
+    /// Creates a validator decoding with the provided decode config
+    ///
+    /// `config` The decode configuration
+    pub fn new(config: &DecodeConfig) -> Self {
+        Self {
+            lenient: config.lenient_address(),
+        }
+    }
+
+    /// Attempts to decode the provided packet. Packets with a known
+    /// model are decoded using it, all other packets have each of
+    /// their tags read to check the structure
+    ///
+    /// `component` The component of the packet
+    /// `packet`    The packet to decode
+    fn validate(&self, component: &Components, packet: &Packet) -> DecodeResult<()> {
+        let contents = &packet.contents;
+        match (component, &packet.header.ty) {
+            (Components::Redirector(Redirector::GetServerInstance), PacketType::Response) => {
+                let mut reader = TdfReader::new(contents);
+                ServerList::decode_with(&mut reader, self.lenient).map(|_| ())
+            }
+            (component, PacketType::Response) if LoginObserver::is_login(component) => {
+                validate_model::<LoginResponse>(contents)
+            }
+            (Components::Util(Util::PreAuth), PacketType::Response) => {
+                QosSettings::find(contents).map(|_| ())
+            }
+            (Components::UserSessions(UserSessions::UpdateNetworkInfo), PacketType::Request) => {
+                QosLatencyReport::find(contents).map(|_| ())
+            }
+            (Components::Util(Util::UserSettingsSave), PacketType::Request) => {
+                validate_model::<UserSettingsSave>(contents)
+            }
+            (Components::Util(Util::SetClientMetrics), PacketType::Request) => {
+                validate_model::<ClientMetrics>(contents)
+            }
+            _ => validate_structure(contents),
+        }
+    }
+}
+
+#[async_trait]
+impl PacketInterceptor for DecodeValidator {
+    async fn on_packet(
+        &self,
+        _session: &SessionInfo,
+        direction: Direction,
+        packet: &mut Packet,
+    ) -> Action {
+        let component = Components::from_header(&packet.header);
+        if let Err(err) = self.validate(&component, packet) {
+            METRICS.decode_error(&packet.header);
+            warn!(
+                "Failed to decode packet (Component: {:?}, Direction: {:?}, Cause: {:?}, Contents: {})",
+                component,
+                direction,
+                err,
+                encode_hex(&packet.contents)
+            );
+        }
+        Action::Forward
+    }
+}
+
+/// Decodes the provided contents as the model type
+///
+/// `contents` The packet contents
+fn validate_model<T: Decodable>(contents: &[u8]) -> DecodeResult<()> {
+    let mut reader = TdfReader::new(contents);
+    T::decode(&mut reader).map(|_| ())
+}
+
+/// Reads each of the tags in the provided contents to check that
+/// they are structurally valid
+///
+/// `contents` The packet contents
+fn validate_structure(contents: &[u8]) -> DecodeResult<()> {
+    let mut reader = TdfReader::new(contents);
+    while reader.cursor < reader.buffer.len() {
+        let Tag(_, ty) = reader.read_tag()?;
+        reader.skip_type(&ty)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Authentication;
+    use blaze_pk::writer::TdfWriter;
+
+    /// PreAuth response contents containing the QoS settings
+    const PRE_AUTH: &[u8] = include_bytes!("../tests/synthetic/pre_auth.bin");
+    /// SilentLogin response contents containing the logged in session
+    const LOGIN_RESPONSE: &[u8] = include_bytes!("../tests/synthetic/login_response.bin");
+    /// UpdateNetworkInfo request contents containing the latency report
+    const UPDATE_NETWORK_INFO: &[u8] = include_bytes!("../tests/synthetic/update_network_info.bin");
+
+    /// Validates the provided contents as a packet of the component and
+    /// packet type
+    ///
+    /// `component` The component of the packet
+    /// `ty`        The type of the packet
+    /// `contents`  The packet contents
+    fn validate(component: Components, ty: PacketType, contents: &[u8]) -> DecodeResult<()> {
+        let request = Packet::request_raw(1, component, contents.to_vec());
+        let packet = match ty {
+            PacketType::Response => Packet::response_raw(&request, contents.to_vec()),
+            _ => request,
+        };
+        let component = Components::from_header(&packet.header);
+        DecodeValidator::new(&DecodeConfig::default()).validate(&component, &packet)
+    }
+
+    /// The proxied packets with a model are decoded by it
+    #[test]
+    fn test_validate_models() {
+        let login = Components::Authentication(Authentication::SilentLogin);
+        assert!(validate(login, PacketType::Response, LOGIN_RESPONSE).is_ok());
+        let pre_auth = Components::Util(Util::PreAuth);
+        assert!(validate(pre_auth, PacketType::Response, PRE_AUTH).is_ok());
+        let network = Components::UserSessions(UserSessions::UpdateNetworkInfo);
+        assert!(validate(network, PacketType::Request, UPDATE_NETWORK_INFO).is_ok());
+    }
+
+    /// Packets that are structurally valid but are missing the fields of
+    /// their model fail to validate
+    #[test]
+    fn test_validate_model_drift() {
+        let mut writer = TdfWriter::default();
+        writer.tag_str(b"ASRC", "303107");
+        writer.tag_u32(b"INST", 1);
+        assert!(validate_structure(&writer.buffer).is_ok());
+
+        let pre_auth = Components::Util(Util::PreAuth);
+        assert!(validate(pre_auth, PacketType::Response, &writer.buffer).is_err());
+        let settings = Components::Util(Util::UserSettingsSave);
+        assert!(validate(settings, PacketType::Request, &writer.buffer).is_err());
+        // Packets without a model only have their structure checked
+        let ping = Components::Util(Util::Ping);
+        assert!(validate(ping, PacketType::Request, &writer.buffer).is_ok());
+    }
+}
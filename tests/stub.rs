@@ -0,0 +1,121 @@
+//! End to end test of the redirector and MITM servers against the stub of
+//! the official servers. Requires the `testing` feature
+
+use blaze_pk::packet::{Packet, PacketComponents, PacketType};
+use blaze_ssl_async::BlazeStream;
+use pocket_relay_mitm::{
+    components::{Authentication, Components, Redirector},
+    config::{Config, RedirectTarget},
+    mitm::read_packet,
+    models::{InstanceDetails, InstanceNet, InstanceRequest},
+    stub::StubUpstream,
+    Server,
+};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{io::AsyncWriteExt, net::TcpStream, time};
+use tokio_util::sync::CancellationToken;
+
+/// The address of the stub redirector
+const STUB_REDIRECTOR: &str = "127.0.0.1:42310";
+/// The address of the stub main server
+const STUB_SERVER: &str = "127.0.0.1:42311";
+/// The address of the redirector under test
+const REDIRECTOR: &str = "127.0.0.1:42312";
+/// The address of the MITM server under test
+const MITM: &str = "127.0.0.1:42313";
+
+/// Connects to the redirector under test retrying while the server is
+/// still starting
+///
+/// `addr` The address of the redirector
+async fn connect_redirector(addr: SocketAddr) -> BlazeStream {
+    for _ in 0..50 {
+        if let Ok(stream) = BlazeStream::connect(addr).await {
+            return stream;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Redirector didn't start on {addr}");
+}
+
+/// Tests redirecting a client to the MITM server and a login request
+/// being proxied to the stub main server and back
+#[tokio::test]
+async fn test_redirect_and_login() {
+    let stub = StubUpstream::start(
+        STUB_REDIRECTOR.parse().unwrap(),
+        STUB_SERVER.parse().unwrap(),
+    )
+    .await
+    .unwrap();
+    let redirector: SocketAddr = REDIRECTOR.parse().unwrap();
+    let mitm: SocketAddr = MITM.parse().unwrap();
+
+    let mut config = Config::default();
+    config.retriever.redirector = Some(stub.redirector_addr());
+    let server = Server::builder()
+        .config(config)
+        .redirector_bind(vec![redirector])
+        .mitm_bind(mitm)
+        .redirect_target(RedirectTarget {
+            host: mitm.ip().to_string(),
+            port: mitm.port(),
+            secure: false,
+        })
+        .build();
+    let shutdown = CancellationToken::new();
+    let run = tokio::spawn(server.run(shutdown.clone()));
+
+    // The client is redirected to the MITM server
+    let mut stream = connect_redirector(redirector).await;
+    let request = Packet::request(
+        1,
+        Components::Redirector(Redirector::GetServerInstance),
+        InstanceRequest::default(),
+    );
+    request.write_async(&mut stream).await.unwrap();
+    stream.flush().await.unwrap();
+    let response = read_packet(&mut stream, usize::MAX).await.unwrap();
+    assert_eq!(response.header.ty, PacketType::Response);
+    let instance: InstanceDetails = response.decode().unwrap();
+    assert_eq!(
+        instance.net(),
+        &InstanceNet::from((mitm.ip().to_string(), mitm.port()))
+    );
+    assert!(!instance.secure);
+    // The retriever requested the main server from the stub redirector
+    assert!(!stub.requests().is_empty());
+
+    // The login request is proxied and echoed back by the stub
+    let mut stream = TcpStream::connect(mitm).await.unwrap();
+    let contents = vec![0x86, 0x49, 0x32, 0x00, 0x05];
+    let request = Packet::request_raw(
+        2,
+        Components::Authentication(Authentication::SilentLogin),
+        contents.clone(),
+    );
+    request.write_async(&mut stream).await.unwrap();
+    stream.flush().await.unwrap();
+    let response = time::timeout(
+        Duration::from_secs(10),
+        read_packet(&mut stream, usize::MAX),
+    )
+    .await
+    .expect("Login response timed out")
+    .unwrap();
+    assert_eq!(response.header.ty, PacketType::Response);
+    assert_eq!(response.header.id, 2);
+    assert_eq!(
+        Components::from_header(&response.header),
+        Components::Authentication(Authentication::SilentLogin)
+    );
+    assert_eq!(&response.contents[..], contents);
+
+    drop(stream);
+    shutdown.cancel();
+    time::timeout(Duration::from_secs(10), run)
+        .await
+        .expect("Server didn't shut down")
+        .unwrap()
+        .unwrap();
+}
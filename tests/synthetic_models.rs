@@ -0,0 +1,163 @@
+//! Decoding tests for the model types against the synthetic packet
+//! contents in the `synthetic` directory. These aren't captured from the
+//! official servers, they are assembled by hand following the field layout
+//! of the official redirector packets including the fields the decoders
+//! skip over
+
+use blaze_pk::{
+    codec::{Decodable, Encodable},
+    reader::TdfReader,
+};
+use pocket_relay_mitm::models::{
+    Environment, InstanceAddress, InstanceDetails, InstanceHost, InstanceNet, InstanceRequest,
+    Locale, NetAddress, NetworkAddressType, NetworkPair, ServerList,
+};
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+/// GetServerInstance request with the values sent by the Mass Effect 3 PC
+/// client
+const GET_SERVER_INSTANCE: &[u8] = include_bytes!("synthetic/get_server_instance.bin");
+/// GetServerInstance response with a server address carrying both the
+/// hostname and IP address of the instance
+const SERVER_INSTANCE: &[u8] = include_bytes!("synthetic/server_instance.bin");
+/// GetServerInstance response using the hostname address type
+const SERVER_INSTANCE_HOSTNAME: &[u8] = include_bytes!("synthetic/server_instance_hostname.bin");
+/// GetServerInstance response using the IP address type
+const SERVER_INSTANCE_IP: &[u8] = include_bytes!("synthetic/server_instance_ip.bin");
+/// GetServerInstance response using the pair address type with the
+/// external and internal address of the instance
+const SERVER_INSTANCE_PAIR: &[u8] = include_bytes!("synthetic/server_instance_pair.bin");
+/// GetServerInstance response with a LIST of instances using the server,
+/// IP and hostname address types
+const SERVER_LIST: &[u8] = include_bytes!("synthetic/server_list.bin");
+
+/// Decodes the provided contents as instance details
+///
+/// `contents` The packet contents
+fn decode_details(contents: &[u8]) -> InstanceDetails {
+    let mut reader = TdfReader::new(contents);
+    InstanceDetails::decode(&mut reader).expect("Failed to decode instance details")
+}
+
+/// Tests decoding the request values of the client
+#[test]
+fn test_get_server_instance() {
+    let mut reader = TdfReader::new(GET_SERVER_INSTANCE);
+    let request = InstanceRequest::decode(&mut reader).unwrap();
+    assert_eq!(request, InstanceRequest::default());
+    assert_eq!(request.client, "MassEffect3-pc");
+    assert_eq!(request.environment, Environment::Prod);
+    assert_eq!(request.locale, Locale::EN_NZ);
+    assert_eq!(request.first_party_id, None);
+    assert_eq!(
+        InstanceRequest::find_client(GET_SERVER_INSTANCE).unwrap(),
+        "MassEffect3-pc"
+    );
+}
+
+/// Tests that the hostname of a server address is used over its IP
+/// address and that the fields after the address are skipped
+#[test]
+fn test_server_instance() {
+    let details = decode_details(SERVER_INSTANCE);
+    assert!(matches!(details.address, InstanceAddress::Server(_)));
+    let net = details.net();
+    assert_eq!(
+        net.host,
+        InstanceHost::Host("383933-gosprapp396.ea.com".to_string())
+    );
+    assert_eq!(net.port, 42128);
+    assert!(details.secure);
+    assert!(!details.xdns);
+}
+
+/// Tests decoding the hostname address type
+#[test]
+fn test_server_instance_hostname() {
+    let details = decode_details(SERVER_INSTANCE_HOSTNAME);
+    assert_eq!(details.address.ty(), NetworkAddressType::HostnameAddress);
+    assert!(details.is_hostname());
+    assert_eq!(details.host_string(), "gosprapp396.ea.com");
+    assert_eq!(details.net().port, 42130);
+    assert!(!details.secure);
+    assert!(details.xdns);
+}
+
+/// Tests decoding the IP address type
+#[test]
+fn test_server_instance_ip() {
+    let details = decode_details(SERVER_INSTANCE_IP);
+    assert_eq!(details.address.ty(), NetworkAddressType::IpAddress);
+    assert_eq!(
+        details.net().host,
+        InstanceHost::Address(NetAddress(Ipv4Addr::new(159, 153, 64, 175)))
+    );
+    assert_eq!(details.net().port, 42128);
+    assert!(details.secure);
+}
+
+/// Tests that a response without a LIST decodes as a single instance
+#[test]
+fn test_server_list_single() {
+    let mut reader = TdfReader::new(SERVER_INSTANCE);
+    let list = ServerList::decode(&mut reader).unwrap();
+    assert_eq!(list.instances, vec![decode_details(SERVER_INSTANCE)]);
+}
+
+/// Tests decoding each instance of a LIST in order along with the values
+/// that differ between them
+#[test]
+fn test_server_list_multiple() {
+    let mut reader = TdfReader::new(SERVER_LIST);
+    let list = ServerList::decode(&mut reader).unwrap();
+    assert_eq!(reader.cursor, SERVER_LIST.len());
+    assert_eq!(list.instances.len(), 3);
+
+    let first = &list.instances[0];
+    assert!(matches!(first.address, InstanceAddress::Server(_)));
+    assert_eq!(
+        first.net().host,
+        InstanceHost::Host("383933-gosprapp396.ea.com".to_string())
+    );
+    assert_eq!(first.net().port, 42128);
+    assert!(first.secure);
+    assert!(!first.xdns);
+    assert_eq!(list.primary(), Some(first));
+
+    let second = &list.instances[1];
+    assert_eq!(second.address.ty(), NetworkAddressType::IpAddress);
+    assert_eq!(
+        second.net().host,
+        InstanceHost::Address(NetAddress(Ipv4Addr::new(159, 153, 64, 176)))
+    );
+    assert_eq!(second.net().port, 42128);
+    assert!(second.secure);
+
+    let third = &list.instances[2];
+    assert_eq!(third.address.ty(), NetworkAddressType::HostnameAddress);
+    assert_eq!(third.host_string(), "gosprapp397.ea.com");
+    assert_eq!(third.net().port, 42130);
+    assert!(!third.secure);
+    assert!(third.xdns);
+}
+
+/// Tests decoding the pair address type and that the encoded details
+/// decode back to the same value. The bytes aren't compared as the
+/// encoder writes ports with a padded VarInt
+#[test]
+fn test_server_instance_pair() {
+    let details = decode_details(SERVER_INSTANCE_PAIR);
+    let pair = NetworkPair {
+        internal: InstanceNet::from("192.168.1.20:3659".parse::<SocketAddrV4>().unwrap()),
+        external: InstanceNet::from("203.0.113.7:3659".parse::<SocketAddrV4>().unwrap()),
+    };
+    assert_eq!(details.address, InstanceAddress::Pair(pair.clone()));
+    // The external address is the one connected to
+    assert_eq!(details.net(), &pair.external);
+    assert!(!details.secure);
+
+    let encoded = details.encode_bytes();
+    // The union uses the pair address type
+    assert_eq!(encoded[4], NetworkAddressType::Pair.value());
+    assert_eq!(decode_details(&encoded), details);
+}